@@ -0,0 +1,82 @@
+//! Benchmarks the generation of the STARK tables from traces of increasing
+//! sizes, both concurrently and one table after the other.
+//!
+//! The size at which the concurrent generation starts beating the sequential
+//! one is the crossover point to set through the
+//! `EVM_PARALLEL_TABLES_MIN_CELLS` environment variable, see
+//! `evm_arithmetization::witness::traces::parallel_tables_min_cells`. The
+//! benchmarks of each size are named after the number of cells of their
+//! traces.
+//!
+//! The generation of the memory and Keccak tables, usually the largest ones, is
+//! also benchmarked on its own.
+//!
+//! Requires the `bench-internals` feature.
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use evm_arithmetization::all_stark::Table;
+use evm_arithmetization::witness::traces::dummy_traces;
+use evm_arithmetization::{AllStark, StarkConfig};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::util::timing::TimingTree;
+
+type F = GoldilocksField;
+
+const NUM_OPS: [usize; 6] = [1 << 4, 1 << 6, 1 << 8, 1 << 10, 1 << 12, 1 << 14];
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let all_stark = AllStark::<F, 2>::default();
+    let config = StarkConfig::standard_fast_config();
+
+    let mut group = c.benchmark_group("tables_generation");
+    group.sample_size(10);
+    for num_ops in NUM_OPS {
+        let num_cells = dummy_traces::<F>(num_ops).get_lengths().num_cells();
+
+        for (name, min_cells) in [("sequential", usize::MAX), ("parallel", 0)] {
+            group.bench_function(BenchmarkId::new(name, num_cells), |b| {
+                b.iter_batched(
+                    || dummy_traces::<F>(num_ops),
+                    |traces| {
+                        traces.into_tables_with_parallel_threshold(
+                            &all_stark,
+                            &config,
+                            &mut TimingTree::default(),
+                            min_cells,
+                        )
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("memory_and_keccak_tables_generation");
+    group.sample_size(10);
+    for num_ops in NUM_OPS {
+        group.bench_function(BenchmarkId::from_parameter(num_ops), |b| {
+            // Only the time spent on the memory and Keccak tables is measured.
+            b.iter_custom(|iters| {
+                (0..iters)
+                    .map(|_| {
+                        let (_, durations) = dummy_traces::<F>(num_ops)
+                            .into_tables_with_parallel_threshold(
+                                &all_stark,
+                                &config,
+                                &mut TimingTree::default(),
+                                usize::MAX,
+                            );
+                        durations[*Table::Memory] + durations[*Table::Keccak]
+                    })
+                    .sum::<Duration>()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
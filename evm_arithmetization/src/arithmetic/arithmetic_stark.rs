@@ -157,12 +157,11 @@ impl<F: RichField, const D: usize> ArithmeticStark<F, D> {
 
     pub(crate) fn generate_trace(&self, operations: Vec<Operation>) -> Vec<PolynomialValues<F>> {
         // The number of rows reserved is the smallest value that's
-        // guaranteed to avoid a reallocation: The only ops that use
-        // two rows are the modular operations and DIV, so the only
-        // way to reach capacity is when every op is modular or DIV
-        // (which is obviously unlikely in normal
-        // circumstances). (Also need at least RANGE_MAX rows to
-        // accommodate range checks.)
+        // guaranteed to avoid a reallocation: No op uses more than two
+        // rows (see `Operation::num_rows`), so the only way to reach
+        // capacity is when every op is a two-row op (which is obviously
+        // unlikely in normal circumstances). (Also need at least
+        // RANGE_MAX rows to accommodate range checks.)
         let max_rows = std::cmp::max(2 * operations.len(), RANGE_MAX);
         let mut trace_rows = Vec::with_capacity(max_rows);
 
@@ -341,6 +340,7 @@ mod tests {
 
     use super::ArithmeticStark;
     use crate::arithmetic::*;
+    use crate::witness::traces::Traces;
 
     #[test]
     fn degree() -> Result<()> {
@@ -368,6 +368,61 @@ mod tests {
         test_stark_circuit_constraints::<F, C, S, D>(stark)
     }
 
+    #[test]
+    fn trace_lengths_match_the_generated_rows() {
+        const D: usize = 2;
+        type C = PoseidonGoldilocksConfig;
+        type F = <C as GenericConfig<D>>::F;
+
+        let binary_operators = [
+            BinaryOperator::Add,
+            BinaryOperator::Mul,
+            BinaryOperator::Sub,
+            BinaryOperator::Div,
+            BinaryOperator::Mod,
+            BinaryOperator::Lt,
+            BinaryOperator::Gt,
+            BinaryOperator::AddFp254,
+            BinaryOperator::MulFp254,
+            BinaryOperator::SubFp254,
+            BinaryOperator::Byte,
+            BinaryOperator::Shl,
+            BinaryOperator::Shr,
+        ];
+        let ternary_operators = [
+            TernaryOperator::AddMod,
+            TernaryOperator::MulMod,
+            TernaryOperator::SubMod,
+        ];
+
+        let ops = binary_operators
+            .into_iter()
+            .map(|operator| Operation::binary(operator, U256::from(123), U256::from(8)))
+            .chain(ternary_operators.into_iter().map(|operator| {
+                Operation::ternary(operator, U256::from(123), U256::from(456), U256::from(1007))
+            }))
+            .chain(std::iter::once(Operation::range_check(
+                U256::from(123),
+                U256::zero(),
+                U256::zero(),
+                U256::from(0x1b),
+                U256::zero(),
+            )));
+
+        let mut traces = Traces::<F>::new();
+        let mut num_generated_rows = 0;
+        for op in ops {
+            let (_, maybe_row2) = op.to_rows::<F>();
+            let op_rows = 1 + usize::from(maybe_row2.is_some());
+            assert_eq!(op.num_rows(), op_rows, "{:?}", op);
+
+            num_generated_rows += op_rows;
+            traces.arithmetic_ops.push(op);
+        }
+
+        assert_eq!(traces.get_lengths().arithmetic_len, num_generated_rows);
+    }
+
     #[test]
     fn basic_trace() {
         const D: usize = 2;
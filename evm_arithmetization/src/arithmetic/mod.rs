@@ -1,5 +1,7 @@
 use ethereum_types::U256;
 use plonky2::field::types::PrimeField64;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use self::columns::{
     INPUT_REGISTER_0, INPUT_REGISTER_1, INPUT_REGISTER_2, OPCODE_COL, OUTPUT_REGISTER,
@@ -25,6 +27,7 @@ pub(crate) mod columns;
 /// `Shl` and `Shr` are handled differently, by leveraging `Mul` and `Div`
 /// respectively.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) enum BinaryOperator {
     Add,
     Mul,
@@ -115,6 +118,7 @@ impl BinaryOperator {
 /// An enum representing different ternary operations.
 #[allow(clippy::enum_variant_names)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) enum TernaryOperator {
     AddMod,
     MulMod,
@@ -147,6 +151,7 @@ impl TernaryOperator {
 /// ternary.
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) enum Operation {
     BinaryOperation {
         operator: BinaryOperator,
@@ -238,6 +243,24 @@ impl Operation {
         }
     }
 
+    /// Returns the number of rows of the `ArithmeticStark` trace this operation
+    /// gets converted into by [`Operation::to_rows`].
+    pub(crate) const fn num_rows(&self) -> usize {
+        match self {
+            Operation::BinaryOperation { operator, .. } => match operator {
+                BinaryOperator::Div
+                | BinaryOperator::Mod
+                | BinaryOperator::Shr
+                | BinaryOperator::AddFp254
+                | BinaryOperator::MulFp254
+                | BinaryOperator::SubFp254 => 2,
+                _ => 1,
+            },
+            Operation::TernaryOperation { .. } => 2,
+            Operation::RangeCheckOperation { .. } => 1,
+        }
+    }
+
     /// Convert operation into one or two rows of the trace.
     ///
     /// Morally these types should be [F; NUM_ARITH_COLUMNS], but we
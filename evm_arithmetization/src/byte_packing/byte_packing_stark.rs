@@ -39,6 +39,8 @@ use plonky2::iop::ext_target::ExtensionTarget;
 use plonky2::timed;
 use plonky2::util::timing::TimingTree;
 use plonky2::util::transpose;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use starky::constraint_consumer::{ConstraintConsumer, RecursiveConstraintConsumer};
 use starky::evaluation_frame::StarkEvaluationFrame;
 use starky::lookup::{Column, Filter, Lookup};
@@ -53,7 +55,7 @@ use crate::byte_packing::columns::{
 use crate::witness::memory::MemoryAddress;
 
 /// Strict upper bound for the individual bytes range-check.
-const BYTE_RANGE_MAX: usize = 1usize << 8;
+pub(crate) const BYTE_RANGE_MAX: usize = 1usize << 8;
 
 /// Creates the vector of `Columns` for `BytePackingStark` corresponding to the
 /// final packed limbs being read/written. `CpuStark` will look into these
@@ -129,6 +131,7 @@ pub(crate) fn ctl_looking_memory_filter<F: Field>(i: usize) -> Filter<F> {
 
 /// Information about a byte packing operation needed for witness generation.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) struct BytePackingOp {
     /// Whether this is a read (packing) or write (unpacking) operation.
     pub(crate) is_read: bool,
@@ -264,7 +267,8 @@ impl<F: RichField + Extendable<D>, const D: usize> BytePackingStark<F, D> {
 }
 
 impl<F: RichField + Extendable<D>, const D: usize> Stark<F, D> for BytePackingStark<F, D> {
-    type EvaluationFrame<FE, P, const D2: usize> = EvmStarkFrame<P, FE, NUM_COLUMNS>
+    type EvaluationFrame<FE, P, const D2: usize>
+        = EvmStarkFrame<P, FE, NUM_COLUMNS>
     where
         FE: FieldExtension<D2, BaseField = F>,
         P: PackedField<Scalar = FE>;
@@ -4,6 +4,10 @@ use core::mem::{size_of, transmute};
 use core::ops::{Index, IndexMut};
 
 use plonky2::field::types::Field;
+#[cfg(feature = "serde")]
+use serde::de::Error;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use zk_evm_proc_macro::Columns;
 
 use crate::cpu::columns::general::CpuGeneralColumnsView;
@@ -99,6 +103,28 @@ pub(crate) struct CpuColumnsView<T: Copy> {
 /// `u8` is guaranteed to have a `size_of` of 1.
 pub(crate) const NUM_CPU_COLUMNS: usize = size_of::<CpuColumnsView<u8>>();
 
+// `CpuColumnsView` contains a union, so it is (de)serialized as its flat array
+// of columns.
+#[cfg(feature = "serde")]
+impl<T: Copy + Serialize> Serialize for CpuColumnsView<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let cols: &[T; NUM_CPU_COLUMNS] = self.borrow();
+        serializer.collect_seq(cols)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Copy + Deserialize<'de>> Deserialize<'de> for CpuColumnsView<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let cols = Vec::<T>::deserialize(deserializer)?;
+        let cols: [T; NUM_CPU_COLUMNS] = cols.try_into().map_err(|cols: Vec<T>| {
+            D::Error::invalid_length(cols.len(), &"exactly NUM_CPU_COLUMNS columns")
+        })?;
+
+        Ok(cols.into())
+    }
+}
+
 const fn make_col_map() -> CpuColumnsView<usize> {
     let indices_arr = indices_arr::<NUM_CPU_COLUMNS>();
     unsafe { transmute::<[usize; NUM_CPU_COLUMNS], CpuColumnsView<usize>>(indices_arr) }
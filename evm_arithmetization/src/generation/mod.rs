@@ -32,6 +32,7 @@ pub mod mpt;
 pub(crate) mod prover_input;
 pub(crate) mod rlp;
 pub(crate) mod state;
+mod test_vector;
 mod trie_extractor;
 
 use self::state::State;
@@ -236,9 +237,12 @@ pub fn generate_traces<F: RichField + Extendable<D>, const D: usize>(
     }
 
     log::info!(
-        "Trace lengths (before padding): {:?}",
+        "Trace lengths (before padding): {}",
         state.traces.get_lengths()
     );
+    state
+        .traces
+        .log_largest_padded_table(config, log::Level::Info);
 
     let read_metadata = |field| state.memory.read_global_metadata(field);
     let trie_roots_before = TrieRoots {
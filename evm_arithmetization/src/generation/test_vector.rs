@@ -0,0 +1,162 @@
+//! Self-contained JSON test vectors of [`GenerationInputs`], which can be
+//! attached to a bug report to reproduce the proving of a single txn.
+//!
+//! A test vector is the regular JSON serialization of the inputs, except for
+//! the tries, which are encoded as their root hash along with the hex-encoded
+//! RLP of their nodes (see [`RlpEncodedTrie`]).
+
+use std::fs;
+use std::path::Path;
+
+use ethereum_types::H256;
+use mpt_trie::builder::RlpEncodedTrie;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{GenerationInputs, TrieInputs};
+
+const TRIES_KEY: &str = "tries";
+
+#[derive(Deserialize, Serialize)]
+struct RlpEncodedTrieInputs {
+    state_trie: RlpEncodedTrie,
+    transactions_trie: RlpEncodedTrie,
+    receipts_trie: RlpEncodedTrie,
+    storage_tries: Vec<(H256, RlpEncodedTrie)>,
+}
+
+impl From<&TrieInputs> for RlpEncodedTrieInputs {
+    fn from(tries: &TrieInputs) -> Self {
+        Self {
+            state_trie: RlpEncodedTrie::from_trie(&tries.state_trie),
+            transactions_trie: RlpEncodedTrie::from_trie(&tries.transactions_trie),
+            receipts_trie: RlpEncodedTrie::from_trie(&tries.receipts_trie),
+            storage_tries: tries
+                .storage_tries
+                .iter()
+                .map(|(h_addr, trie)| (*h_addr, RlpEncodedTrie::from_trie(trie)))
+                .collect(),
+        }
+    }
+}
+
+impl From<RlpEncodedTrieInputs> for TrieInputs {
+    fn from(tries: RlpEncodedTrieInputs) -> Self {
+        Self {
+            state_trie: tries.state_trie.into_trie(),
+            transactions_trie: tries.transactions_trie.into_trie(),
+            receipts_trie: tries.receipts_trie.into_trie(),
+            storage_tries: tries
+                .storage_tries
+                .into_iter()
+                .map(|(h_addr, trie)| (h_addr, trie.into_trie()))
+                .collect(),
+        }
+    }
+}
+
+impl GenerationInputs {
+    /// Serializes these inputs to a JSON test vector.
+    pub fn to_test_vector_json(&self) -> serde_json::Result<String> {
+        let inputs_without_tries = GenerationInputs {
+            tries: TrieInputs::default(),
+            ..self.clone()
+        };
+
+        let mut json = serde_json::to_value(inputs_without_tries)?;
+        json[TRIES_KEY] = serde_json::to_value(RlpEncodedTrieInputs::from(&self.tries))?;
+
+        serde_json::to_string_pretty(&json)
+    }
+
+    /// Deserializes inputs from a JSON test vector.
+    pub fn from_test_vector_json(json: &str) -> serde_json::Result<Self> {
+        let mut json: Value = serde_json::from_str(json)?;
+        let tries: RlpEncodedTrieInputs = serde_json::from_value(json[TRIES_KEY].take())?;
+        json[TRIES_KEY] = serde_json::to_value(TrieInputs::default())?;
+
+        let mut inputs: GenerationInputs = serde_json::from_value(json)?;
+        inputs.tries = tries.into();
+
+        Ok(inputs)
+    }
+
+    /// Writes these inputs as a JSON test vector to the file at `path`.
+    pub fn save_test_vector(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        fs::write(path, self.to_test_vector_json()?)?;
+        Ok(())
+    }
+
+    /// Reads inputs from the JSON test vector in the file at `path`.
+    pub fn load_test_vector(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self::from_test_vector_json(&fs::read_to_string(path)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_types::{Address, U256};
+    use keccak_hash::keccak;
+    use mpt_trie::nibbles::Nibbles;
+    use mpt_trie::partial_trie::{HashedPartialTrie, PartialTrie};
+
+    use super::*;
+    use crate::generation::mpt::AccountRlp;
+
+    #[test]
+    fn test_vector_round_trips() {
+        let mut storage_trie = HashedPartialTrie::default();
+        storage_trie
+            .insert(
+                Nibbles::from_h256_be(keccak([0u8; 32])),
+                rlp::encode(&U256::from(42)).to_vec(),
+            )
+            .unwrap();
+
+        let addr = Address::repeat_byte(1);
+        let account = AccountRlp {
+            nonce: 1.into(),
+            balance: 1_000_000.into(),
+            storage_root: storage_trie.hash(),
+            ..Default::default()
+        };
+        let mut state_trie = HashedPartialTrie::default();
+        state_trie
+            .insert(
+                Nibbles::from_h256_be(keccak(addr)),
+                rlp::encode(&account).to_vec(),
+            )
+            .unwrap();
+        state_trie
+            .insert(
+                Nibbles::from_h256_be(keccak(Address::repeat_byte(2))),
+                H256::repeat_byte(3),
+            )
+            .unwrap();
+
+        let inputs = GenerationInputs {
+            signed_txn: Some(vec![0xf8, 0x01]),
+            withdrawals: vec![(addr, 10.into())],
+            tries: TrieInputs {
+                state_trie,
+                storage_tries: vec![(keccak(addr), storage_trie)],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let json = inputs.to_test_vector_json().unwrap();
+        let decoded = GenerationInputs::from_test_vector_json(&json).unwrap();
+
+        assert_eq!(decoded.tries.state_trie, inputs.tries.state_trie);
+        assert_eq!(decoded.tries.storage_tries, inputs.tries.storage_tries);
+        assert_eq!(
+            decoded.tries.transactions_trie,
+            inputs.tries.transactions_trie
+        );
+        assert_eq!(decoded.tries.receipts_trie, inputs.tries.receipts_trie);
+        assert_eq!(decoded.signed_txn, inputs.signed_txn);
+        assert_eq!(decoded.withdrawals, inputs.withdrawals);
+        assert_eq!(decoded.block_hashes, inputs.block_hashes);
+    }
+}
@@ -76,23 +76,26 @@ impl<F: RichField + Extendable<D>, const D: usize> KeccakStark<F, D> {
             .max(min_rows)
             .next_power_of_two();
 
-        let mut rows = Vec::with_capacity(num_rows);
-        for input_and_timestamp in inputs_and_timestamps.iter() {
-            let rows_for_perm = self.generate_trace_rows_for_perm(*input_and_timestamp);
-            rows.extend(rows_for_perm);
+        // The padding rows are all zeros, so the permutation rows are written
+        // in place into the zeroed trace.
+        let mut rows = vec![[F::ZERO; NUM_COLUMNS]; num_rows];
+        for (input_and_timestamp, rows_for_perm) in inputs_and_timestamps
+            .iter()
+            .zip(rows.chunks_exact_mut(NUM_ROUNDS))
+        {
+            self.generate_trace_rows_for_perm(*input_and_timestamp, rows_for_perm);
         }
 
-        while rows.len() < num_rows {
-            rows.push([F::ZERO; NUM_COLUMNS]);
-        }
         rows
     }
 
+    /// Writes the rows of a permutation into `rows`, which must be zeroed and
+    /// hold exactly `NUM_ROUNDS` rows.
     fn generate_trace_rows_for_perm(
         &self,
         input_and_timestamp: ([u64; NUM_INPUTS], usize),
-    ) -> Vec<[F; NUM_COLUMNS]> {
-        let mut rows = vec![[F::ZERO; NUM_COLUMNS]; NUM_ROUNDS];
+        rows: &mut [[F; NUM_COLUMNS]],
+    ) {
         let input = input_and_timestamp.0;
         let timestamp = input_and_timestamp.1;
         // Set the timestamp of the current input.
@@ -119,8 +122,6 @@ impl<F: RichField + Extendable<D>, const D: usize> KeccakStark<F, D> {
             self.copy_output_to_input(rows[round - 1], &mut rows[round]);
             self.generate_trace_row_for_round(&mut rows[round], round);
         }
-
-        rows
     }
 
     fn copy_output_to_input(&self, prev_row: [F; NUM_COLUMNS], next_row: &mut [F; NUM_COLUMNS]) {
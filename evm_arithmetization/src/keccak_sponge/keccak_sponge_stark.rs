@@ -14,6 +14,8 @@ use plonky2::timed;
 use plonky2::util::timing::TimingTree;
 use plonky2::util::transpose;
 use plonky2_util::ceil_div_usize;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use starky::constraint_consumer::{ConstraintConsumer, RecursiveConstraintConsumer};
 use starky::evaluation_frame::StarkEvaluationFrame;
 use starky::lookup::{Column, Filter, Lookup};
@@ -231,6 +233,7 @@ pub(crate) fn ctl_looking_keccak_filter<F: Field>() -> Filter<F> {
 
 /// Information about a Keccak sponge operation needed for witness generation.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) struct KeccakSpongeOp {
     /// The base address at which inputs are read.
     pub(crate) base_address: MemoryAddress,
@@ -242,6 +245,17 @@ pub(crate) struct KeccakSpongeOp {
     pub(crate) input: Vec<u8>,
 }
 
+impl KeccakSpongeOp {
+    /// Returns the number of rows this operation takes in the `KeccakSponge`
+    /// STARK: one per full block of `KECCAK_RATE_BYTES` input bytes, plus the
+    /// final padded block. The padding always takes at least one byte, so an
+    /// input whose length is a multiple of the rate gets a final block made
+    /// only of padding.
+    pub(crate) fn num_rows(&self) -> usize {
+        self.input.len() / KECCAK_RATE_BYTES + 1
+    }
+}
+
 /// Structure representing the `KeccakSponge` STARK, which carries out the
 /// sponge permutation.
 #[derive(Copy, Clone, Default)]
@@ -279,10 +293,7 @@ impl<F: RichField + Extendable<D>, const D: usize> KeccakSpongeStark<F, D> {
         operations: Vec<KeccakSpongeOp>,
         min_rows: usize,
     ) -> Vec<[F; NUM_KECCAK_SPONGE_COLUMNS]> {
-        let base_len: usize = operations
-            .iter()
-            .map(|op| op.input.len() / KECCAK_RATE_BYTES + 1)
-            .sum();
+        let base_len: usize = operations.iter().map(KeccakSpongeOp::num_rows).sum();
         let mut rows = Vec::with_capacity(base_len.max(min_rows).next_power_of_two());
         // Generate active rows.
         for op in operations {
@@ -301,7 +312,7 @@ impl<F: RichField + Extendable<D>, const D: usize> KeccakSpongeStark<F, D> {
     /// accordingly. The number of rows is the number of input chunks of
     /// size `KECCAK_RATE_BYTES`.
     fn generate_rows_for_op(&self, op: KeccakSpongeOp) -> Vec<[F; NUM_KECCAK_SPONGE_COLUMNS]> {
-        let mut rows = Vec::with_capacity(op.input.len() / KECCAK_RATE_BYTES + 1);
+        let mut rows = Vec::with_capacity(op.num_rows());
 
         let mut sponge_state = [0u32; KECCAK_WIDTH_U32S];
 
@@ -533,7 +544,8 @@ impl<F: RichField + Extendable<D>, const D: usize> KeccakSpongeStark<F, D> {
 }
 
 impl<F: RichField + Extendable<D>, const D: usize> Stark<F, D> for KeccakSpongeStark<F, D> {
-    type EvaluationFrame<FE, P, const D2: usize> = EvmStarkFrame<P, FE, NUM_KECCAK_SPONGE_COLUMNS>
+    type EvaluationFrame<FE, P, const D2: usize>
+        = EvmStarkFrame<P, FE, NUM_KECCAK_SPONGE_COLUMNS>
     where
         FE: FieldExtension<D2, BaseField = F>,
         P: PackedField<Scalar = FE>;
@@ -1016,4 +1028,32 @@ mod tests {
         assert_eq!(output, expected_output.0);
         Ok(())
     }
+
+    #[test]
+    fn num_rows_matches_generated_rows() {
+        const D: usize = 2;
+        type F = GoldilocksField;
+        type S = KeccakSpongeStark<F, D>;
+
+        let stark = S::default();
+        for (input_len, expected_rows) in [
+            (0, 1),
+            (KECCAK_RATE_BYTES - 1, 1),
+            (KECCAK_RATE_BYTES, 2),
+            (KECCAK_RATE_BYTES + 1, 2),
+        ] {
+            let op = KeccakSpongeOp {
+                base_address: MemoryAddress::new(0, Segment::Code, 0),
+                timestamp: 0,
+                input: vec![0xab; input_len],
+            };
+
+            assert_eq!(op.num_rows(), expected_rows, "input length {input_len}");
+            assert_eq!(
+                stark.generate_rows_for_op(op).len(),
+                expected_rows,
+                "input length {input_len}"
+            );
+        }
+    }
 }
@@ -133,13 +133,84 @@ pub(crate) fn generate_first_change_flags_and_rc<F: RichField>(
     }
 }
 
+/// This memory STARK orders rows by `(context, segment, virt, timestamp)`.
+/// To enforce the ordering, it range checks the delta of the first
+/// field that changed.
+///
+/// This method adds some dummy operations to ensure that none of these
+/// range checks will be too large, i.e. that they will all be smaller
+/// than the number of rows, allowing them to be checked easily with a
+/// single lookup.
+///
+/// For example, say there are 32 memory operations, and a particular
+/// address is accessed at timestamps 20 and 100. 80 would fail the
+/// range check, so this method would add two dummy reads to the same
+/// address, say at timestamps 50 and 80.
+fn fill_gaps(memory_ops: &mut Vec<MemoryOp>) {
+    let max_rc = memory_ops.len().next_power_of_two() - 1;
+    for (mut curr, mut next) in memory_ops.clone().into_iter().tuple_windows() {
+        if curr.address.context != next.address.context
+            || curr.address.segment != next.address.segment
+        {
+            // We won't bother to check if there's a large context gap, because there can't
+            // be more than 500 contexts or so, as explained here:
+            // https://notes.ethereum.org/@vbuterin/proposals_to_adjust_memory_gas_costs
+            // Similarly, the number of possible segments is a small constant, so any gap
+            // must be small. max_rc will always be much larger, as just
+            // bootloading the kernel will trigger thousands of memory
+            // operations. However, we do check that the first address
+            // accessed is range-checkable. If not, we could start at a
+            // negative address and cheat.
+            while next.address.virt > max_rc {
+                let mut dummy_address = next.address;
+                dummy_address.virt -= max_rc;
+                let dummy_read = MemoryOp::new_dummy_read(dummy_address, 0, U256::zero());
+                memory_ops.push(dummy_read);
+                next = dummy_read;
+            }
+        } else if curr.address.virt != next.address.virt {
+            while next.address.virt - curr.address.virt - 1 > max_rc {
+                let mut dummy_address = curr.address;
+                dummy_address.virt += max_rc + 1;
+                let dummy_read = MemoryOp::new_dummy_read(dummy_address, 0, U256::zero());
+                memory_ops.push(dummy_read);
+                curr = dummy_read;
+            }
+        } else {
+            while next.timestamp - curr.timestamp > max_rc {
+                let dummy_read =
+                    MemoryOp::new_dummy_read(curr.address, curr.timestamp + max_rc, curr.value);
+                memory_ops.push(dummy_read);
+                curr = dummy_read;
+            }
+        }
+    }
+}
+
+/// Returns the exact height of the memory trace that would be generated from
+/// `memory_ops`, accounting for the dummy reads inserted by [`fill_gaps`],
+/// without generating the trace itself.
+///
+/// This still clones and sorts all memory operations.
+pub(crate) fn memory_trace_height(memory_ops: &[MemoryOp]) -> usize {
+    let mut memory_ops = memory_ops.to_vec();
+    memory_ops.sort_by_key(MemoryOp::sorting_key);
+    fill_gaps(&mut memory_ops);
+
+    memory_ops.len().next_power_of_two()
+}
+
 impl<F: RichField + Extendable<D>, const D: usize> MemoryStark<F, D> {
     /// Generate most of the trace rows. Excludes a few columns like `COUNTER`,
     /// which are generated later, after transposing to column-major form.
     fn generate_trace_row_major(&self, mut memory_ops: Vec<MemoryOp>) -> Vec<[F; NUM_COLUMNS]> {
+        // Reserve room for the padding upfront, which is enough for all the
+        // operations unless `fill_gaps` pushes their number past a power of two.
+        memory_ops.reserve(memory_ops.len().next_power_of_two() - memory_ops.len());
+
         // fill_gaps expects an ordered list of operations.
         memory_ops.sort_by_key(MemoryOp::sorting_key);
-        Self::fill_gaps(&mut memory_ops);
+        fill_gaps(&mut memory_ops);
 
         Self::pad_memory_ops(&mut memory_ops);
 
@@ -175,60 +246,6 @@ impl<F: RichField + Extendable<D>, const D: usize> MemoryStark<F, D> {
         }
     }
 
-    /// This memory STARK orders rows by `(context, segment, virt, timestamp)`.
-    /// To enforce the ordering, it range checks the delta of the first
-    /// field that changed.
-    ///
-    /// This method adds some dummy operations to ensure that none of these
-    /// range checks will be too large, i.e. that they will all be smaller
-    /// than the number of rows, allowing them to be checked easily with a
-    /// single lookup.
-    ///
-    /// For example, say there are 32 memory operations, and a particular
-    /// address is accessed at timestamps 20 and 100. 80 would fail the
-    /// range check, so this method would add two dummy reads to the same
-    /// address, say at timestamps 50 and 80.
-    fn fill_gaps(memory_ops: &mut Vec<MemoryOp>) {
-        let max_rc = memory_ops.len().next_power_of_two() - 1;
-        for (mut curr, mut next) in memory_ops.clone().into_iter().tuple_windows() {
-            if curr.address.context != next.address.context
-                || curr.address.segment != next.address.segment
-            {
-                // We won't bother to check if there's a large context gap, because there can't
-                // be more than 500 contexts or so, as explained here:
-                // https://notes.ethereum.org/@vbuterin/proposals_to_adjust_memory_gas_costs
-                // Similarly, the number of possible segments is a small constant, so any gap
-                // must be small. max_rc will always be much larger, as just
-                // bootloading the kernel will trigger thousands of memory
-                // operations. However, we do check that the first address
-                // accessed is range-checkable. If not, we could start at a
-                // negative address and cheat.
-                while next.address.virt > max_rc {
-                    let mut dummy_address = next.address;
-                    dummy_address.virt -= max_rc;
-                    let dummy_read = MemoryOp::new_dummy_read(dummy_address, 0, U256::zero());
-                    memory_ops.push(dummy_read);
-                    next = dummy_read;
-                }
-            } else if curr.address.virt != next.address.virt {
-                while next.address.virt - curr.address.virt - 1 > max_rc {
-                    let mut dummy_address = curr.address;
-                    dummy_address.virt += max_rc + 1;
-                    let dummy_read = MemoryOp::new_dummy_read(dummy_address, 0, U256::zero());
-                    memory_ops.push(dummy_read);
-                    curr = dummy_read;
-                }
-            } else {
-                while next.timestamp - curr.timestamp > max_rc {
-                    let dummy_read =
-                        MemoryOp::new_dummy_read(curr.address, curr.timestamp + max_rc, curr.value);
-                    memory_ops.push(dummy_read);
-                    curr = dummy_read;
-                }
-            }
-        }
-    }
-
     fn pad_memory_ops(memory_ops: &mut Vec<MemoryOp>) {
         let last_op = *memory_ops.last().expect("No memory ops?");
 
@@ -242,11 +259,8 @@ impl<F: RichField + Extendable<D>, const D: usize> MemoryStark<F, D> {
             ..last_op
         };
 
-        let num_ops = memory_ops.len();
-        let num_ops_padded = num_ops.next_power_of_two();
-        for _ in num_ops..num_ops_padded {
-            memory_ops.push(padding_op);
-        }
+        let num_ops_padded = memory_ops.len().next_power_of_two();
+        memory_ops.resize(num_ops_padded, padding_op);
     }
 
     pub(crate) fn generate_trace(
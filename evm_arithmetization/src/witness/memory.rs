@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 
 use ethereum_types::U256;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use crate::cpu::membus::{NUM_CHANNELS, NUM_GP_CHANNELS};
 
@@ -34,6 +36,7 @@ impl MemoryChannel {
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MemoryAddress {
     pub(crate) context: usize,
     pub(crate) segment: usize,
@@ -84,25 +87,49 @@ impl MemoryAddress {
         Self::new_u256s(context, segment, virt)
     }
 
+    /// The context of this address.
+    pub const fn context(&self) -> usize {
+        self.context
+    }
+
+    /// The unscaled segment of this address.
+    pub const fn segment(&self) -> usize {
+        self.segment
+    }
+
+    /// The virtual offset of this address within its segment.
+    pub const fn virt(&self) -> usize {
+        self.virt
+    }
+
     pub(crate) fn increment(&mut self) {
         self.virt = self.virt.saturating_add(1);
     }
 }
 
+/// Whether a [`MemoryOp`] reads from or writes to memory.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub(crate) enum MemoryOpKind {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MemoryOpKind {
     Read,
     Write,
 }
 
+/// A single memory operation, as recorded in the memory STARK trace.
 #[derive(Clone, Copy, Debug)]
-pub(crate) struct MemoryOp {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MemoryOp {
     /// true if this is an actual memory operation, or false if it's a padding
     /// row.
     pub filter: bool,
+    /// The CPU clock at which this operation happened, scaled by the number
+    /// of memory channels and offset by the index of the channel used.
     pub timestamp: usize,
+    /// The address being accessed.
     pub address: MemoryAddress,
+    /// Whether this operation is a read or a write.
     pub kind: MemoryOpKind,
+    /// The value read or written.
     pub value: U256,
 }
 
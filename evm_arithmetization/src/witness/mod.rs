@@ -1,8 +1,11 @@
 pub(crate) mod errors;
 pub(crate) mod gas;
+#[cfg(feature = "debug-internals")]
+pub mod memory;
+#[cfg(not(feature = "debug-internals"))]
 pub(crate) mod memory;
 pub(crate) mod operation;
 pub(crate) mod state;
-pub(crate) mod traces;
+pub mod traces;
 pub mod transition;
 pub(crate) mod util;
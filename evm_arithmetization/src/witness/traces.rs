@@ -1,32 +1,188 @@
+use std::fmt::{self, Display};
+use std::time::{Duration, Instant};
+use std::{env, mem};
+
+use anyhow::{bail, Result};
+use once_cell::sync::Lazy;
 use plonky2::field::extension::Extendable;
 use plonky2::field::polynomial::PolynomialValues;
 use plonky2::hash::hash_types::RichField;
 use plonky2::timed;
 use plonky2::util::timing::TimingTree;
+use plonky2_maybe_rayon::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use starky::config::StarkConfig;
-use starky::util::trace_rows_to_poly_values;
 
-use crate::all_stark::{AllStark, NUM_TABLES};
-use crate::arithmetic::{BinaryOperator, Operation};
-use crate::byte_packing::byte_packing_stark::BytePackingOp;
-use crate::cpu::columns::CpuColumnsView;
+use crate::all_stark::{AllStark, Table, NUM_TABLES};
+use crate::arithmetic::arithmetic_stark::RANGE_MAX;
+use crate::arithmetic::Operation;
+use crate::byte_packing::byte_packing_stark::{BytePackingOp, BYTE_RANGE_MAX};
+use crate::cpu::columns::{CpuColumnsView, NUM_CPU_COLUMNS};
+use crate::cpu::membus::NUM_CHANNELS;
+use crate::keccak_sponge::columns::NUM_KECCAK_SPONGE_COLUMNS;
 use crate::keccak_sponge::keccak_sponge_stark::KeccakSpongeOp;
+use crate::memory::memory_stark::memory_trace_height;
 use crate::witness::memory::MemoryOp;
-use crate::{arithmetic, keccak, keccak_sponge, logic};
-
-#[derive(Clone, Copy, Debug)]
-pub(crate) struct TraceCheckpoint {
-    pub(self) arithmetic_len: usize,
-    pub(self) byte_packing_len: usize,
-    pub(self) cpu_len: usize,
-    pub(self) keccak_len: usize,
-    pub(self) keccak_sponge_len: usize,
-    pub(self) logic_len: usize,
-    pub(self) memory_len: usize,
+use crate::{arithmetic, byte_packing, keccak, logic, memory};
+
+/// The default value of [`parallel_tables_min_cells`].
+///
+/// Spawning the generation of each table on the rayon pool only pays off once
+/// the tables are large enough for the work to outweigh the scheduling and
+/// cross-thread synchronization. The default of 2^16 cells only keeps the
+/// tiniest traces, such as the ones of dummy payloads, on the calling thread.
+/// The actual crossover point depends on the hardware, and can be found by
+/// running the `tables_generation` benchmark.
+pub const DEFAULT_PARALLEL_TABLES_MIN_CELLS: usize = 1 << 16;
+
+/// The environment variable overriding [`DEFAULT_PARALLEL_TABLES_MIN_CELLS`].
+pub const PARALLEL_TABLES_MIN_CELLS_ENV_VAR: &str = "EVM_PARALLEL_TABLES_MIN_CELLS";
+
+static PARALLEL_TABLES_MIN_CELLS: Lazy<usize> = Lazy::new(|| {
+    env::var(PARALLEL_TABLES_MIN_CELLS_ENV_VAR)
+        .ok()
+        .and_then(|val| match val.parse() {
+            Ok(min_cells) => Some(min_cells),
+            Err(_) => {
+                log::warn!(
+                    "Ignoring invalid {}: {:?}, using the default of {} cells",
+                    PARALLEL_TABLES_MIN_CELLS_ENV_VAR,
+                    val,
+                    DEFAULT_PARALLEL_TABLES_MIN_CELLS
+                );
+                None
+            }
+        })
+        .unwrap_or(DEFAULT_PARALLEL_TABLES_MIN_CELLS)
+});
+
+/// Returns the number of trace cells, as given by
+/// [`TraceCheckpoint::num_cells`], below which the tables are generated one
+/// after the other by [`Traces::into_tables_with_timings`] and
+/// [`Traces::into_tables_into`], even with the `parallel` feature enabled.
+///
+/// This is [`DEFAULT_PARALLEL_TABLES_MIN_CELLS`], unless overridden by the
+/// [`PARALLEL_TABLES_MIN_CELLS_ENV_VAR`] environment variable. The variable is
+/// only read once, the first time this is called.
+pub fn parallel_tables_min_cells() -> usize {
+    *PARALLEL_TABLES_MIN_CELLS
+}
+
+/// A length for each STARK module.
+///
+/// Depending on how it was obtained, these are either the number of operations
+/// recorded for each STARK module (see [`Traces::checkpoint`]), or their actual
+/// trace lengths (see [`Traces::get_lengths`]).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TraceCheckpoint {
+    /// Length for the arithmetic STARK.
+    pub arithmetic_len: usize,
+    /// Length for the byte packing STARK.
+    pub byte_packing_len: usize,
+    /// Length for the CPU STARK.
+    pub cpu_len: usize,
+    /// Length for the Keccak STARK.
+    pub keccak_len: usize,
+    /// Length for the Keccak sponge STARK.
+    pub keccak_sponge_len: usize,
+    /// Length for the logic STARK.
+    pub logic_len: usize,
+    /// Length for the memory STARK.
+    pub memory_len: usize,
+}
+
+impl Display for TraceCheckpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "arithmetic: {}, byte packing: {}, cpu: {}, keccak: {}, keccak sponge: {}, logic: {}, memory: {}",
+            self.arithmetic_len,
+            self.byte_packing_len,
+            self.cpu_len,
+            self.keccak_len,
+            self.keccak_sponge_len,
+            self.logic_len,
+            self.memory_len
+        )
+    }
+}
+
+impl TraceCheckpoint {
+    /// Estimates the height of each STARK trace once padded, in the order of
+    /// [`Table`](crate::all_stark::Table), by applying the same padding as
+    /// each STARK's `generate_trace`. The lengths must be actual trace lengths,
+    /// as returned by [`Traces::get_lengths`].
+    ///
+    /// The memory estimate is a lower bound: `memory_len` only accounts for the
+    /// operations performed so far, while the memory STARK also inserts dummy
+    /// reads to fill large address or timestamp gaps. These may push the number
+    /// of memory operations past a power of two, in which case the final
+    /// memory table is twice as high as estimated here. Use
+    /// [`Traces::memory_trace_height`] for the exact memory table height.
+    pub fn padded_heights(&self, config: &StarkConfig) -> [usize; NUM_TABLES] {
+        let min_rows = config.fri_config.num_cap_elements();
+
+        [
+            self.arithmetic_len.next_power_of_two().max(RANGE_MAX),
+            self.byte_packing_len
+                .max(BYTE_RANGE_MAX)
+                .max(min_rows)
+                .next_power_of_two(),
+            self.cpu_len.next_power_of_two(),
+            self.keccak_len.max(min_rows).next_power_of_two(),
+            self.keccak_sponge_len.max(min_rows).next_power_of_two(),
+            self.logic_len.max(min_rows).next_power_of_two(),
+            self.memory_len.next_power_of_two(),
+        ]
+    }
+
+    /// Returns, in the order of [`Table`](crate::all_stark::Table), whether
+    /// each STARK module has an empty trace, i.e. a length of zero.
+    ///
+    /// The CPU and memory tables are never empty, as every execution runs the
+    /// kernel, which reads its code from memory. The other tables are only
+    /// filled by the operations that need them, and can hence legitimately be
+    /// empty, e.g. the Keccak and Keccak sponge tables when nothing gets
+    /// hashed, or the byte packing table when no value gets (un)packed.
+    ///
+    /// Note that an empty table still gets generated as a padded table and
+    /// proven, as the cross-table lookups expect a proof for every table.
+    pub fn empty_tables(&self) -> [bool; NUM_TABLES] {
+        [
+            self.arithmetic_len,
+            self.byte_packing_len,
+            self.cpu_len,
+            self.keccak_len,
+            self.keccak_sponge_len,
+            self.logic_len,
+            self.memory_len,
+        ]
+        .map(|len| len == 0)
+    }
+
+    /// Returns the total number of cells of the STARK traces, i.e. the sum of
+    /// the lengths weighted by the number of columns of each table, before any
+    /// padding. The lengths must be actual trace lengths, as returned by
+    /// [`Traces::get_lengths`].
+    pub fn num_cells(&self) -> usize {
+        self.arithmetic_len * arithmetic::columns::NUM_ARITH_COLUMNS
+            + self.byte_packing_len * byte_packing::columns::NUM_COLUMNS
+            + self.cpu_len * NUM_CPU_COLUMNS
+            + self.keccak_len * keccak::columns::NUM_COLUMNS
+            + self.keccak_sponge_len * NUM_KECCAK_SPONGE_COLUMNS
+            + self.logic_len * logic::columns::NUM_COLUMNS
+            + self.memory_len * memory::columns::NUM_COLUMNS
+    }
 }
 
+/// The operations recorded for each STARK module during witness generation.
+///
+/// With the `serde` feature enabled, these can be serialized, e.g. to save them
+/// to disk once generated and resume proving later on through `into_tables`.
 #[derive(Debug)]
-pub(crate) struct Traces<T: Copy> {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Traces<T: Copy> {
     pub(crate) arithmetic_ops: Vec<arithmetic::Operation>,
     pub(crate) byte_packing_ops: Vec<BytePackingOp>,
     pub(crate) cpu: Vec<CpuColumnsView<T>>,
@@ -49,22 +205,29 @@ impl<T: Copy> Traces<T> {
         }
     }
 
+    /// Creates empty traces, with enough capacity for each STARK module to hold
+    /// the number of operations of the given checkpoint without reallocating.
+    ///
+    /// The checkpoint is expected to come from [`Traces::checkpoint`], e.g.
+    /// from a previous run on a similar block, rather than from
+    /// [`Traces::get_lengths`].
+    pub fn with_capacity(checkpoint: TraceCheckpoint) -> Self {
+        Traces {
+            arithmetic_ops: Vec::with_capacity(checkpoint.arithmetic_len),
+            byte_packing_ops: Vec::with_capacity(checkpoint.byte_packing_len),
+            cpu: Vec::with_capacity(checkpoint.cpu_len),
+            logic_ops: Vec::with_capacity(checkpoint.logic_len),
+            memory_ops: Vec::with_capacity(checkpoint.memory_len),
+            keccak_inputs: Vec::with_capacity(checkpoint.keccak_len),
+            keccak_sponge_ops: Vec::with_capacity(checkpoint.keccak_sponge_len),
+        }
+    }
+
     /// Returns the actual trace lengths for each STARK module.
     //  Uses a `TraceCheckPoint` as return object for convenience.
-    pub(crate) fn get_lengths(&self) -> TraceCheckpoint {
+    pub fn get_lengths(&self) -> TraceCheckpoint {
         TraceCheckpoint {
-            arithmetic_len: self
-                .arithmetic_ops
-                .iter()
-                .map(|op| match op {
-                    Operation::TernaryOperation { .. } => 2,
-                    Operation::BinaryOperation { operator, .. } => match operator {
-                        BinaryOperator::Div | BinaryOperator::Mod => 2,
-                        _ => 1,
-                    },
-                    Operation::RangeCheckOperation { .. } => 1,
-                })
-                .sum(),
+            arithmetic_len: self.arithmetic_ops.iter().map(Operation::num_rows).sum(),
             byte_packing_len: self
                 .byte_packing_ops
                 .iter()
@@ -75,7 +238,7 @@ impl<T: Copy> Traces<T> {
             keccak_sponge_len: self
                 .keccak_sponge_ops
                 .iter()
-                .map(|op| op.input.len() / keccak_sponge::columns::KECCAK_RATE_BYTES + 1)
+                .map(KeccakSpongeOp::num_rows)
                 .sum(),
             logic_len: self.logic_ops.len(),
             // This is technically a lower-bound, as we may fill gaps,
@@ -84,8 +247,106 @@ impl<T: Copy> Traces<T> {
         }
     }
 
+    /// Returns the exact height of the memory trace, including the dummy reads
+    /// inserted to fill large address or timestamp gaps, and the padding to a
+    /// power of two.
+    ///
+    /// Unlike `memory_len` in [`Traces::get_lengths`], this is not a lower
+    /// bound, but it requires cloning and sorting all memory operations.
+    pub fn memory_trace_height(&self) -> usize {
+        memory_trace_height(&self.memory_ops)
+    }
+
+    /// Returns `true` if no operation has been recorded for any STARK module.
+    pub fn is_empty(&self) -> bool {
+        self.arithmetic_ops.is_empty()
+            && self.byte_packing_ops.is_empty()
+            && self.cpu.is_empty()
+            && self.logic_ops.is_empty()
+            && self.memory_ops.is_empty()
+            && self.keccak_inputs.is_empty()
+            && self.keccak_sponge_ops.is_empty()
+    }
+
+    /// Returns a one-line summary of the trace lengths given by
+    /// [`Traces::get_lengths`], meant for logging.
+    pub fn summary(&self) -> String {
+        let lengths = self.get_lengths();
+        format!(
+            "arith={} bp={} cpu={} keccak={} sponge={} logic={} mem={}",
+            lengths.arithmetic_len,
+            lengths.byte_packing_len,
+            lengths.cpu_len,
+            lengths.keccak_len,
+            lengths.keccak_sponge_len,
+            lengths.logic_len,
+            lengths.memory_len
+        )
+    }
+
+    /// Checks that the padded height of each STARK trace, in the order of
+    /// [`Table`], does not exceed the corresponding maximum height. This is
+    /// meant to be called before generating the tables, to bail out early on
+    /// traces that won't fit the proving parameters.
+    ///
+    /// The memory table height is computed exactly, see
+    /// [`Traces::memory_trace_height`].
+    pub fn check_max_heights(
+        &self,
+        config: &StarkConfig,
+        max_heights: &[usize; NUM_TABLES],
+    ) -> Result<()> {
+        let mut heights = self.get_lengths().padded_heights(config);
+        heights[*Table::Memory] = self.memory_trace_height();
+
+        for ((table, height), &max_height) in Table::all().into_iter().zip(heights).zip(max_heights)
+        {
+            if height > max_height {
+                bail!(
+                    "The {:?} table has a padded height of {} rows, exceeding the maximum of {} by {} rows",
+                    table,
+                    height,
+                    max_height,
+                    height - max_height
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the STARK table with the largest padded height, along with that
+    /// height, as estimated by [`TraceCheckpoint::padded_heights`]. This is the
+    /// table which bounds how much can be proven at once.
+    pub fn largest_padded_table(&self, config: &StarkConfig) -> (Table, usize) {
+        Table::all()
+            .into_iter()
+            .zip(self.get_lengths().padded_heights(config))
+            .max_by_key(|&(_, height)| height)
+            .expect("There is at least one STARK table")
+    }
+
+    /// Logs the table returned by [`Traces::largest_padded_table`] at the given
+    /// `level`. Nothing gets computed if `level` is disabled.
+    ///
+    /// The message is logged under the target of this module, so that it can
+    /// be filtered independently of the other proving logs.
+    pub fn log_largest_padded_table(&self, config: &StarkConfig, level: log::Level) {
+        if !log::log_enabled!(level) {
+            return;
+        }
+
+        let (table, height) = self.largest_padded_table(config);
+        log::log!(
+            level,
+            "Largest padded table: {:?} with {} rows",
+            table,
+            height
+        );
+    }
+
     /// Returns the number of operations for each STARK module.
-    pub(crate) fn checkpoint(&self) -> TraceCheckpoint {
+    pub fn checkpoint(&self) -> TraceCheckpoint {
         TraceCheckpoint {
             arithmetic_len: self.arithmetic_ops.len(),
             byte_packing_len: self.byte_packing_ops.len(),
@@ -97,6 +358,12 @@ impl<T: Copy> Traces<T> {
         }
     }
 
+    /// Drops the operations recorded since `checkpoint` was taken.
+    ///
+    /// `checkpoint` must come from [`Traces::checkpoint`], as it is expressed
+    /// in numbers of operations rather than in trace lengths. In particular,
+    /// each Keccak input accounts for a single operation but for
+    /// `NUM_ROUNDS` rows in [`Traces::get_lengths`].
     pub(crate) fn rollback(&mut self, checkpoint: TraceCheckpoint) {
         self.arithmetic_ops.truncate(checkpoint.arithmetic_len);
         self.byte_packing_ops.truncate(checkpoint.byte_packing_len);
@@ -108,10 +375,100 @@ impl<T: Copy> Traces<T> {
         self.memory_ops.truncate(checkpoint.memory_len);
     }
 
+    /// Appends the operations of `other` to the ones of `self`, for each STARK
+    /// module.
+    pub fn merge(mut self, other: Traces<T>) -> Traces<T> {
+        let Traces {
+            arithmetic_ops,
+            byte_packing_ops,
+            cpu,
+            logic_ops,
+            memory_ops,
+            keccak_inputs,
+            keccak_sponge_ops,
+        } = other;
+
+        self.arithmetic_ops.extend(arithmetic_ops);
+        self.byte_packing_ops.extend(byte_packing_ops);
+        self.cpu.extend(cpu);
+        self.logic_ops.extend(logic_ops);
+        self.memory_ops.extend(memory_ops);
+        self.keccak_inputs.extend(keccak_inputs);
+        self.keccak_sponge_ops.extend(keccak_sponge_ops);
+
+        self
+    }
+
+    /// Splits off the operations performed at or after the CPU cycle `clock`,
+    /// returning them as new traces and leaving the ones performed before in
+    /// `self`. This is the inverse of [`Traces::merge`] for the CPU rows.
+    ///
+    /// The memory, byte packing, Keccak and Keccak sponge operations are split
+    /// by the timestamp they were recorded at, i.e. `clock * NUM_CHANNELS`
+    /// plus their channel. The arithmetic and logic operations do not record
+    /// the cycle they were performed at, so they are all kept in `self`:
+    /// attributing them would require replaying the CPU rows looking them up,
+    /// in order.
+    ///
+    /// Note that neither part can be proven on its own as is: the memory state
+    /// at the split has to be carried to the second part, and the cross-table
+    /// lookups of the arithmetic and logic operations only balance out once
+    /// these are attributed.
+    pub fn split_at_cpu(&mut self, clock: usize) -> Self {
+        let split_timestamp = clock * NUM_CHANNELS;
+
+        Traces {
+            arithmetic_ops: vec![],
+            byte_packing_ops: split_off_after(&mut self.byte_packing_ops, |op| {
+                op.timestamp >= split_timestamp
+            }),
+            cpu: self.cpu.split_off(clock.min(self.cpu.len())),
+            logic_ops: vec![],
+            memory_ops: split_off_after(&mut self.memory_ops, |op| op.timestamp >= split_timestamp),
+            keccak_inputs: split_off_after(&mut self.keccak_inputs, |(_, timestamp)| {
+                *timestamp >= split_timestamp
+            }),
+            keccak_sponge_ops: split_off_after(&mut self.keccak_sponge_ops, |op| {
+                op.timestamp >= split_timestamp
+            }),
+        }
+    }
+
+    /// Returns the memory operations recorded since `checkpoint`, in the order
+    /// in which they were performed.
+    #[cfg(feature = "debug-internals")]
+    pub fn mem_ops_since(&self, checkpoint: TraceCheckpoint) -> &[MemoryOp] {
+        &self.memory_ops[checkpoint.memory_len..]
+    }
+
+    #[cfg(not(feature = "debug-internals"))]
     pub(crate) fn mem_ops_since(&self, checkpoint: TraceCheckpoint) -> &[MemoryOp] {
         &self.memory_ops[checkpoint.memory_len..]
     }
 
+    /// Returns the number of memory operations recorded so far.
+    pub fn memory_ops_count(&self) -> usize {
+        self.memory_ops.len()
+    }
+
+    /// Returns the number of memory operations recorded since
+    /// [`Traces::memory_ops_count`] returned `count`, without requiring a
+    /// full [`TraceCheckpoint`].
+    ///
+    /// Operations recorded after `count` was taken but dropped since, e.g.
+    /// when rolling back, are not counted.
+    pub fn memory_ops_count_since(&self, count: usize) -> usize {
+        self.memory_ops.len().saturating_sub(count)
+    }
+
+    /// Returns the current CPU clock, i.e. the number of CPU rows recorded so
+    /// far.
+    #[cfg(feature = "debug-internals")]
+    pub fn clock(&self) -> usize {
+        self.cpu.len()
+    }
+
+    #[cfg(not(feature = "debug-internals"))]
     pub(crate) fn clock(&self) -> usize {
         self.cpu.len()
     }
@@ -122,10 +479,109 @@ impl<T: Copy> Traces<T> {
         config: &StarkConfig,
         timing: &mut TimingTree,
     ) -> [Vec<PolynomialValues<T>>; NUM_TABLES]
+    where
+        T: RichField + Extendable<D>,
+    {
+        self.into_tables_with_timings(all_stark, config, timing).0
+    }
+
+    /// Generates the trace of each STARK module, along with the time it took
+    /// to generate each of them, in the order of
+    /// [`Table`](crate::all_stark::Table).
+    ///
+    /// When the traces are generated one after the other, each of them is
+    /// timed in its own scope of `timing`, along with the scopes its STARK
+    /// opens. When they are generated concurrently, `timing` only gets the
+    /// scope enclosing all of them: the per-table scopes are lost, and the
+    /// durations returned here are the only timing of each table. They may
+    /// then add up to more than the total time spent in this method.
+    pub fn into_tables_with_timings<const D: usize>(
+        self,
+        all_stark: &AllStark<T, D>,
+        config: &StarkConfig,
+        timing: &mut TimingTree,
+    ) -> (
+        [Vec<PolynomialValues<T>>; NUM_TABLES],
+        [Duration; NUM_TABLES],
+    )
+    where
+        T: RichField + Extendable<D>,
+    {
+        self.into_tables_with_parallel_threshold(
+            all_stark,
+            config,
+            timing,
+            parallel_tables_min_cells(),
+        )
+    }
+
+    /// Same as [`Traces::into_tables_with_timings`], but generates the tables
+    /// one after the other if the traces have fewer than `min_cells` cells (see
+    /// [`TraceCheckpoint::num_cells`]), instead of using the threshold given by
+    /// [`parallel_tables_min_cells`].
+    pub fn into_tables_with_parallel_threshold<const D: usize>(
+        self,
+        all_stark: &AllStark<T, D>,
+        config: &StarkConfig,
+        timing: &mut TimingTree,
+        min_cells: usize,
+    ) -> (
+        [Vec<PolynomialValues<T>>; NUM_TABLES],
+        [Duration; NUM_TABLES],
+    )
+    where
+        T: RichField + Extendable<D>,
+    {
+        self.generate_tables(all_stark, config, timing, Vec::new(), min_cells)
+    }
+
+    /// Same as [`Traces::into_tables`], but writes the traces to `out` instead
+    /// of returning a new array, so that a prover looping over many segments
+    /// can hand back the tables of the previous one.
+    ///
+    /// The buffers of the CPU table, by far the widest one, are cleared and
+    /// reused where their capacity allows. The other tables are generated by
+    /// their STARKs into new vectors, which replace the ones in `out`.
+    pub fn into_tables_into<const D: usize>(
+        self,
+        all_stark: &AllStark<T, D>,
+        config: &StarkConfig,
+        timing: &mut TimingTree,
+        out: &mut [Vec<PolynomialValues<T>>; NUM_TABLES],
+    ) where
+        T: RichField + Extendable<D>,
+    {
+        let cpu_polys = mem::take(&mut out[*Table::Cpu]);
+        *out = self
+            .generate_tables(
+                all_stark,
+                config,
+                timing,
+                cpu_polys,
+                parallel_tables_min_cells(),
+            )
+            .0;
+    }
+
+    /// Generates the trace of each STARK module, writing the CPU one to
+    /// `cpu_polys`. The tables are only generated concurrently if the traces
+    /// have at least `min_cells` cells.
+    fn generate_tables<const D: usize>(
+        self,
+        all_stark: &AllStark<T, D>,
+        config: &StarkConfig,
+        timing: &mut TimingTree,
+        cpu_polys: Vec<PolynomialValues<T>>,
+        min_cells: usize,
+    ) -> (
+        [Vec<PolynomialValues<T>>; NUM_TABLES],
+        [Duration; NUM_TABLES],
+    )
     where
         T: RichField + Extendable<D>,
     {
         let cap_elements = config.fri_config.num_cap_elements();
+        let parallel = self.get_lengths().num_cells() >= min_cells;
         let Traces {
             arithmetic_ops,
             byte_packing_ops,
@@ -136,61 +592,448 @@ impl<T: Copy> Traces<T> {
             keccak_sponge_ops,
         } = self;
 
-        let arithmetic_trace = timed!(
-            timing,
-            "generate arithmetic trace",
-            all_stark.arithmetic_stark.generate_trace(arithmetic_ops)
-        );
-        let byte_packing_trace = timed!(
-            timing,
-            "generate byte packing trace",
+        // The tables are generated independently of each other, so they can be
+        // generated concurrently. `TimingTree` can't be shared across threads, nor
+        // take a scope measured elsewhere, so in that case the tables only get
+        // timed on their own, without any scope on `timing`.
+        let gen_arithmetic =
+            |_: &mut TimingTree| all_stark.arithmetic_stark.generate_trace(arithmetic_ops);
+        let gen_byte_packing = |timing: &mut TimingTree| {
             all_stark
                 .byte_packing_stark
                 .generate_trace(byte_packing_ops, cap_elements, timing)
-        );
-        let cpu_rows = cpu.into_iter().map(|x| x.into()).collect();
-        let cpu_trace = trace_rows_to_poly_values(cpu_rows);
-        let keccak_trace = timed!(
-            timing,
-            "generate Keccak trace",
+        };
+        let gen_cpu = |_: &mut TimingTree| cpu_trace_rows_to_poly_values(cpu, cpu_polys);
+        let gen_keccak = |timing: &mut TimingTree| {
             all_stark
                 .keccak_stark
                 .generate_trace(keccak_inputs, cap_elements, timing)
-        );
-        let keccak_sponge_trace = timed!(
-            timing,
-            "generate Keccak sponge trace",
+        };
+        let gen_keccak_sponge = |timing: &mut TimingTree| {
             all_stark
                 .keccak_sponge_stark
                 .generate_trace(keccak_sponge_ops, cap_elements, timing)
-        );
-        let logic_trace = timed!(
-            timing,
-            "generate logic trace",
+        };
+        let gen_logic = |timing: &mut TimingTree| {
             all_stark
                 .logic_stark
                 .generate_trace(logic_ops, cap_elements, timing)
-        );
-        let memory_trace = timed!(
-            timing,
-            "generate memory trace",
-            all_stark.memory_stark.generate_trace(memory_ops, timing)
-        );
+        };
+        let gen_memory =
+            |timing: &mut TimingTree| all_stark.memory_stark.generate_trace(memory_ops, timing);
 
-        [
-            arithmetic_trace,
-            byte_packing_trace,
-            cpu_trace,
-            keccak_trace,
-            keccak_sponge_trace,
-            logic_trace,
-            memory_trace,
-        ]
+        timed!(timing, "generate all traces", {
+            let (
+                ((arithmetic_trace, arithmetic_time), (byte_packing_trace, byte_packing_time)),
+                (
+                    ((cpu_trace, cpu_time), (keccak_trace, keccak_time)),
+                    (
+                        (keccak_sponge_trace, keccak_sponge_time),
+                        ((logic_trace, logic_time), (memory_trace, memory_time)),
+                    ),
+                ),
+            ) = if parallel {
+                join(
+                    || {
+                        join(
+                            || timed_apart(gen_arithmetic),
+                            || timed_apart(gen_byte_packing),
+                        )
+                    },
+                    || {
+                        join(
+                            || join(|| timed_apart(gen_cpu), || timed_apart(gen_keccak)),
+                            || {
+                                join(
+                                    || timed_apart(gen_keccak_sponge),
+                                    || join(|| timed_apart(gen_logic), || timed_apart(gen_memory)),
+                                )
+                            },
+                        )
+                    },
+                )
+            } else {
+                // Below the threshold, the overhead of scheduling the tables on
+                // the thread pool outweighs the gain of generating them
+                // concurrently.
+                (
+                    (
+                        timed_table(timing, Table::Arithmetic, gen_arithmetic),
+                        timed_table(timing, Table::BytePacking, gen_byte_packing),
+                    ),
+                    (
+                        (
+                            timed_table(timing, Table::Cpu, gen_cpu),
+                            timed_table(timing, Table::Keccak, gen_keccak),
+                        ),
+                        (
+                            timed_table(timing, Table::KeccakSponge, gen_keccak_sponge),
+                            (
+                                timed_table(timing, Table::Logic, gen_logic),
+                                timed_table(timing, Table::Memory, gen_memory),
+                            ),
+                        ),
+                    ),
+                )
+            };
+
+            let durations = [
+                arithmetic_time,
+                byte_packing_time,
+                cpu_time,
+                keccak_time,
+                keccak_sponge_time,
+                logic_time,
+                memory_time,
+            ];
+            (
+                [
+                    arithmetic_trace,
+                    byte_packing_trace,
+                    cpu_trace,
+                    keccak_trace,
+                    keccak_sponge_trace,
+                    logic_trace,
+                    memory_trace,
+                ],
+                durations,
+            )
+        })
     }
 }
 
+/// Removes the operations matching `is_after` from `ops` and returns them,
+/// preserving the order of both parts.
+fn split_off_after<Op>(ops: &mut Vec<Op>, is_after: impl Fn(&Op) -> bool) -> Vec<Op> {
+    let (before, after) = mem::take(ops).into_iter().partition(|op| !is_after(op));
+    *ops = before;
+    after
+}
+
+/// Same as [`starky::util::trace_rows_to_poly_values`] for the CPU rows, but
+/// converting the rows and then transposing them column by column in parallel
+/// when the `parallel` feature is enabled. The CPU table being the widest, this
+/// is noticeably faster than the sequential transpose.
+///
+/// The columns are written to `polys`, whose buffers get reused.
+fn cpu_trace_rows_to_poly_values<F: RichField>(
+    cpu: Vec<CpuColumnsView<F>>,
+    mut polys: Vec<PolynomialValues<F>>,
+) -> Vec<PolynomialValues<F>> {
+    let cpu_rows: Vec<[F; NUM_CPU_COLUMNS]> = cpu.into_par_iter().map(|x| x.into()).collect();
+
+    polys.resize_with(NUM_CPU_COLUMNS, || PolynomialValues { values: Vec::new() });
+    polys.par_iter_mut().enumerate().for_each(|(col, poly)| {
+        poly.values.clear();
+        poly.values.extend(cpu_rows.iter().map(|row| row[col]));
+    });
+
+    polys
+}
+
+/// The name of the timing scope of the generation of each table, in the order
+/// of [`Table`].
+const TRACE_TIMING_NAMES: [&str; NUM_TABLES] = [
+    "generate arithmetic trace",
+    "generate byte packing trace",
+    "generate CPU trace",
+    "generate Keccak trace",
+    "generate Keccak sponge trace",
+    "generate logic trace",
+    "generate memory trace",
+];
+
+/// Runs `f`, generating the trace of `table`, in a scope of `timing`, and also
+/// returns the time `f` took to run.
+fn timed_table<R>(
+    timing: &mut TimingTree,
+    table: Table,
+    f: impl FnOnce(&mut TimingTree) -> R,
+) -> (R, Duration) {
+    let start = Instant::now();
+    let res = timed!(timing, TRACE_TIMING_NAMES[*table], f(timing));
+    (res, start.elapsed())
+}
+
+/// Runs `f` on another thread than the one owning the caller's `TimingTree`,
+/// and returns the time `f` took to run. The scopes `f` opens are recorded on a
+/// throwaway `TimingTree`, and hence lost.
+fn timed_apart<R>(f: impl FnOnce(&mut TimingTree) -> R) -> (R, Duration) {
+    let start = Instant::now();
+    let res = f(&mut TimingTree::default());
+    (res, start.elapsed())
+}
+
 impl<T: Copy> Default for Traces<T> {
     fn default() -> Self {
         Self::new()
     }
 }
+
+/// Returns traces with `num_ops` dummy operations for the arithmetic, CPU,
+/// logic, memory and Keccak STARK modules, to test and benchmark the generation
+/// of the tables.
+#[cfg(any(test, feature = "bench-internals"))]
+pub fn dummy_traces<F: RichField>(num_ops: usize) -> Traces<F> {
+    use ethereum_types::U256;
+
+    use crate::arithmetic::BinaryOperator;
+    use crate::memory::segments::Segment;
+    use crate::witness::memory::MemoryAddress;
+
+    let mut traces = Traces::new();
+    for i in 0..num_ops {
+        traces.arithmetic_ops.push(Operation::binary(
+            BinaryOperator::Div,
+            U256::from(i),
+            U256::one(),
+        ));
+        traces.cpu.push(CpuColumnsView::default());
+        traces.logic_ops.push(logic::Operation::new(
+            logic::Op::And,
+            U256::from(i),
+            U256::one(),
+        ));
+        traces.memory_ops.push(MemoryOp::new_dummy_read(
+            MemoryAddress::new(0, Segment::Code, i),
+            i,
+            U256::zero(),
+        ));
+        traces
+            .keccak_inputs
+            .push(([0; keccak::keccak_stark::NUM_INPUTS], i));
+    }
+
+    traces
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::field::goldilocks_field::GoldilocksField as F;
+    use plonky2::field::types::Field;
+    use starky::util::trace_rows_to_poly_values;
+
+    use super::*;
+
+    #[test]
+    fn rollback_drops_every_op_recorded_since_the_checkpoint() {
+        let mut traces = dummy_traces::<F>(3);
+        let checkpoint = traces.checkpoint();
+
+        traces = traces.merge(dummy_traces::<F>(2));
+        assert_ne!(traces.checkpoint(), checkpoint);
+
+        traces.rollback(checkpoint);
+        let expected = dummy_traces::<F>(3);
+        assert_eq!(traces.checkpoint(), checkpoint);
+        assert_eq!(traces.get_lengths(), expected.get_lengths());
+        assert_eq!(traces.keccak_inputs, expected.keccak_inputs);
+
+        let all_stark = AllStark::<F, 2>::default();
+        let config = StarkConfig::standard_fast_config();
+        assert_eq!(
+            traces.into_tables(&all_stark, &config, &mut TimingTree::default()),
+            expected.into_tables(&all_stark, &config, &mut TimingTree::default())
+        );
+    }
+
+    #[test]
+    fn memory_ops_count_since_counts_the_ops_recorded_after_the_count() {
+        let mut traces = dummy_traces::<F>(3);
+        let count = traces.memory_ops_count();
+        assert_eq!(count, 3);
+        assert_eq!(traces.memory_ops_count_since(count), 0);
+
+        let checkpoint = traces.checkpoint();
+        traces = traces.merge(dummy_traces::<F>(2));
+        assert_eq!(traces.memory_ops_count_since(count), 2);
+        assert_eq!(
+            traces.memory_ops_count_since(count),
+            traces.mem_ops_since(checkpoint).len()
+        );
+
+        traces.rollback(TraceCheckpoint {
+            memory_len: 1,
+            ..checkpoint
+        });
+        assert_eq!(traces.memory_ops_count_since(count), 0);
+    }
+
+    #[test]
+    fn into_tables_into_matches_into_tables() {
+        let all_stark = AllStark::<F, 2>::default();
+        let config = StarkConfig::standard_fast_config();
+
+        // Start from the (larger) tables of a previous segment.
+        let mut out =
+            dummy_traces::<F>(8).into_tables(&all_stark, &config, &mut TimingTree::default());
+        dummy_traces::<F>(3).into_tables_into(
+            &all_stark,
+            &config,
+            &mut TimingTree::default(),
+            &mut out,
+        );
+
+        assert_eq!(
+            out,
+            dummy_traces::<F>(3).into_tables(&all_stark, &config, &mut TimingTree::default())
+        );
+    }
+
+    #[test]
+    fn tables_do_not_depend_on_the_parallel_threshold() {
+        let all_stark = AllStark::<F, 2>::default();
+        let config = StarkConfig::standard_fast_config();
+        let num_cells = dummy_traces::<F>(5).get_lengths().num_cells();
+
+        let [sequential, at_threshold, parallel] = [num_cells + 1, num_cells, 0].map(|min_cells| {
+            dummy_traces::<F>(5)
+                .into_tables_with_parallel_threshold(
+                    &all_stark,
+                    &config,
+                    &mut TimingTree::default(),
+                    min_cells,
+                )
+                .0
+        });
+        assert_eq!(sequential, parallel);
+        assert_eq!(at_threshold, parallel);
+    }
+
+    #[test]
+    fn empty_tables_are_the_ones_without_ops() {
+        let traces = dummy_traces::<F>(3);
+
+        let mut expected = [false; NUM_TABLES];
+        expected[*Table::BytePacking] = true;
+        expected[*Table::KeccakSponge] = true;
+        assert_eq!(traces.get_lengths().empty_tables(), expected);
+        assert_eq!(
+            Traces::<F>::new().get_lengths().empty_tables(),
+            [true; NUM_TABLES]
+        );
+    }
+
+    #[test]
+    fn split_at_cpu_splits_the_ops_by_clock() {
+        let mut traces = dummy_traces::<F>(4);
+        for (clock, op) in traces.memory_ops.iter_mut().enumerate() {
+            op.timestamp = clock * NUM_CHANNELS;
+        }
+        for (clock, (_, timestamp)) in traces.keccak_inputs.iter_mut().enumerate() {
+            *timestamp = clock * NUM_CHANNELS;
+        }
+        let lengths = traces.get_lengths();
+
+        let tail = traces.split_at_cpu(3);
+
+        assert_eq!(traces.cpu.len(), 3);
+        assert_eq!(traces.memory_ops.len(), 3);
+        assert_eq!(traces.keccak_inputs.len(), 3);
+        assert_eq!(tail.cpu.len(), 1);
+        assert_eq!(tail.memory_ops.len(), 1);
+        assert_eq!(tail.keccak_inputs.len(), 1);
+        assert_eq!(tail.memory_ops[0].timestamp, 3 * NUM_CHANNELS);
+
+        // The arithmetic and logic ops can't be attributed to a cycle.
+        assert!(tail.arithmetic_ops.is_empty());
+        assert!(tail.logic_ops.is_empty());
+
+        assert_eq!(traces.merge(tail).get_lengths(), lengths);
+    }
+
+    #[test]
+    fn check_max_heights_names_the_overflowing_table() {
+        let traces = dummy_traces::<F>(4);
+        let config = StarkConfig::standard_fast_config();
+        let heights = traces.get_lengths().padded_heights(&config);
+
+        assert!(traces.check_max_heights(&config, &heights).is_ok());
+
+        let mut max_heights = heights;
+        max_heights[*Table::Logic] -= 1;
+        let err = traces.check_max_heights(&config, &max_heights).unwrap_err();
+        assert!(err.to_string().contains("Logic"));
+    }
+
+    #[test]
+    fn largest_padded_table_has_the_max_padded_height() {
+        let traces = dummy_traces::<F>(4);
+        let config = StarkConfig::standard_fast_config();
+        let heights = traces.get_lengths().padded_heights(&config);
+
+        let (table, height) = traces.largest_padded_table(&config);
+        assert_eq!(height, heights.into_iter().max().unwrap());
+        assert_eq!(heights[*table], height);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn traces_serialization_roundtrip() {
+        let traces = dummy_traces::<F>(4);
+
+        let serialized = serde_json::to_string(&traces).unwrap();
+        let deserialized: Traces<F> = serde_json::from_str(&serialized).unwrap();
+
+        assert_eq!(deserialized.get_lengths(), traces.get_lengths());
+        assert_eq!(deserialized.cpu, traces.cpu);
+    }
+
+    #[test]
+    fn cpu_trace_is_identical_to_the_sequential_transpose() {
+        let cpu: Vec<CpuColumnsView<F>> = (0..8)
+            .map(|i| {
+                let row: [F; NUM_CPU_COLUMNS] =
+                    core::array::from_fn(|col| F::from_canonical_usize(i * NUM_CPU_COLUMNS + col));
+                row.into()
+            })
+            .collect();
+
+        let sequential_rows: Vec<[F; NUM_CPU_COLUMNS]> = cpu.iter().map(|&x| x.into()).collect();
+        let sequential = trace_rows_to_poly_values(sequential_rows);
+
+        assert_eq!(cpu_trace_rows_to_poly_values(cpu, Vec::new()), sequential);
+    }
+
+    #[test]
+    fn summary_lists_every_trace_length() {
+        let traces = Traces::<F>::new();
+        assert!(traces.is_empty());
+        assert_eq!(
+            traces.summary(),
+            "arith=0 bp=0 cpu=0 keccak=0 sponge=0 logic=0 mem=0"
+        );
+
+        let traces = dummy_traces::<F>(3);
+        assert!(!traces.is_empty());
+        assert_eq!(
+            traces.summary(),
+            format!(
+                "arith=6 bp=0 cpu=3 keccak={} sponge=0 logic=3 mem=3",
+                3 * keccak::keccak_stark::NUM_ROUNDS
+            )
+        );
+    }
+
+    #[test]
+    fn merged_lengths_are_the_sum_of_both_lengths() {
+        let a = dummy_traces::<F>(3);
+        let b = dummy_traces::<F>(5);
+        let (a_lens, b_lens) = (a.get_lengths(), b.get_lengths());
+
+        let merged_lens = a.merge(b).get_lengths();
+
+        assert_eq!(
+            merged_lens,
+            TraceCheckpoint {
+                arithmetic_len: a_lens.arithmetic_len + b_lens.arithmetic_len,
+                byte_packing_len: a_lens.byte_packing_len + b_lens.byte_packing_len,
+                cpu_len: a_lens.cpu_len + b_lens.cpu_len,
+                keccak_len: a_lens.keccak_len + b_lens.keccak_len,
+                keccak_sponge_len: a_lens.keccak_sponge_len + b_lens.keccak_sponge_len,
+                logic_len: a_lens.logic_len + b_lens.logic_len,
+                memory_len: a_lens.memory_len + b_lens.memory_len,
+            }
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! Benchmarks hashing a state trie after every txn of a block, either from
+//! scratch each time or reusing the cached hashes of the subtries that the
+//! txn left untouched.
+//!
+//! The block is simulated by `300` txns, each updating a few random accounts
+//! of a trie holding `10_000` of them.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use ethereum_types::H256;
+use mpt_trie::{
+    nibbles::Nibbles,
+    partial_trie::{HashedPartialTrie, Node, PartialTrie, StandardTrie},
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+const NUM_ACCOUNTS: usize = 10_000;
+const NUM_TXNS: usize = 300;
+const NUM_WRITES_PER_TXN: usize = 8;
+
+type TrieWrite = (Nibbles, Vec<u8>);
+
+fn random_val(rng: &mut StdRng) -> Vec<u8> {
+    rng.gen::<[u8; 32]>().to_vec()
+}
+
+fn random_block(rng: &mut StdRng) -> (Vec<TrieWrite>, Vec<Vec<TrieWrite>>) {
+    let accounts: Vec<_> = (0..NUM_ACCOUNTS)
+        .map(|_| (Nibbles::from_h256_be(H256(rng.gen())), random_val(rng)))
+        .collect();
+
+    let txn_writes = (0..NUM_TXNS)
+        .map(|_| {
+            accounts
+                .choose_multiple(rng, NUM_WRITES_PER_TXN)
+                .map(|(k, _)| (*k, random_val(rng)))
+                .collect()
+        })
+        .collect();
+
+    (accounts, txn_writes)
+}
+
+fn initial_trie<T: PartialTrie>(accounts: &[TrieWrite]) -> T {
+    let mut trie = T::new(Node::Empty);
+    trie.extend(accounts.iter().cloned()).unwrap();
+
+    // Like the state trie after the previous txn of a block, the trie has been
+    // hashed once already.
+    trie.hash();
+    trie
+}
+
+fn hash_after_every_txn<T: PartialTrie>(mut trie: T, txn_writes: &[Vec<TrieWrite>]) {
+    for writes in txn_writes {
+        trie.extend(writes.iter().cloned()).unwrap();
+        black_box(trie.hash());
+    }
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let (accounts, txn_writes) = random_block(&mut StdRng::seed_from_u64(0));
+    let standard_trie: StandardTrie = initial_trie(&accounts);
+    let hashed_trie: HashedPartialTrie = initial_trie(&accounts);
+
+    let mut group = c.benchmark_group("Trie hashing after each of 300 txns");
+    group.bench_function("Full", |b| {
+        b.iter_batched(
+            || standard_trie.clone(),
+            |trie| hash_after_every_txn(trie, &txn_writes),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("Incremental", |b| {
+        b.iter_batched(
+            || hashed_trie.clone(),
+            |trie| hash_after_every_txn(trie, &txn_writes),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = criterion_benchmark);
+criterion_main!(benches);
@@ -6,10 +6,12 @@ use std::sync::Arc;
 use ethereum_types::H256;
 use keccak_hash::keccak;
 use rlp::{Prototype, Rlp};
+use serde::{Deserialize, Serialize};
 
 use super::{
     nibbles::Nibbles,
     partial_trie::{Node, PartialTrie, WrappedNode},
+    trie_hashing::collect_rlp_encoded_nodes,
 };
 
 /// The hash of an empty trie.
@@ -75,6 +77,14 @@ impl<T: PartialTrie> PartialTrieBuilder<T> {
         construct_partial_trie(self.root, &self.nodes)
     }
 
+    /// Builds the partial trie rooted at `root` from the nodes, without
+    /// consuming the builder. This allows building several tries sharing the
+    /// same collection of nodes, e.g. the storage tries of the accounts of a
+    /// state trie.
+    pub fn build_at(&self, root: H256) -> T {
+        construct_partial_trie(root, &self.nodes)
+    }
+
     fn insert_short_node_variants(&mut self, bytes: Vec<Vec<u8>>) {
         let is_leaf = is_leaf_node(&bytes);
         let mut nibbles = Nibbles::from_bytes_be(&bytes[0][..]).unwrap();
@@ -89,6 +99,64 @@ impl<T: PartialTrie> PartialTrieBuilder<T> {
     }
 }
 
+/// A partial trie encoded as its root hash along with the RLP encoding of its
+/// nodes, serialized as hex strings.
+///
+/// Nodes replaced by a `Hash` node in the partial trie have no encoding, so
+/// they remain `Hash` nodes once decoded.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RlpEncodedTrie {
+    /// The root hash of the trie.
+    pub root: H256,
+    /// The RLP encoding of every node referred to by its hash.
+    #[serde(with = "hex_nodes")]
+    pub nodes: Vec<Vec<u8>>,
+}
+
+impl RlpEncodedTrie {
+    /// Encodes the given trie.
+    pub fn from_trie<T: PartialTrie>(trie: &T) -> Self {
+        let mut nodes = Vec::new();
+        collect_rlp_encoded_nodes(trie, true, &mut nodes);
+
+        Self {
+            root: trie.hash(),
+            nodes,
+        }
+    }
+
+    /// Decodes the trie back.
+    pub fn into_trie<T: PartialTrie>(self) -> T {
+        PartialTrieBuilder::new(
+            self.root,
+            self.nodes
+                .into_iter()
+                .map(|node| (keccak(&node), node))
+                .collect(),
+        )
+        .build()
+    }
+}
+
+mod hex_nodes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(nodes: &[Vec<u8>], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(
+            nodes
+                .iter()
+                .map(|node| impl_serde::serialize::to_hex(node, false)),
+        )
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(d)?
+            .iter()
+            .map(|node| impl_serde::serialize::from_hex(node).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
 /// Constructs a partial trie from a root hash and a collection of nodes.
 fn construct_partial_trie<T: PartialTrie>(hash: H256, nodes: &HashMap<H256, Vec<u8>>) -> T {
     let bytes = match nodes.get(&hash) {
@@ -180,3 +248,44 @@ fn parse_child_node<T: PartialTrie>(bytes: &[u8], nodes: &HashMap<H256, Vec<u8>>
         _ => construct_partial_trie(H256::from_slice(bytes), nodes),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        partial_trie::HashedPartialTrie,
+        testing_utils::{common_setup, generate_n_random_variable_trie_value_entries},
+        trie_subsets::create_trie_subset,
+        utils::TryFromIterator,
+    };
+
+    #[test]
+    fn rlp_encoded_trie_round_trips_through_json() {
+        common_setup();
+
+        let entries: Vec<_> = generate_n_random_variable_trie_value_entries(500, 42).collect();
+        let full_trie = HashedPartialTrie::try_from_iter(entries.iter().cloned()).unwrap();
+        // Hash most of the trie out, so that the encoded trie holds `Hash` nodes.
+        let trie = create_trie_subset(&full_trie, entries.iter().take(5).map(|(k, _)| *k)).unwrap();
+
+        let encoded = RlpEncodedTrie::from_trie(&trie);
+        assert_eq!(encoded.root, full_trie.hash());
+
+        let json = serde_json::to_string(&encoded).unwrap();
+        let decoded: RlpEncodedTrie = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, encoded);
+
+        let decoded_trie: HashedPartialTrie = decoded.into_trie();
+        assert_eq!(decoded_trie, trie);
+        assert_eq!(decoded_trie.hash(), full_trie.hash());
+    }
+
+    #[test]
+    fn empty_rlp_encoded_trie_decodes_to_an_empty_trie() {
+        let encoded = RlpEncodedTrie::from_trie(&HashedPartialTrie::default());
+        assert!(encoded.nodes.is_empty());
+
+        let decoded_trie: HashedPartialTrie = encoded.into_trie();
+        assert_eq!(decoded_trie, HashedPartialTrie::default());
+    }
+}
@@ -36,9 +36,20 @@ pub(crate) fn hash_trie<N: PartialTrie + TrieNodeIntern>(node: &Node<N>) -> H256
 pub(crate) fn rlp_encode_and_hash_node<N: PartialTrie + TrieNodeIntern>(
     node: &Node<N>,
 ) -> EncodedNode {
-    let res = match node {
-        Node::Empty => EncodedNode::Raw(Bytes::from_static(&rlp::NULL_RLP)),
+    match node {
         Node::Hash(h) => EncodedNode::Hashed(h.0),
+        _ => hash_bytes_if_large_enough(rlp_encode_node(node)),
+    }
+}
+
+/// RLP encodes a node, referring to its children by their hash if their own
+/// encoding is large enough.
+///
+/// Must not be called on a `Hash` node, whose encoding is unknown.
+fn rlp_encode_node<N: PartialTrie + TrieNodeIntern>(node: &Node<N>) -> Bytes {
+    match node {
+        Node::Empty => Bytes::from_static(&rlp::NULL_RLP),
+        Node::Hash(_) => unreachable!("The encoding of a hash node is unknown"),
         Node::Branch { children, value } => {
             let mut stream = RlpStream::new_list(17);
 
@@ -51,7 +62,7 @@ pub(crate) fn rlp_encode_and_hash_node<N: PartialTrie + TrieNodeIntern>(
                 true => stream.append_empty_data(),
             };
 
-            hash_bytes_if_large_enough(stream.out().into())
+            stream.out().into()
         }
         Node::Extension { nibbles, child } => {
             let mut stream = RlpStream::new_list(2);
@@ -59,7 +70,7 @@ pub(crate) fn rlp_encode_and_hash_node<N: PartialTrie + TrieNodeIntern>(
             stream.append(&nibbles.to_hex_prefix_encoding(false));
             append_to_stream(&mut stream, child.hash_intern());
 
-            hash_bytes_if_large_enough(stream.out().into())
+            stream.out().into()
         }
         Node::Leaf { nibbles, value } => {
             let hex_prefix_k = nibbles.to_hex_prefix_encoding(true);
@@ -68,11 +79,34 @@ pub(crate) fn rlp_encode_and_hash_node<N: PartialTrie + TrieNodeIntern>(
             stream.append(&hex_prefix_k);
             stream.append(value);
 
-            hash_bytes_if_large_enough(stream.out().into())
+            stream.out().into()
         }
-    };
+    }
+}
+
+/// Collects the RLP encoding of every node of a trie that is referred to by
+/// its hash, i.e. of the root and of every node whose encoding is at least
+/// `32` bytes long. Other nodes are embedded in the encoding of their parent.
+///
+/// `Hash` nodes and empty nodes have nothing to collect.
+pub(crate) fn collect_rlp_encoded_nodes<N: PartialTrie + TrieNodeIntern>(
+    node: &Node<N>,
+    is_root: bool,
+    encoded_nodes: &mut Vec<Vec<u8>>,
+) {
+    match node {
+        Node::Empty | Node::Hash(_) => return,
+        Node::Branch { children, .. } => children
+            .iter()
+            .for_each(|c| collect_rlp_encoded_nodes(c, false, encoded_nodes)),
+        Node::Extension { child, .. } => collect_rlp_encoded_nodes(child, false, encoded_nodes),
+        Node::Leaf { .. } => (),
+    }
 
-    res
+    let encoded_node = rlp_encode_node(node);
+    if is_root || encoded_node.len() >= 32 {
+        encoded_nodes.push(encoded_node.to_vec());
+    }
 }
 
 fn hash_bytes_if_large_enough(bytes: Bytes) -> EncodedNode {
@@ -455,4 +489,34 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn updating_a_trie_only_invalidates_the_cached_hashes_along_the_updated_path(
+    ) -> TrieOpResult<()> {
+        common_setup();
+
+        let mut trie =
+            HashedPartialTrie::try_from_iter([large_entry(0x1234), large_entry(0x2345)])?;
+        let cached_hash_of_root_child = |trie: &HashedPartialTrie, nibble: usize| match &**trie {
+            Node::Branch { children, .. } => *children[nibble].hash.read(),
+            _ => unreachable!(),
+        };
+
+        let orig_hash = trie.hash();
+        assert!(cached_hash_of_root_child(&trie, 1).is_some());
+        assert!(cached_hash_of_root_child(&trie, 2).is_some());
+
+        let (k, v) = large_entry(0x1567);
+        trie.insert(k, v.clone())?;
+        assert!(cached_hash_of_root_child(&trie, 1).is_none());
+        assert!(cached_hash_of_root_child(&trie, 2).is_some());
+
+        let mut expected_trie =
+            HashedPartialTrie::try_from_iter([large_entry(0x1234), large_entry(0x2345)])?;
+        expected_trie.insert(k, v)?;
+        assert_eq!(trie.hash(), expected_trie.hash());
+        assert_ne!(trie.hash(), orig_hash);
+
+        Ok(())
+    }
 }
@@ -6,6 +6,8 @@ use std::{fmt::Display, mem::size_of};
 use enum_as_inner::EnumAsInner;
 use ethereum_types::{H256, U128, U256, U512};
 use log::trace;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
@@ -19,7 +21,8 @@ use crate::{
 pub type TrieOpResult<T> = Result<T, TrieOpError>;
 
 /// An error type for trie operation.
-#[derive(Clone, Debug, Error)]
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub enum TrieOpError {
     /// An error that occurs when a hash node is found during an insert
     /// operation.
@@ -56,7 +59,7 @@ impl From<(Nibbles, ValOrHash)> for InsertEntry {
 
 impl Display for InsertEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "TrieEntry: (k: {}, v: {:?})", &self.nibbles, self.v)
+        write!(f, "TrieEntry: (k: {}, v: {})", &self.nibbles, self.v)
     }
 }
 
@@ -146,6 +149,39 @@ impl ValOrHash {
         self.into_val()
             .expect("Expected a `ValOrHash` to be a value")
     }
+
+    /// The number of bytes held by this entry. Always `32` for a
+    /// [`ValOrHash::Hash`].
+    pub fn len(&self) -> usize {
+        match self {
+            ValOrHash::Val(v) => v.len(),
+            ValOrHash::Hash(h) => h.as_bytes().len(),
+        }
+    }
+
+    /// Returns `true` if this is a [`ValOrHash::Val`] holding no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The max number of bytes of a [`ValOrHash::Val`] that get printed by its
+/// `Display` impl before the output is truncated.
+const MAX_DISPLAYED_VAL_BYTES: usize = 32;
+
+impl Display for ValOrHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValOrHash::Val(v) if v.len() > MAX_DISPLAYED_VAL_BYTES => write!(
+                f,
+                "Val(0x{}... ({} bytes))",
+                hex::encode(&v[..MAX_DISPLAYED_VAL_BYTES]),
+                v.len()
+            ),
+            ValOrHash::Val(v) => write!(f, "Val(0x{})", hex::encode(v)),
+            ValOrHash::Hash(h) => write!(f, "Hash(0x{})", hex::encode(h.as_bytes())),
+        }
+    }
 }
 
 /// prefix/postfix info when comparing two `Nibbles`.
@@ -816,6 +852,7 @@ fn create_node_if_ins_val_not_hash<N, F: FnOnce(Vec<u8>) -> WrappedNode<N>>(
 mod tests {
     use std::{collections::HashSet, iter::once};
 
+    use ethereum_types::H256;
     use log::debug;
 
     use super::ValOrHash;
@@ -1172,4 +1209,22 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn val_or_hash_display_truncates_long_vals() {
+        let short = ValOrHash::Val(vec![0xab; 2]);
+        assert_eq!(short.to_string(), "Val(0xabab)");
+        assert_eq!(short.len(), 2);
+
+        let long = ValOrHash::Val(vec![1; 40]);
+        assert_eq!(
+            long.to_string(),
+            format!("Val(0x{}... (40 bytes))", "01".repeat(32))
+        );
+
+        let hash = ValOrHash::Hash(H256::repeat_byte(0xff));
+        assert_eq!(hash.to_string(), format!("Hash(0x{})", "ff".repeat(32)));
+        assert!(hash.is_hash());
+        assert_eq!(hash.len(), 32);
+    }
 }
@@ -9,6 +9,8 @@ use std::{
 
 use ethereum_types::H256;
 use num_traits::PrimInt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use crate::{
     nibbles::{Nibble, Nibbles, NibblesIntern},
@@ -17,6 +19,7 @@ use crate::{
 };
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 /// Simplified trie node type to make logging cleaner.
 pub enum TrieNodeType {
     /// Empty node.
@@ -4,9 +4,12 @@
 //! The block being processed here is the 19240650th Ethereum block
 //! (<https://etherscan.io/block/19240650>) containing 201 transactions and 16 withdrawals.
 
+use std::sync::{Arc, Mutex};
+
 use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 use serde::{Deserialize, Serialize};
 use trace_decoder::{
+    key_hash_cache::KeyHashCache,
     processed_block_trace::ProcessingMeta,
     trace_protocol::BlockTrace,
     types::{CodeHash, OtherBlockData},
@@ -40,6 +43,51 @@ fn criterion_benchmark(c: &mut Criterion) {
             BatchSize::LargeInput,
         )
     });
+
+    // Only times the decoding of the processed trace into the IR, which excludes
+    // the parsing of the pre-images and the processing of the txn traces.
+    c.bench_function("Block 19240650 decoding of the processed trace", |b| {
+        b.iter_batched(
+            || {
+                prover_input
+                    .block_trace
+                    .clone()
+                    .into_processed(
+                        &ProcessingMeta::new(resolve_code_hash_fn),
+                        &prover_input.other_data,
+                    )
+                    .unwrap()
+            },
+            |processed| {
+                processed
+                    .into_txn_proof_gen_ir(
+                        &ProcessingMeta::new(resolve_code_hash_fn),
+                        prover_input.other_data.clone(),
+                    )
+                    .unwrap()
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    // Each iteration starts from an empty cache, so that only the slots accessed
+    // by several txns of the block hit it.
+    let new_cache = || Arc::new(Mutex::new(KeyHashCache::new(1 << 16)));
+
+    c.bench_function("Block 19240650 processing with a key hash cache", |b| {
+        b.iter_batched(
+            || (prover_input.clone(), new_cache()),
+            |(pi, cache)| {
+                pi.block_trace
+                    .into_txn_proof_gen_ir(
+                        &ProcessingMeta::new(resolve_code_hash_fn).with_key_hash_cache(cache),
+                        prover_input.other_data.clone(),
+                    )
+                    .unwrap()
+            },
+            BatchSize::LargeInput,
+        )
+    });
 }
 
 criterion_group!(
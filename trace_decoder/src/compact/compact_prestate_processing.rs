@@ -50,7 +50,7 @@ const BRANCH_MAX_CHILDREN: usize = 16;
 const CURSOR_ERROR_BYTES_MAX_LEN: usize = 10;
 
 /// An error from processing Erigon's compact witness format.
-#[derive(Debug, Error)]
+#[derive(Debug, Eq, Error, PartialEq)]
 pub enum CompactParsingError {
     /// The header in the compact payload was missing. This is just a single
     /// byte that is used for versioning.
@@ -129,6 +129,11 @@ pub enum CompactParsingError {
     /// Failure due to a trie operation error.
     #[error("Trie operation error: {0}")]
     TrieOpError(TrieOpError),
+
+    /// An error of which only the message is known, e.g. one deserialized as
+    /// part of a [`TraceParsingError`](crate::decoding::TraceParsingError).
+    #[error("{0}")]
+    Message(String),
 }
 
 impl From<TrieOpError> for CompactParsingError {
@@ -139,7 +144,7 @@ impl From<TrieOpError> for CompactParsingError {
 
 /// Represents detailed error information about issues encountered
 /// while processing byte streams with a cursor.
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq)]
 pub struct CursorBytesErrorInfo {
     error_start_pos: usize,
     bad_bytes_hex: String,
@@ -185,7 +190,7 @@ enum Opcode {
 }
 
 /// Compact witness entry.
-#[derive(Clone, Debug, EnumAsInner)]
+#[derive(Clone, Debug, EnumAsInner, Eq, PartialEq)]
 pub enum WitnessEntry {
     /// An instruction.
     Instruction(Instruction),
@@ -248,7 +253,7 @@ impl From<Instruction> for WitnessEntry {
 }
 
 /// A node witness entry.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum NodeEntry {
     /// A branch node.
     Branch([Option<Box<NodeEntry>>; 16]),
@@ -278,7 +283,7 @@ impl Display for NodeEntry {
 }
 
 /// A value of a node data.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ValueNodeData(pub(super) Vec<u8>);
 
 impl From<Vec<u8>> for ValueNodeData {
@@ -288,7 +293,7 @@ impl From<Vec<u8>> for ValueNodeData {
 }
 
 /// A leaf node data.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum LeafNodeData {
     /// A value node.
     Value(ValueNodeData),
@@ -297,7 +302,7 @@ pub enum LeafNodeData {
 }
 
 /// An account node code.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum AccountNodeCode {
     /// A code node.
     CodeNode(Vec<u8>),
@@ -318,7 +323,7 @@ impl From<TrieRootHash> for AccountNodeCode {
 }
 
 /// An account node data.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct AccountNodeData {
     /// The nonce of the account.
     pub nonce: Nonce,
@@ -857,6 +862,32 @@ impl<C: CompactCursor> WitnessBytes<C> {
         Ok(())
     }
 
+    /// Walks the instructions of the witness without building any trie,
+    /// counting them as it goes.
+    fn process_into_size_estimate(mut self) -> CompactParsingResult<CompactPrestateSizeEstimate> {
+        let header = self.parse_header()?;
+        let mut estimate = CompactPrestateSizeEstimate {
+            header,
+            num_bytes: self.byte_cursor.intern().get_ref().len(),
+            num_nodes: 0,
+            num_accounts: 0,
+            num_storage_tries: 0,
+            num_code_entries: 0,
+        };
+
+        while !self.byte_cursor.at_eof() {
+            self.process_operator()?;
+
+            // Only the instruction just parsed is ever kept around.
+            match self.instrs.pop() {
+                Some(WitnessEntry::Instruction(instr)) => estimate.record_instruction(&instr),
+                _ => unreachable!("Parsing an opcode always pushes a single instruction!"),
+            }
+        }
+
+        Ok(estimate)
+    }
+
     fn read_account_flag_field_if_present_or_default<F, T>(
         present_flag: bool,
         mut read_f: F,
@@ -1127,7 +1158,7 @@ impl CompactCursor for DebugCompactCursor {
 
 /// We kind of want a wrapper around the actual data structure I think since
 /// there's a good chance this will change a few times in the future.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Eq, PartialEq)]
 pub struct WitnessEntries {
     // Yeah a LL is actually (unfortunately) a very good choice here. We will be doing a ton of
     // inserts mid-list, and the list can get very large. There might be a better choice for a data
@@ -1237,6 +1268,43 @@ const fn try_get_node_entry_from_witness_entry(entry: &WitnessEntry) -> Option<&
     }
 }
 
+/// A summary of the content of a compact prestate, obtained by
+/// [`estimate_compact_prestate_size`] without building its tries.
+#[derive(Debug)]
+pub struct CompactPrestateSizeEstimate {
+    /// The header of the compact.
+    pub header: Header,
+    /// The size of the compact in bytes, header included.
+    pub num_bytes: usize,
+    /// The number of nodes (leaf, extension, branch, hash and empty root) over
+    /// the state and all storage tries.
+    pub num_nodes: usize,
+    /// The number of accounts in the state trie.
+    pub num_accounts: usize,
+    /// The number of accounts with a storage trie.
+    pub num_storage_tries: usize,
+    /// The number of contract codes embedded in the compact.
+    pub num_code_entries: usize,
+}
+
+impl CompactPrestateSizeEstimate {
+    fn record_instruction(&mut self, instr: &Instruction) {
+        match instr {
+            Instruction::Leaf(..)
+            | Instruction::Extension(_)
+            | Instruction::Branch(_)
+            | Instruction::Hash(_)
+            | Instruction::EmptyRoot => self.num_nodes += 1,
+            Instruction::Code(_) => self.num_code_entries += 1,
+            Instruction::AccountLeaf(_, _, _, _, has_storage) => {
+                self.num_nodes += 1;
+                self.num_accounts += 1;
+                self.num_storage_tries += usize::from(*has_storage);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct PartialTriePreImages {
     pub state: HashedPartialTrie,
@@ -1276,6 +1344,15 @@ pub fn process_compact_prestate_debug(
     process_compact_prestate_common(state, ParserState::create_and_extract_header_debug)
 }
 
+/// Cheaply summarizes the content of a compact prestate, without building any
+/// of its tries. Useful to decide where to decode a block before committing to
+/// it.
+pub fn estimate_compact_prestate_size(
+    state: &TrieCompact,
+) -> CompactParsingResult<CompactPrestateSizeEstimate> {
+    WitnessBytes::<CompactCursorFast>::new(state.0.clone()).process_into_size_estimate()
+}
+
 fn process_compact_prestate_common(
     state: TrieCompact,
     create_and_extract_header_f: fn(Vec<u8>) -> CompactParsingResult<(Header, ParserState)>,
@@ -1439,10 +1516,16 @@ fn get_bytes_from_cursor<C: CompactCursor>(cursor: &mut C, cursor_start_pos: u64
 mod tests {
     use mpt_trie::nibbles::Nibbles;
 
-    use super::{key_bytes_to_nibbles, parse_just_to_instructions, Instruction};
-    use crate::compact::complex_test_payloads::{
-        TEST_PAYLOAD_1, TEST_PAYLOAD_2, TEST_PAYLOAD_3, TEST_PAYLOAD_4, TEST_PAYLOAD_5,
-        TEST_PAYLOAD_6,
+    use super::{
+        estimate_compact_prestate_size, key_bytes_to_nibbles, parse_just_to_instructions,
+        CompactParsingError, Instruction,
+    };
+    use crate::{
+        compact::complex_test_payloads::{
+            TEST_PAYLOAD_1, TEST_PAYLOAD_2, TEST_PAYLOAD_3, TEST_PAYLOAD_4, TEST_PAYLOAD_5,
+            TEST_PAYLOAD_6,
+        },
+        trace_protocol::TrieCompact,
     };
 
     const SIMPLE_PAYLOAD_STR: &str = "01004110443132333400411044313233340218300042035044313233350218180158200000000000000000000000000000000000000000000000000000000000000012";
@@ -1488,6 +1571,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn size_estimate_counts_the_parsed_instructions() {
+        init();
+
+        let bytes = hex::decode(SIMPLE_PAYLOAD_STR).unwrap();
+        let num_bytes = bytes.len();
+        let estimate = estimate_compact_prestate_size(&TrieCompact(bytes)).unwrap();
+
+        assert_eq!(estimate.header.version, 1);
+        assert_eq!(estimate.num_bytes, num_bytes);
+        assert_eq!(estimate.num_nodes, 6);
+        assert_eq!(estimate.num_accounts, 0);
+        assert_eq!(estimate.num_storage_tries, 0);
+        assert_eq!(estimate.num_code_entries, 0);
+    }
+
+    #[test]
+    fn size_estimate_rejects_invalid_opcodes() {
+        init();
+
+        let res = estimate_compact_prestate_size(&TrieCompact(vec![1, 0xff]));
+        assert!(matches!(res, Err(CompactParsingError::InvalidOpcode(0xff))));
+    }
+
     #[test]
     fn complex_payload_1() {
         init();
@@ -1,33 +1,52 @@
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, VecDeque},
     fmt::{self, Display, Formatter},
-    iter::{self, empty, once},
+    iter::{self, once},
+    mem, slice,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    vec,
 };
 
 use ethereum_types::{Address, H256, U256, U512};
 use evm_arithmetization::{
-    generation::{mpt::AccountRlp, GenerationInputs, TrieInputs},
+    generation::{
+        mpt::{AccountRlp, LegacyReceiptRlp},
+        GenerationInputs, TrieInputs,
+    },
     proof::{ExtraBlockData, TrieRoots},
 };
-use log::trace;
+use log::{trace, warn};
 use mpt_trie::{
+    builder::PartialTrieBuilder,
     nibbles::Nibbles,
     partial_trie::{HashedPartialTrie, Node, PartialTrie},
     special_query::path_for_query,
     trie_ops::{TrieOpError, TrieOpResult},
     trie_subsets::{create_trie_subset, SubsetTrieError},
-    utils::{IntoTrieKey, TriePath},
+    utils::{IntoTrieKey, TriePath, TrieSegment},
 };
+use plonky2_maybe_rayon::*;
+use rlp::Rlp;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tracing::trace_span;
 
 use crate::{
-    compact::compact_prestate_processing::CompactParsingError,
+    compact::compact_prestate_processing::{CompactParsingError, PartialTriePreImages},
+    key_hash_cache::{storage_key, KeyHashCache},
     processed_block_trace::{
-        NodesUsedByTxn, ProcessedBlockTrace, ProcessedTxnInfo, StateTrieWrites, TxnMetaState,
+        NodesUsedByTxn, ProcessedBlockTrace, ProcessedTxnInfo, ProcessingMeta, ProcessingOptions,
+        StateTrieWrites, TxnMetaState,
     },
     types::{
-        HashedAccountAddr, HashedNodeAddr, HashedStorageAddr, HashedStorageAddrNibbles,
-        OtherBlockData, TrieRootHash, TxnIdx, EMPTY_ACCOUNT_BYTES_RLPED,
+        AccountNibbles, Bloom, CodeHash, CodeHashResolveFunc, HashedAccountAddr, HashedNodeAddr,
+        HashedStorageAddr, HashedStorageAddrNibbles, OtherBlockData, StorageNibbles, TrieRootHash,
+        TxnIdx, EMPTY_ACCOUNT_BYTES_RLPED, EMPTY_CODE_HASH, EMPTY_TRIE_HASH, NUM_PREV_BLOCK_HASHES,
         ZERO_STORAGE_SLOT_VAL_RLPED,
     },
     utils::{hash, optional_field, optional_field_hex, update_val_if_some},
@@ -37,6 +56,13 @@ use crate::{
 /// failure.
 pub type TraceParsingResult<T> = Result<T, Box<TraceParsingError>>;
 
+/// Alias of [TraceParsingError], which is the single error type used for both
+/// parsing and decoding failures.
+pub type TraceDecodingError = TraceParsingError;
+
+/// Alias of [TraceParsingResult].
+pub type TraceDecodingResult<T> = TraceParsingResult<T>;
+
 /// Represents errors that can occur during the processing of a block trace.
 ///
 /// This struct is intended to encapsulate various kinds of errors that might
@@ -44,7 +70,11 @@ pub type TraceParsingResult<T> = Result<T, Box<TraceParsingError>>;
 /// blockchain blocks. It could include issues like malformed trace data,
 /// inconsistencies found during processing, or any other condition that
 /// prevents successful completion of the trace processing task.
+///
+/// With the `serde` feature enabled, this error (along with all of its optional
+/// context fields) can be serialized, e.g. to be sent back to an orchestrator.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct TraceParsingError {
     block_num: Option<U256>,
     block_chain_id: Option<U256>,
@@ -53,6 +83,9 @@ pub struct TraceParsingError {
     h_addr: Option<H256>,
     slot: Option<U512>,
     slot_value: Option<U512>,
+    /// Additional context attached by integrators, see
+    /// [`TraceParsingError::with_context`].
+    context: Vec<(Cow<'static, str>, String)>,
     reason: TraceParsingErrorReason, // The original error type
 }
 
@@ -75,7 +108,13 @@ impl std::fmt::Display for TraceParsingError {
             optional_field_hex("Slot", self.slot),
             optional_field("Hashed Slot", h_slot),
             optional_field_hex("Slot value", self.slot_value),
-        )
+        )?;
+
+        for (key, value) in &self.context {
+            writeln!(f, "{}: {}", key, value)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -92,66 +131,180 @@ impl TraceParsingError {
             h_addr: None,
             slot: None,
             slot_value: None,
+            context: Vec::new(),
             reason,
         }
     }
 
     /// Builder method to set block_num
-    pub(crate) fn block_num(&mut self, block_num: U256) -> &mut Self {
+    pub(crate) fn set_block_num(&mut self, block_num: U256) -> &mut Self {
         self.block_num = Some(block_num);
         self
     }
 
     /// Builder method to set block_chain_id
-    pub(crate) fn block_chain_id(&mut self, block_chain_id: U256) -> &mut Self {
+    pub(crate) fn set_block_chain_id(&mut self, block_chain_id: U256) -> &mut Self {
         self.block_chain_id = Some(block_chain_id);
         self
     }
 
     /// Builder method to set txn_idx
-    pub fn txn_idx(&mut self, txn_idx: usize) -> &mut Self {
+    pub fn set_txn_idx(&mut self, txn_idx: usize) -> &mut Self {
         self.txn_idx = Some(txn_idx);
         self
     }
 
     /// Builder method to set addr
-    pub fn addr(&mut self, addr: Address) -> &mut Self {
+    pub fn set_addr(&mut self, addr: Address) -> &mut Self {
         self.addr = Some(addr);
         self
     }
 
     /// Builder method to set h_addr
-    pub fn h_addr(&mut self, h_addr: H256) -> &mut Self {
+    pub fn set_h_addr(&mut self, h_addr: H256) -> &mut Self {
         self.h_addr = Some(h_addr);
         self
     }
 
     /// Builder method to set slot
-    pub fn slot(&mut self, slot: U512) -> &mut Self {
+    pub fn set_slot(&mut self, slot: U512) -> &mut Self {
         self.slot = Some(slot);
         self
     }
 
     /// Builder method to set slot_value
-    pub fn slot_value(&mut self, slot_value: U512) -> &mut Self {
+    pub fn set_slot_value(&mut self, slot_value: U512) -> &mut Self {
         self.slot_value = Some(slot_value);
         self
     }
+
+    /// Attaches an arbitrary key/value pair to this error, e.g. the endpoint
+    /// the trace was fetched from. These get displayed after the built-in
+    /// fields.
+    pub fn with_context(&mut self, key: &'static str, value: String) -> &mut Self {
+        self.context.push((Cow::Borrowed(key), value));
+        self
+    }
+
+    /// Returns the key/value pairs attached with
+    /// [`TraceParsingError::with_context`], in insertion order.
+    pub fn context(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.context
+            .iter()
+            .map(|(key, value)| (key.as_ref(), value.as_str()))
+    }
+
+    /// Returns the reason of this error.
+    pub const fn reason(&self) -> &TraceParsingErrorReason {
+        &self.reason
+    }
+
+    /// Returns the block number, if set.
+    pub const fn block_num(&self) -> Option<U256> {
+        self.block_num
+    }
+
+    /// Returns the block chain id, if set.
+    pub const fn block_chain_id(&self) -> Option<U256> {
+        self.block_chain_id
+    }
+
+    /// Returns the txn index, if set.
+    pub const fn txn_idx(&self) -> Option<usize> {
+        self.txn_idx
+    }
+
+    /// Returns the address, if set.
+    pub const fn addr(&self) -> Option<Address> {
+        self.addr
+    }
+
+    /// Returns the hashed address, if set.
+    pub const fn h_addr(&self) -> Option<H256> {
+        self.h_addr
+    }
+
+    /// Returns the slot, if set.
+    pub const fn slot(&self) -> Option<U512> {
+        self.slot
+    }
+
+    /// Returns the slot value, if set.
+    pub const fn slot_value(&self) -> Option<U512> {
+        self.slot_value
+    }
 }
 
 /// An error reason for trie parsing.
-#[derive(Debug, Error)]
+#[derive(Debug, Eq, Error, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub enum TraceParsingErrorReason {
     /// Failure to decode an Ethereum Account.
     #[error("Failed to decode RLP bytes ({0}) as an Ethereum account due to the error: {1}")]
     AccountDecode(String, String),
 
+    /// Failure to decode a txn receipt trie node from the given hex-encoded
+    /// bytes.
+    #[error("Failed to decode RLP bytes ({0}) as a legacy or typed txn receipt")]
+    ReceiptDecode(String),
+
+    /// Failure due to the union of the logs blooms of the receipts of the block
+    /// not matching the block bloom, when checked through
+    /// [`ProcessingMeta::with_validate_receipts`].
+    #[error("The logs bloom of the receipts does not match the block one (expected: {expected:?}, got: {got:?})")]
+    ReceiptsBloomMismatch {
+        /// The bloom of the block metadata.
+        expected: Bloom,
+        /// The union of the blooms of the receipts.
+        got: Bloom,
+    },
+
+    /// Failure to deserialize a block witness, e.g. due to a missing field.
+    #[error("Failed to deserialize the block witness due to the error: {0}")]
+    BlockWitnessDecode(String),
+
+    /// Failure to decode RLP bytes.
+    #[error("Failed to decode RLP bytes due to the error: {0}")]
+    RlpDecode(String),
+
     /// Failure due to trying to access or delete a storage trie missing
     /// from the base trie.
     #[error("Missing account storage trie in base trie when constructing subset partial trie for txn (account: {0:x})")]
     MissingAccountStorageTrie(HashedAccountAddr),
 
+    /// Failure due to a storage trie supplied by a compact pre-image not
+    /// hashing to the storage root of its account in the state trie.
+    #[error("The storage trie of account {h_addr:x} in the pre-image has root {in_image:x}, but the account has storage root {in_account:x}")]
+    StorageRootMismatch {
+        /// The hashed address of the account.
+        h_addr: HashedAccountAddr,
+        /// The storage root in the RLP of the account.
+        in_account: TrieRootHash,
+        /// The root hash of the storage trie supplied by the pre-image.
+        in_image: TrieRootHash,
+    },
+
+    /// Failure due to the code resolved for a code hash read by a txn not
+    /// hashing to it, when checked through
+    /// [`ProcessingMeta::with_validate_code_hashes`].
+    #[error("The code resolved for code hash {expected:x} hashes to {got:x}")]
+    CodeHashMismatch {
+        /// The code hash read by the txn.
+        expected: CodeHash,
+        /// The hash of the resolved code.
+        got: CodeHash,
+    },
+
+    /// Failure due to the root node of a pre-image given as RLP-encoded nodes
+    /// being missing from these nodes.
+    #[error("The pre-image nodes do not include the root node {0:x}")]
+    MissingPreImageRoot(TrieRootHash),
+
     /// Failure due to trying to access a non-existent key in the trie.
+    ///
+    /// For [`TrieType::Code`], the key is the code hash whose code could not
+    /// be resolved, and the root hash is [`EMPTY_TRIE_HASH`], as code is not
+    /// kept in a trie.
     #[error("Tried accessing a non-existent key ({1:x}) in the {0} trie (root hash: {2:x})")]
     NonExistentTrieEntry(TrieType, Nibbles, TrieRootHash),
 
@@ -163,15 +316,244 @@ pub enum TraceParsingErrorReason {
     #[error("No account present at {0:x} (hashed: {1:x}) to withdraw {2} Gwei from!")]
     MissingWithdrawalAccount(Address, HashedAccountAddr, U256),
 
+    /// Failure due to the balance of an account overflowing when crediting it
+    /// with a withdrawal.
+    #[error("Withdrawing {amt} Gwei to {addr:x} overflows its balance of {balance}")]
+    WithdrawalBalanceOverflow {
+        /// The address of the withdrawal recipient.
+        addr: Address,
+        /// The balance of the recipient before the withdrawal.
+        balance: U256,
+        /// The withdrawn amount.
+        amt: U256,
+    },
+
+    /// Failure due to the block having withdrawals, but no IR entry to carry
+    /// them. This indicates a bug in the IR padding, which should always
+    /// leave at least one entry.
+    #[error("No IR entry to add the block's {0} withdrawals to")]
+    EmptyIrWithWithdrawals(usize),
+
+    /// Failure due to the accumulated gas used overflowing when adding the gas
+    /// used by a txn.
+    #[error("Gas used overflow when processing txn {0} (gas used before: {1}, txn gas used: {2})")]
+    GasOverflow(TxnIdx, U256, U256),
+
+    /// Failure due to the gas used by an IR entry, i.e. the difference between
+    /// its gas used after and before, not fitting in a `u64`.
+    #[error("Gas used by IR entry {entry_idx} does not fit in a u64 (gas used before: {gas_used_before}, gas used after: {gas_used_after})")]
+    GasUsedOutOfRange {
+        /// The index of the IR entry.
+        entry_idx: usize,
+        /// The gas used before the IR entry.
+        gas_used_before: U256,
+        /// The gas used after the IR entry.
+        gas_used_after: U256,
+    },
+
+    /// Failure due to the txn number overflowing when processing a txn.
+    #[error("Txn number overflow when processing txn {0} (txn number before: {1})")]
+    TxnNumberOverflow(TxnIdx, U256),
+
+    /// Failure due to a txn reporting no gas used, when rejected through
+    /// [`ProcessingMeta::with_reject_zero_gas_txns`].
+    #[error("Txn {0} reports no gas used")]
+    ZeroGasTxn(TxnIdx),
+
+    /// Failure due to the processing of the block being cancelled through
+    /// [`ProcessingMeta::with_cancellation_flag`].
+    #[error("Processing cancelled (last completed txn: {last_completed_txn:?})")]
+    Cancelled {
+        /// The index of the last txn processed before the cancellation, if
+        /// any.
+        last_completed_txn: Option<TxnIdx>,
+    },
+
+    /// Failure due to `txn_number_after` not matching the number of non-dummy
+    /// txns of the block once they have all been processed.
+    #[error("The txn number after the block's txns ({got}) does not match its number of non-dummy txns ({expected})")]
+    TxnNumberMismatch {
+        /// The number of non-dummy txns in the block.
+        expected: usize,
+        /// The txn number after processing the block's txns.
+        got: U256,
+    },
+
+    /// Failure due to a trie root after a txn not matching the expected root
+    /// supplied through [`ProcessingMeta::with_expected_txn_trie_roots`], or
+    /// due to the storage root of an account after a txn not matching the one
+    /// supplied in its [`TxnTrace`](crate::trace_protocol::TxnTrace).
+    #[error("The {trie} trie root after the txn does not match the expected one (expected: {expected:x}, got: {got:x})")]
+    TrieRootMismatch {
+        /// The trie whose root diverged.
+        trie: TrieType,
+        /// The expected root hash.
+        expected: TrieRootHash,
+        /// The root hash computed while processing the txn.
+        got: TrieRootHash,
+    },
+
+    /// Failure due to the state trie root at the end of the block not matching
+    /// [`OtherBlockData::final_state_trie_root`].
+    #[error("The state trie root at the end of the block does not match the expected one (expected: {expected:x}, got: {got:x})")]
+    FinalStateRootMismatch {
+        /// The expected root hash.
+        expected: TrieRootHash,
+        /// The root hash computed after processing the whole block.
+        got: TrieRootHash,
+    },
+
+    /// Failure due to the previous block hashes not holding exactly `256`
+    /// entries.
+    #[error("Expected 256 previous block hashes, but got {0}")]
+    WrongNumberOfPrevBlockHashes(usize),
+
+    /// Failure due to a previous block hash being set for an ancestor that
+    /// does not exist (i.e. one before genesis).
+    #[error(
+        "The previous block hash at index {idx} is set, but block {block_num} has no such ancestor"
+    )]
+    UnexpectedPrevBlockHash {
+        /// The index of the hash in the previous block hashes.
+        idx: usize,
+        /// The number of the block being decoded.
+        block_num: U256,
+    },
+
+    /// Failure due to a previous block hash missing for an existing ancestor.
+    #[error("The previous block hash at index {idx} is missing for block {block_num}")]
+    MissingPrevBlockHash {
+        /// The index of the hash in the previous block hashes.
+        idx: usize,
+        /// The number of the block being decoded.
+        block_num: U256,
+    },
+
     /// Failure due to a trie operation error.
     #[error("Trie operation error: {0}")]
     TrieOpError(TrieOpError),
 
+    /// Failure due to the generated IR being inconsistent, as found by
+    /// [`validate_ir`]. This indicates a bug in the decoding.
+    #[error("The generated IR is inconsistent: {}", display_violations(.0))]
+    InvalidIr(Vec<IrInvariantViolation>),
+
     /// Failure due to a compact parsing error.
+    ///
+    /// Only the error message is kept when serialized, so the error gets
+    /// deserialized as a [`CompactParsingError::Message`].
     #[error("Compact parsing error: {0}")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            serialize_with = "crate::utils::serialize_as_display",
+            deserialize_with = "crate::utils::deserialize_compact_parsing_error_message"
+        )
+    )]
     CompactParsingError(CompactParsingError),
 }
 
+impl TraceParsingErrorReason {
+    /// Returns a short code identifying the category of the failure, e.g. for
+    /// metrics or alerting. Unlike the message of the error, the codes are
+    /// stable across releases.
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::AccountDecode(..) => "account_decode",
+            Self::ReceiptDecode(_) => "receipt_decode",
+            Self::ReceiptsBloomMismatch { .. } => "receipts_bloom_mismatch",
+            Self::BlockWitnessDecode(_) => "block_witness_decode",
+            Self::RlpDecode(_) => "rlp_decode",
+            Self::MissingAccountStorageTrie(_) => "missing_storage_trie",
+            Self::StorageRootMismatch { .. } => "storage_root_mismatch",
+            Self::CodeHashMismatch { .. } => "code_hash_mismatch",
+            Self::MissingPreImageRoot(_) => "missing_pre_image_root",
+            Self::NonExistentTrieEntry(..) => "non_existent_trie_entry",
+            Self::MissingKeysCreatingSubPartialTrie(..) => "missing_keys_creating_sub_trie",
+            Self::MissingWithdrawalAccount(..) => "missing_withdrawal_account",
+            Self::WithdrawalBalanceOverflow { .. } => "withdrawal_balance_overflow",
+            Self::EmptyIrWithWithdrawals(_) => "empty_ir_with_withdrawals",
+            Self::GasOverflow(..) => "gas_overflow",
+            Self::GasUsedOutOfRange { .. } => "gas_used_out_of_range",
+            Self::TxnNumberOverflow(..) => "txn_number_overflow",
+            Self::ZeroGasTxn(_) => "zero_gas_txn",
+            Self::Cancelled { .. } => "cancelled",
+            Self::TxnNumberMismatch { .. } => "txn_number_mismatch",
+            Self::TrieRootMismatch { .. } => "trie_root_mismatch",
+            Self::FinalStateRootMismatch { .. } => "final_state_root_mismatch",
+            Self::WrongNumberOfPrevBlockHashes(_) => "wrong_number_of_prev_block_hashes",
+            Self::UnexpectedPrevBlockHash { .. } => "unexpected_prev_block_hash",
+            Self::MissingPrevBlockHash { .. } => "missing_prev_block_hash",
+            Self::TrieOpError(_) => "trie_op",
+            Self::InvalidIr(_) => "invalid_ir",
+            Self::CompactParsingError(_) => "compact_parsing",
+        }
+    }
+}
+
+/// An invariant violated by consecutive entries of the IR of a block (see
+/// [`validate_ir`]).
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum IrInvariantViolation {
+    /// A dummy entry uses some gas.
+    #[error(
+        "dummy entry {idx} uses gas (gas used before: {gas_used_before}, after: {gas_used_after})"
+    )]
+    DummyGasUsed {
+        /// The index of the entry in the IR.
+        idx: usize,
+        /// The gas used before the entry.
+        gas_used_before: U256,
+        /// The gas used after the entry.
+        gas_used_after: U256,
+    },
+
+    /// The gas used before an entry is not the one after the previous entry.
+    #[error("the gas used before entry {idx} is {got} instead of {expected}")]
+    GasUsedDiscontinuity {
+        /// The index of the entry in the IR.
+        idx: usize,
+        /// The gas used after the previous entry.
+        expected: U256,
+        /// The gas used before the entry.
+        got: U256,
+    },
+
+    /// The txn number before an entry is not the one after the previous entry.
+    #[error("the txn number before entry {idx} is {got} instead of {expected}")]
+    TxnNumberDiscontinuity {
+        /// The index of the entry in the IR.
+        idx: usize,
+        /// The txn number after the previous entry.
+        expected: U256,
+        /// The txn number before the entry.
+        got: U256,
+    },
+
+    /// An input trie of an entry does not have the root the previous entry
+    /// ended with.
+    #[error("the input {trie} trie of entry {idx} has root {got:x} instead of {expected:x}")]
+    TrieRootDiscontinuity {
+        /// The index of the entry in the IR.
+        idx: usize,
+        /// The type of the trie.
+        trie: TrieType,
+        /// The root of the trie after the previous entry.
+        expected: TrieRootHash,
+        /// The root of the input trie of the entry.
+        got: TrieRootHash,
+    },
+}
+
+fn display_violations(violations: &[IrInvariantViolation]) -> String {
+    violations
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
 impl From<TrieOpError> for TraceParsingError {
     fn from(err: TrieOpError) -> Self {
         // Convert TrieOpError into TraceParsingError
@@ -179,6 +561,12 @@ impl From<TrieOpError> for TraceParsingError {
     }
 }
 
+impl From<rlp::DecoderError> for TraceParsingError {
+    fn from(err: rlp::DecoderError) -> Self {
+        TraceParsingError::new(TraceParsingErrorReason::RlpDecode(err.to_string()))
+    }
+}
+
 impl From<CompactParsingError> for TraceParsingError {
     fn from(err: CompactParsingError) -> Self {
         // Convert CompactParsingError into TraceParsingError
@@ -187,7 +575,8 @@ impl From<CompactParsingError> for TraceParsingError {
 }
 
 /// An enum to cover all Ethereum trie types (see <https://ethereum.github.io/yellowpaper/paper.pdf> for details).
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub enum TrieType {
     /// State trie.
     State,
@@ -197,6 +586,8 @@ pub enum TrieType {
     Receipt,
     /// Transaction trie.
     Txn,
+    /// Contract code, keyed by code hash.
+    Code,
 }
 
 impl Display for TrieType {
@@ -206,7 +597,215 @@ impl Display for TrieType {
             TrieType::Storage => write!(f, "storage"),
             TrieType::Receipt => write!(f, "receipt"),
             TrieType::Txn => write!(f, "transaction"),
+            TrieType::Code => write!(f, "code"),
+        }
+    }
+}
+
+impl TrieType {
+    /// Returns a short code identifying the trie type, which is stable across
+    /// releases (see [`TraceParsingErrorReason::code`]).
+    pub const fn code(&self) -> &'static str {
+        match self {
+            TrieType::State => "state",
+            TrieType::Storage => "storage",
+            TrieType::Receipt => "receipt",
+            TrieType::Txn => "txn",
+            TrieType::Code => "code",
+        }
+    }
+}
+
+/// Specifies how many entries the generated IR must contain, padding it with
+/// dummy payloads if needed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IrPadding {
+    /// The minimum number of entries in the generated IR. Defaults to `2`, as
+    /// we need at least two entries to generate an aggregation proof. Values
+    /// below `1` are treated as `1`.
+    ///
+    /// With a minimum of `1`, a block without txns is decoded to a single
+    /// dummy entry, which carries the withdrawals of the block if any.
+    pub min_len: usize,
+    /// If greater than `1`, the number of entries is also padded up to the next
+    /// multiple of this value. Defaults to `1`.
+    pub multiple_of: usize,
+}
+
+impl Default for IrPadding {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl IrPadding {
+    /// The default padding, see [`IrPadding::min_len`] and
+    /// [`IrPadding::multiple_of`].
+    pub const DEFAULT: Self = Self {
+        min_len: 2,
+        multiple_of: 1,
+    };
+
+    /// Returns the length that an IR of `len` entries is padded to.
+    pub fn padded_len(&self, len: usize) -> usize {
+        len.max(self.min_len)
+            .max(1)
+            .next_multiple_of(self.multiple_of.max(1))
+    }
+
+    /// Returns the number of dummy entries added to the IR of a block with
+    /// `num_txns` txns.
+    ///
+    /// The dummy entries always come first: they are prepended to the txns, or
+    /// make up the whole IR of a block without txns. The withdrawals of the
+    /// block, if any, are carried by its last txn, or by the last dummy entry
+    /// in a block without txns, which is then not flagged as a dummy by
+    /// [`BlockTrace::into_txn_proof_gen_ir_with_dummy_flags`](crate::trace_protocol::BlockTrace::into_txn_proof_gen_ir_with_dummy_flags).
+    pub fn num_dummies(&self, num_txns: usize) -> usize {
+        self.padded_len(num_txns) - num_txns
+    }
+}
+
+/// The changes a txn made to the state and storage tries, passed to the
+/// observer set with [`ProcessingMeta::with_txn_state_diff_observer`] right
+/// after the deltas of the txn got applied.
+///
+/// Accounts are identified by their hashed address, as this is all the decoder
+/// knows about them.
+#[derive(Debug)]
+pub struct TxnStateDiff<'a> {
+    txn_idx: TxnIdx,
+    nodes_used_by_txn: &'a NodesUsedByTxn,
+    delta_application_out: &'a TrieDeltaApplicationOutput,
+    account_diffs: &'a [AccountRlpDiff],
+    trie_roots_after: &'a TrieRoots,
+}
+
+/// An account written by a txn, as it was right before and right after the
+/// txn.
+///
+/// An account created by the txn is empty before it.
+#[derive(Debug)]
+pub struct AccountRlpDiff {
+    /// The hashed address of the account.
+    pub h_addr: HashedAccountAddr,
+    /// The account before the txn.
+    pub before: AccountRlp,
+    /// The account after the txn.
+    pub after: AccountRlp,
+}
+
+impl<'a> TxnStateDiff<'a> {
+    /// The index of the txn in the block.
+    pub const fn txn_idx(&self) -> TxnIdx {
+        self.txn_idx
+    }
+
+    /// The accounts whose balance, nonce, code or storage trie were written.
+    pub fn changed_accounts(&self) -> impl Iterator<Item = &'a HashedAccountAddr> {
+        self.nodes_used_by_txn
+            .state_writes
+            .iter()
+            .map(|(h_addr, _)| h_addr)
+    }
+
+    /// The accounts of [`Self::changed_accounts`], as they were before and
+    /// after the txn, ordered by hashed address.
+    ///
+    /// For an account that self-destructed, `after` is the account right
+    /// before it got removed (see [`Self::self_destructed_accounts`]).
+    pub const fn account_diffs(&self) -> &'a [AccountRlpDiff] {
+        self.account_diffs
+    }
+
+    /// The storage slots written, along with their RLP encoded new value. An
+    /// RLP encoded zero means the slot got cleared.
+    pub fn storage_writes(
+        &self,
+    ) -> impl Iterator<
+        Item = (
+            &'a HashedAccountAddr,
+            &'a HashedStorageAddrNibbles,
+            &'a [u8],
+        ),
+    > {
+        self.nodes_used_by_txn
+            .storage_writes
+            .iter()
+            .flat_map(|(h_addr, writes)| {
+                writes
+                    .iter()
+                    .map(move |(slot, val)| (h_addr, slot, val.as_slice()))
+            })
+    }
+
+    /// The accounts that self-destructed, and were hence removed from the state
+    /// trie.
+    pub fn self_destructed_accounts(&self) -> &'a [HashedAccountAddr] {
+        &self.nodes_used_by_txn.self_destructed_accounts
+    }
+
+    /// The paths that applying the deltas of the txn flagged as not to be
+    /// hashed out of its minimal tries, because of collapsed branches.
+    pub const fn delta_application_output(&self) -> &'a TrieDeltaApplicationOutput {
+        self.delta_application_out
+    }
+
+    /// The roots of all tries after the txn.
+    pub const fn trie_roots_after(&self) -> &'a TrieRoots {
+        self.trie_roots_after
+    }
+}
+
+/// A shared callback observing the [TxnStateDiff] of every txn.
+#[derive(Clone)]
+pub(crate) struct TxnStateDiffObserver(Arc<dyn Fn(&TxnStateDiff) + Send + Sync>);
+
+impl TxnStateDiffObserver {
+    pub(crate) fn new(observer: impl Fn(&TxnStateDiff) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(observer))
+    }
+}
+
+impl fmt::Debug for TxnStateDiffObserver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("TxnStateDiffObserver")
+    }
+}
+
+/// A shared callback returning the RLP encoding of a trie node given its hash,
+/// set with [`ProcessingMeta::with_node_provider`].
+#[derive(Clone)]
+pub(crate) struct TrieNodeProvider(Arc<dyn Fn(H256) -> Option<Vec<u8>> + Send + Sync>);
+
+impl TrieNodeProvider {
+    pub(crate) fn new(provider: impl Fn(H256) -> Option<Vec<u8>> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(provider))
+    }
+
+    /// Fetches the node hashing to `hash_node`, with its children which are not
+    /// inlined left as `Hash` nodes.
+    ///
+    /// A node which does not hash to `hash`, or which is not a branch,
+    /// extension or leaf node, is ignored.
+    fn fetch(&self, hash_node: H256) -> Option<HashedPartialTrie> {
+        let bytes = (self.0)(hash_node)?;
+        if hash(&bytes) != hash_node || !matches!(Rlp::new(&bytes).item_count(), Ok(2 | 17)) {
+            warn!(
+                "Ignoring the invalid node provided for hash {:x}: {}",
+                hash_node,
+                hex::encode(&bytes)
+            );
+            return None;
         }
+
+        Some(PartialTrieBuilder::new(hash_node, HashMap::from([(hash_node, bytes)])).build())
+    }
+}
+
+impl fmt::Debug for TrieNodeProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("TrieNodeProvider")
     }
 }
 
@@ -220,122 +819,370 @@ struct PartialTrieState {
     receipt: HashedPartialTrie,
 }
 
+impl PartialTrieState {
+    /// Captures the tries as they are before applying the deltas of a txn, but
+    /// only with the parts needed later on to create its minimal sub-tries.
+    ///
+    /// The trie nodes are reference counted, so cloning a whole trie only
+    /// copies its root. What used to dominate is cloning the storage trie map,
+    /// which holds an entry for every account seen so far in the block, while
+    /// a txn only ever needs the storage tries of the accounts it accesses.
+    fn snapshot_for_txn(&self, nodes_used_by_txn: &NodesUsedByTxn) -> Self {
+        let storage = nodes_used_by_txn
+            .storage_accesses
+            .iter()
+            .filter_map(|(h_addr, _)| self.storage.get(h_addr).map(|trie| (*h_addr, trie.clone())))
+            .collect();
+
+        Self {
+            state: self.state.clone(),
+            storage,
+            txn: self.txn.clone(),
+            receipt: self.receipt.clone(),
+        }
+    }
+}
+
+/// How a delete affected the branch above the deleted node.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BranchCollapse {
+    /// No branch was left with a single child.
+    NoCollapse,
+    /// A branch was left with a single child and collapsed with it, into a
+    /// leaf if the child is a leaf, or into an extension otherwise.
+    /// `remaining_key` is the path to the resulting node, i.e. the full key of
+    /// the leaf or the path to the extension.
+    ///
+    /// Both cases are handled alike: the child gets merged with the branch,
+    /// so it must be unhashed in the minimal tries whatever its type.
+    Collapsed { remaining_key: Nibbles },
+}
+
+/// The state of a txn captured during the sequential delta application pass.
+/// Holds everything needed to create the minimal sub-tries of the txn, which
+/// can then be done independently of the other txns.
+#[derive(Debug)]
+struct TxnTrieSnapshot {
+    txn_idx: TxnIdx,
+    txn_info: ProcessedTxnInfo,
+    tries_at_start_of_txn: PartialTrieState,
+    delta_out: TrieDeltaApplicationOutput,
+    trie_roots_after: TrieRoots,
+    /// The accumulators as they were right after processing the txn.
+    extra_data: ExtraBlockData,
+}
+
 /// Additional information discovered during delta application.
 #[derive(Debug, Default)]
-struct TrieDeltaApplicationOutput {
+pub struct TrieDeltaApplicationOutput {
     // During delta application, if a delete occurs, we may have to make sure additional nodes
     // that are not accessed by the txn remain unhashed.
     additional_state_trie_paths_to_not_hash: Vec<Nibbles>,
     additional_storage_trie_paths_to_not_hash: HashMap<H256, Vec<Nibbles>>,
 }
 
+impl TrieDeltaApplicationOutput {
+    /// The paths of the state trie nodes left as the only child of a branch
+    /// collapsed by a delete, and which must hence not be hashed out of the
+    /// minimal state trie of the txn.
+    pub fn additional_state_trie_paths_to_not_hash(&self) -> &[Nibbles] {
+        &self.additional_state_trie_paths_to_not_hash
+    }
+
+    /// Same as [`Self::additional_state_trie_paths_to_not_hash`], for the
+    /// storage trie of each account.
+    pub const fn additional_storage_trie_paths_to_not_hash(
+        &self,
+    ) -> &HashMap<HashedAccountAddr, Vec<Nibbles>> {
+        &self.additional_storage_trie_paths_to_not_hash
+    }
+}
+
 impl ProcessedBlockTrace {
-    pub(crate) fn into_txn_proof_gen_ir(
+    /// Processes and returns the [GenerationInputs] for all transactions in the
+    /// block.
+    pub fn into_txn_proof_gen_ir<F>(
         self,
+        p_meta: &ProcessingMeta<F>,
         other_data: OtherBlockData,
-    ) -> TraceParsingResult<Vec<GenerationInputs>> {
-        let mut curr_block_tries = PartialTrieState {
-            state: self.tries.state.clone(),
-            storage: self.tries.storage.clone(),
-            ..Default::default()
-        };
+    ) -> TraceParsingResult<Vec<GenerationInputs>>
+    where
+        F: CodeHashResolveFunc,
+    {
+        self.into_txn_proof_gen_ir_and_final_tries(p_meta, other_data)
+            .map(|(txn_gen_inputs, _, _)| txn_gen_inputs)
+    }
 
-        // This is just a copy of `curr_block_tries`.
-        let initial_tries_for_dummies = PartialTrieState {
-            state: self.tries.state,
-            storage: self.tries.storage,
-            ..Default::default()
-        };
+    /// Like [`ProcessedBlockTrace::into_txn_proof_gen_ir`], but also returns
+    /// whether each [GenerationInputs] is a dummy entry padding the IR (see
+    /// [IrPadding]), in the same order.
+    pub fn into_txn_proof_gen_ir_with_dummy_flags<F>(
+        self,
+        p_meta: &ProcessingMeta<F>,
+        other_data: OtherBlockData,
+    ) -> TraceParsingResult<(Vec<GenerationInputs>, Vec<bool>)>
+    where
+        F: CodeHashResolveFunc,
+    {
+        self.into_txn_proof_gen_ir_and_final_tries(p_meta, other_data)
+            .map(|(txn_gen_inputs, is_dummy, _)| (txn_gen_inputs, is_dummy))
+    }
 
-        let mut extra_data = ExtraBlockData {
-            checkpoint_state_trie_root: other_data.checkpoint_state_trie_root,
-            txn_number_before: U256::zero(),
-            txn_number_after: U256::zero(),
-            gas_used_before: U256::zero(),
-            gas_used_after: U256::zero(),
-        };
+    /// Processes the [GenerationInputs] of a sequence of consecutive blocks,
+    /// returning them block by block.
+    ///
+    /// The tries mutated by a block are carried forward as the starting tries
+    /// of the next one, so only the minimal sub-tries of each txn need to be
+    /// derived again. The pre-images of each block must start from the state
+    /// root the previous block ended on, and are only used to fill in the
+    /// nodes which were hashed out of the carried tries.
+    pub fn process_block_range<F>(
+        blocks: impl IntoIterator<Item = (ProcessedBlockTrace, OtherBlockData)>,
+        p_meta: &ProcessingMeta<F>,
+    ) -> TraceParsingResult<Vec<Vec<GenerationInputs>>>
+    where
+        F: CodeHashResolveFunc,
+    {
+        let mut carried_tries: Option<PartialTrieState> = None;
+
+        blocks
+            .into_iter()
+            .map(|(mut block, other_data)| {
+                if let Some(prev_final_tries) = carried_tries.take() {
+                    block.tries =
+                        carry_tries_forward(prev_final_tries, block.tries).map_err(|mut e| {
+                            e.set_block_num(other_data.b_data.b_meta.block_number);
+                            e.set_block_chain_id(other_data.b_data.b_meta.block_chain_id);
+                            e
+                        })?;
+                }
+
+                let (txn_gen_inputs, _, final_tries) =
+                    block.into_txn_proof_gen_ir_and_final_tries(p_meta, other_data)?;
+                carried_tries = Some(final_tries);
+
+                Ok(txn_gen_inputs)
+            })
+            .collect()
+    }
+
+    fn into_txn_proof_gen_ir_and_final_tries<F>(
+        self,
+        p_meta: &ProcessingMeta<F>,
+        other_data: OtherBlockData,
+    ) -> TraceParsingResult<(Vec<GenerationInputs>, Vec<bool>, PartialTrieState)>
+    where
+        F: CodeHashResolveFunc,
+    {
+        let BlockDecodingInit {
+            mut curr_block_tries,
+            initial_tries_for_dummies,
+            mut extra_data,
+            fee_recipients,
+        } = Self::init_block_decoding(self.tries, &self.txn_info, p_meta, &other_data)?;
 
         // A copy of the initial extra_data possibly needed during padding.
         let extra_data_for_dummies = extra_data.clone();
 
-        let mut txn_gen_inputs = self
+        let num_non_dummy_txns = count_non_dummy_txns(&self.txn_info);
+
+        // Delta application mutates the tries txn after txn, so this first pass
+        // must be sequential. It captures everything needed to build the minimal
+        // sub-tries of each txn afterwards.
+        let txn_snapshots = self
             .txn_info
             .into_iter()
             .enumerate()
             .map(|(txn_idx, txn_info)| {
+                let expected_trie_roots = p_meta
+                    .options
+                    .expected_txn_trie_roots
+                    .as_ref()
+                    .and_then(|roots| roots.get(txn_idx));
+
+                check_not_cancelled(p_meta.options.cancellation_flag.as_deref(), txn_idx)?;
+
                 Self::process_txn_info(
                     txn_idx,
                     txn_info,
                     &mut curr_block_tries,
                     &mut extra_data,
-                    &other_data,
+                    expected_trie_roots,
+                    p_meta.options.build_receipt_trie,
+                    p_meta.options.reject_zero_gas_txns,
+                    p_meta.options.txn_state_diff_observer.as_ref(),
+                    p_meta.options.key_hash_cache.as_deref(),
                 )
                 .map_err(|mut e| {
-                    e.txn_idx(txn_idx);
+                    e.set_txn_idx(txn_idx);
                     e
                 })
             })
-            .collect::<TraceParsingResult<Vec<_>>>()
+            .collect::<TraceParsingResult<Vec<_>>>();
+
+        let node_provider = p_meta.options.node_provider.as_ref();
+
+        let mut txn_gen_inputs = txn_snapshots
+            .and_then(|snapshots| {
+                Self::create_gen_inputs_from_txn_snapshots(
+                    snapshots,
+                    &other_data,
+                    &fee_recipients,
+                    node_provider,
+                )
+            })
             .map_err(|mut e| {
-                e.block_num(other_data.b_data.b_meta.block_number);
-                e.block_chain_id(other_data.b_data.b_meta.block_chain_id);
+                e.set_block_num(other_data.b_data.b_meta.block_number);
+                e.set_block_chain_id(other_data.b_data.b_meta.block_chain_id);
                 e
             })?;
 
-        Self::pad_gen_inputs_with_dummy_inputs_if_needed(
+        verify_txn_number_after(&extra_data, num_non_dummy_txns, &other_data)?;
+
+        let is_dummy = Self::finish_block_ir(
             &mut txn_gen_inputs,
+            &mut curr_block_tries,
             &other_data,
             &extra_data,
             &extra_data_for_dummies,
             &initial_tries_for_dummies,
-            &curr_block_tries,
-        );
+            self.withdrawals,
+            p_meta,
+        )?;
 
-        if !self.withdrawals.is_empty() {
-            Self::add_withdrawals_to_txns(
-                &mut txn_gen_inputs,
-                &mut curr_block_tries,
-                self.withdrawals,
-            )?;
+        validate_ir(&txn_gen_inputs).map_err(|mut e| {
+            e.set_block_num(other_data.b_data.b_meta.block_number);
+            e.set_block_chain_id(other_data.b_data.b_meta.block_chain_id);
+            e
+        })?;
+
+        Ok((txn_gen_inputs, is_dummy, curr_block_tries))
+    }
+
+    /// Same as [`ProcessedBlockTrace::into_txn_proof_gen_ir`], but returns an
+    /// iterator which only decodes a txn when its [GenerationInputs] are
+    /// requested.
+    pub fn into_txn_proof_gen_ir_stream<F>(
+        self,
+        p_meta: &ProcessingMeta<F>,
+        other_data: OtherBlockData,
+    ) -> TraceParsingResult<TxnProofGenIrStream>
+    where
+        F: CodeHashResolveFunc,
+    {
+        let BlockDecodingInit {
+            mut curr_block_tries,
+            initial_tries_for_dummies,
+            extra_data,
+            fee_recipients,
+        } = Self::init_block_decoding(self.tries, &self.txn_info, p_meta, &other_data)?;
+
+        let num_txns = self.txn_info.len();
+        let mut pending = VecDeque::new();
+        let mut num_dummies_left = 0;
+        let mut withdrawals = self.withdrawals;
+
+        match num_txns {
+            0 => {
+                // There is nothing to decode lazily, so the dummy entries (and the
+                // withdrawals they may hold) are all generated upfront.
+                let mut gen_inputs = Vec::new();
+                Self::finish_block_ir(
+                    &mut gen_inputs,
+                    &mut curr_block_tries,
+                    &other_data,
+                    &extra_data,
+                    &extra_data,
+                    &initial_tries_for_dummies,
+                    mem::take(&mut withdrawals),
+                    p_meta,
+                )?;
+
+                pending.extend(gen_inputs);
+            }
+            _ => num_dummies_left = p_meta.options.ir_padding.num_dummies(num_txns),
         }
 
-        Ok(txn_gen_inputs)
+        Ok(TxnProofGenIrStream {
+            num_non_dummy_txns: count_non_dummy_txns(&self.txn_info),
+            txn_info: self.txn_info.into_iter().enumerate(),
+            num_txns,
+            num_dummies_left,
+            pending,
+            curr_block_tries,
+            initial_tries_for_dummies,
+            extra_data_for_dummies: extra_data.clone(),
+            extra_data,
+            other_data,
+            withdrawals,
+            options: p_meta.options.clone(),
+            fee_recipients,
+            num_yielded: 0,
+            prev_entry_end: None,
+        })
     }
 
+    /// When `build_receipt_trie` is false, the receipt trie is left untouched
+    /// and hence remains empty for the whole block.
     fn update_txn_and_receipt_tries(
         trie_state: &mut PartialTrieState,
         meta: &TxnMetaState,
         txn_idx: TxnIdx,
+        build_receipt_trie: bool,
     ) -> TrieOpResult<()> {
         let txn_k = Nibbles::from_bytes_be(&rlp::encode(&txn_idx)).unwrap();
-        trie_state.txn.insert(txn_k, meta.txn_bytes())?;
-
         trie_state
-            .receipt
-            .insert(txn_k, meta.receipt_node_bytes.as_ref())
+            .txn
+            .insert(txn_k, meta.txn_bytes().unwrap_or_default())?;
+
+        if build_receipt_trie {
+            trie_state
+                .receipt
+                .insert(txn_k, meta.receipt_node_bytes())?;
+        }
+
+        Ok(())
     }
 
-    /// If the account does not have a storage trie or does but is not
-    /// accessed by any txns, then we still need to manually create an entry for
-    /// them.
+    /// Creates the storage tries of the accounts in `accounts_with_storage`
+    /// missing from `storage_tries`.
+    ///
+    /// The pre-images only need the storage tries of the accounts whose
+    /// storage is accessed by a txn of the block. A missing storage trie is
+    /// hashed out to the storage root of the account in `state`, so that it
+    /// still hashes to the right root, or left empty if the account has no
+    /// storage yet. Note that accessing a slot of a hashed out storage trie
+    /// fails unless the missing nodes can be fetched from a node provider.
     fn init_any_needed_empty_storage_tries<'a>(
         storage_tries: &mut HashMap<HashedAccountAddr, HashedPartialTrie>,
+        state: &HashedPartialTrie,
         accounts_with_storage: impl Iterator<Item = &'a HashedStorageAddr>,
-        state_accounts_with_no_accesses_but_storage_tries: &'a HashMap<
-            HashedAccountAddr,
-            TrieRootHash,
-        >,
-    ) {
+    ) -> TraceParsingResult<()> {
         for h_addr in accounts_with_storage {
-            if !storage_tries.contains_key(h_addr) {
-                let trie = state_accounts_with_no_accesses_but_storage_tries
-                    .get(h_addr)
-                    .map(|s_root| HashedPartialTrie::new(Node::Hash(*s_root)))
-                    .unwrap_or_default();
+            if storage_tries.contains_key(h_addr) {
+                continue;
+            }
 
-                storage_tries.insert(*h_addr, trie);
+            let storage_root = match state.get(Nibbles::from_h256_be(*h_addr)) {
+                Some(acc_bytes) => {
+                    account_from_rlped_bytes(acc_bytes)
+                        .map_err(|mut e| {
+                            e.set_h_addr(*h_addr);
+                            e
+                        })?
+                        .storage_root
+                }
+                None => EMPTY_TRIE_HASH,
             };
+            let trie = match storage_root == EMPTY_TRIE_HASH {
+                false => HashedPartialTrie::new(Node::Hash(storage_root)),
+                true => HashedPartialTrie::default(),
+            };
+
+            storage_tries.insert(*h_addr, trie);
         }
+
+        Ok(())
     }
 
     fn create_minimal_partial_tries_needed_by_txn(
@@ -343,28 +1190,43 @@ impl ProcessedBlockTrace {
         nodes_used_by_txn: &NodesUsedByTxn,
         txn_idx: TxnIdx,
         delta_application_out: TrieDeltaApplicationOutput,
-        _coin_base_addr: &Address,
+        fee_recipients: &[HashedAccountAddr],
+        node_provider: Option<&TrieNodeProvider>,
     ) -> TraceParsingResult<TrieInputs> {
         let state_trie = create_minimal_state_partial_trie(
             &curr_block_tries.state,
-            nodes_used_by_txn.state_accesses.iter().cloned(),
+            nodes_used_by_txn
+                .state_accesses
+                .iter()
+                .chain(fee_recipients)
+                .cloned(),
             delta_application_out
                 .additional_state_trie_paths_to_not_hash
                 .into_iter(),
+            node_provider,
         )?;
 
         let txn_k = Nibbles::from_bytes_be(&rlp::encode(&txn_idx)).unwrap();
 
-        let transactions_trie =
-            create_trie_subset_wrapped(&curr_block_tries.txn, once(txn_k), TrieType::Txn)?;
+        let transactions_trie = create_trie_subset_wrapped(
+            &curr_block_tries.txn,
+            once(txn_k),
+            TrieType::Txn,
+            node_provider,
+        )?;
 
-        let receipts_trie =
-            create_trie_subset_wrapped(&curr_block_tries.receipt, once(txn_k), TrieType::Receipt)?;
+        let receipts_trie = create_trie_subset_wrapped(
+            &curr_block_tries.receipt,
+            once(txn_k),
+            TrieType::Receipt,
+            node_provider,
+        )?;
 
         let storage_tries = create_minimal_storage_partial_tries(
             &curr_block_tries.storage,
             nodes_used_by_txn.storage_accesses.iter(),
             &delta_application_out.additional_storage_trie_paths_to_not_hash,
+            node_provider,
         )?;
 
         Ok(TrieInputs {
@@ -375,55 +1237,110 @@ impl ProcessedBlockTrace {
         })
     }
 
+    /// If `account_diffs` is set, the account of every state write gets pushed
+    /// to it, as it was before and after the write. If `key_hash_cache` is set,
+    /// the keys of the written slots are looked up in it.
     fn apply_deltas_to_trie_state(
         trie_state: &mut PartialTrieState,
         deltas: &NodesUsedByTxn,
+        mut account_diffs: Option<&mut Vec<AccountRlpDiff>>,
+        key_hash_cache: Option<&Mutex<KeyHashCache>>,
     ) -> TraceParsingResult<TrieDeltaApplicationOutput> {
         let mut out = TrieDeltaApplicationOutput::default();
 
-        for (hashed_acc_addr, storage_writes) in deltas.storage_writes.iter() {
-            let storage_trie = trie_state.storage.get_mut(hashed_acc_addr).ok_or_else(|| {
-                let hashed_acc_addr = *hashed_acc_addr;
-                let mut e = TraceParsingError::new(
-                    TraceParsingErrorReason::MissingAccountStorageTrie(hashed_acc_addr),
-                );
-                e.h_addr(hashed_acc_addr);
-                e
-            })?;
+        // The deltas originate from hash maps, so we sort them to apply them in a
+        // deterministic order. While the resulting roots do not depend on it, which
+        // deletes collapse branches (and therefore the additional paths to not hash)
+        // does.
+        //
+        // Repeated writes to the same slot are coalesced so that only the final value
+        // is applied. Otherwise, an intermediate delete could collapse a branch (and
+        // add a path to not hash) even though the slot ends up non-empty.
+        let mut storage_writes: BTreeMap<_, BTreeMap<_, _>> = BTreeMap::new();
+        for (hashed_acc_addr, writes) in deltas.storage_writes.iter() {
+            storage_writes.entry(hashed_acc_addr).or_default().extend(
+                writes
+                    .iter()
+                    .map(|(k, v)| (storage_key(key_hash_cache, k), v)),
+            );
+        }
 
-            for (slot, val) in storage_writes
-                .iter()
-                .map(|(k, v)| (Nibbles::from_h256_be(hash(&k.bytes_be())), v))
-            {
-                // If we are writing a zero, then we actually need to perform a delete.
-                match val == &ZERO_STORAGE_SLOT_VAL_RLPED {
-                    false => storage_trie.insert(slot, val.clone()).map_err(|err| {
-                        let mut e =
-                            TraceParsingError::new(TraceParsingErrorReason::TrieOpError(err));
-                        e.slot(U512::from_big_endian(slot.bytes_be().as_slice()));
-                        e.slot_value(U512::from_big_endian(val.as_slice()));
-                        e
-                    })?,
-                    true => {
-                        if let Some(remaining_slot_key) =
-                            Self::delete_node_and_report_remaining_key_if_branch_collapsed(
-                                storage_trie,
-                                &slot,
-                            )
-                            .map_err(TraceParsingError::from)?
-                        {
-                            out.additional_storage_trie_paths_to_not_hash
-                                .entry(*hashed_acc_addr)
-                                .or_default()
-                                .push(remaining_slot_key);
-                        }
-                    }
-                };
+        // With EIP-6780, only the accounts created in this txn get deleted. As
+        // only a contract can self-destruct, an account which had no code before the
+        // txn must have been created by it.
+        //
+        // The other ones are kept, but their balance is swept to the beneficiary,
+        // unless the trace wrote their final balance: the contract may be its own
+        // beneficiary, or receive funds after self-destructing.
+        let mut self_destructed_accounts = Vec::new();
+        let mut swept_accounts = Vec::new();
+        for hashed_acc_addr in deltas.self_destructed_accounts.iter() {
+            if !deltas.eip_6780 || !Self::account_has_code(&trie_state.state, hashed_acc_addr)? {
+                self_destructed_accounts.push(hashed_acc_addr);
+            } else if !deltas.state_writes.iter().any(|(written_addr, writes)| {
+                written_addr == hashed_acc_addr && writes.balance.is_some()
+            }) {
+                swept_accounts.push(*hashed_acc_addr);
             }
         }
+        self_destructed_accounts.sort_unstable();
+
+        // A swept account the trace didn't write to still needs its balance updated.
+        let sweep_writes: Vec<_> = swept_accounts
+            .iter()
+            .filter(|swept_addr| {
+                !deltas
+                    .state_writes
+                    .iter()
+                    .any(|(written_addr, _)| written_addr == *swept_addr)
+            })
+            .map(|swept_addr| (*swept_addr, StateTrieWrites::default()))
+            .collect();
+
+        let mut state_writes: Vec<_> = deltas.state_writes.iter().chain(&sweep_writes).collect();
+        state_writes.sort_unstable_by_key(|(hashed_acc_addr, _)| *hashed_acc_addr);
+
+        if let Some(hashed_acc_addr) = storage_writes
+            .keys()
+            .find(|hashed_acc_addr| !trie_state.storage.contains_key(*hashed_acc_addr))
+        {
+            let hashed_acc_addr = **hashed_acc_addr;
+            let mut e = TraceParsingError::new(TraceParsingErrorReason::MissingAccountStorageTrie(
+                hashed_acc_addr,
+            ));
+            e.set_h_addr(hashed_acc_addr);
+            return Err(Box::new(e));
+        }
+
+        // The writes of each account only touch its own storage trie, so they can be
+        // applied to all accounts in parallel. They must however all be applied
+        // before the state writes below, which read the new storage roots.
+        let storage_tries_to_update: Vec<_> = trie_state
+            .storage
+            .iter_mut()
+            .filter_map(|(hashed_acc_addr, storage_trie)| {
+                storage_writes
+                    .remove(&hashed_acc_addr)
+                    .map(|slot_writes| (hashed_acc_addr, storage_trie, slot_writes))
+            })
+            .collect();
+
+        let storage_trie_paths_to_not_hash = storage_tries_to_update
+            .into_par_iter()
+            .map(|(hashed_acc_addr, storage_trie, slot_writes)| {
+                Self::apply_slot_writes_to_storage_trie(storage_trie, slot_writes)
+                    .map(|paths| (*hashed_acc_addr, paths))
+            })
+            .collect::<TraceParsingResult<Vec<_>>>()?;
+
+        out.additional_storage_trie_paths_to_not_hash.extend(
+            storage_trie_paths_to_not_hash
+                .into_iter()
+                .filter(|(_, paths)| !paths.is_empty()),
+        );
 
-        for (hashed_acc_addr, s_trie_writes) in deltas.state_writes.iter() {
-            let val_k = Nibbles::from_h256_be(*hashed_acc_addr);
+        for (hashed_acc_addr, s_trie_writes) in state_writes {
+            let val_k = AccountNibbles::from_hashed_addr(*hashed_acc_addr).into_inner();
 
             // If the account was created, then it will not exist in the trie.
             let val_bytes = trie_state
@@ -438,8 +1355,19 @@ impl ProcessedBlockTrace {
                 hashed_acc_addr,
                 &trie_state.storage,
             )?;
+            if swept_accounts.contains(hashed_acc_addr) {
+                account.balance = U256::zero();
+            }
 
             let updated_account_bytes = rlp::encode(&account);
+            if let Some(account_diffs) = account_diffs.as_deref_mut() {
+                account_diffs.push(AccountRlpDiff {
+                    h_addr: *hashed_acc_addr,
+                    before: account_from_rlped_bytes(val_bytes)?,
+                    after: account,
+                });
+            }
+
             trie_state
                 .state
                 .insert(val_k, updated_account_bytes.to_vec())
@@ -447,47 +1375,95 @@ impl ProcessedBlockTrace {
         }
 
         // Remove any accounts that self-destructed.
-        for hashed_addr in deltas.self_destructed_accounts.iter() {
-            let k = Nibbles::from_h256_be(*hashed_addr);
+        for hashed_addr in self_destructed_accounts {
+            let k = AccountNibbles::from_hashed_addr(*hashed_addr);
 
-            trie_state.storage.remove(hashed_addr).ok_or_else(|| {
-                let hashed_addr = *hashed_addr;
-                let mut e = TraceParsingError::new(
-                    TraceParsingErrorReason::MissingAccountStorageTrie(hashed_addr),
-                );
-                e.h_addr(hashed_addr);
-                e
-            })?;
+            // An account that never wrote to its storage may legitimately have no
+            // storage trie, in which case there is nothing to remove.
+            trie_state.storage.remove(hashed_addr);
 
-            // TODO: Once the mechanism for resolving code hashes settles, we probably want
-            // to also delete the code hash mapping here as well...
+            // Note that the code hash mapping of the account is dropped when processing
+            // the block trace (see `CodeHashResolving::remove_code_of_destroyed_account`).
 
-            if let Some(remaining_account_key) =
-                Self::delete_node_and_report_remaining_key_if_branch_collapsed(
-                    &mut trie_state.state,
-                    &k,
-                )
-                .map_err(TraceParsingError::from)?
+            match Self::delete_node_and_report_remaining_key_if_branch_collapsed(
+                &mut trie_state.state,
+                &k.into_inner(),
+            )
+            .map_err(TraceParsingError::from)?
             {
-                out.additional_state_trie_paths_to_not_hash
-                    .push(remaining_account_key);
+                BranchCollapse::NoCollapse => (),
+                BranchCollapse::Collapsed { remaining_key } => out
+                    .additional_state_trie_paths_to_not_hash
+                    .push(remaining_key),
             }
         }
 
         Ok(out)
     }
 
+    /// Returns whether the account exists in `state_trie` with some code.
+    fn account_has_code(
+        state_trie: &HashedPartialTrie,
+        hashed_acc_addr: &HashedAccountAddr,
+    ) -> TraceParsingResult<bool> {
+        let k = AccountNibbles::from_hashed_addr(*hashed_acc_addr).into_inner();
+
+        match state_trie.get(k) {
+            Some(bytes) => Ok(account_from_rlped_bytes(bytes)?.code_hash != EMPTY_CODE_HASH),
+            None => Ok(false),
+        }
+    }
+
+    /// Applies the writes to the slots of a single storage trie, in order, and
+    /// returns the paths to not hash resulting from the deletes that collapsed
+    /// a branch.
+    fn apply_slot_writes_to_storage_trie(
+        storage_trie: &mut HashedPartialTrie,
+        slot_writes: BTreeMap<StorageNibbles, &Vec<u8>>,
+    ) -> TraceParsingResult<Vec<Nibbles>> {
+        let mut paths_to_not_hash = Vec::new();
+
+        for (slot, val) in slot_writes {
+            let slot = slot.into_inner();
+
+            // If we are writing a zero, then we actually need to perform a delete.
+            match val == &ZERO_STORAGE_SLOT_VAL_RLPED {
+                false => storage_trie.insert(slot, val.clone()).map_err(|err| {
+                    let mut e = TraceParsingError::new(TraceParsingErrorReason::TrieOpError(err));
+                    e.set_slot(U512::from_big_endian(slot.bytes_be().as_slice()));
+                    e.set_slot_value(U512::from_big_endian(val.as_slice()));
+                    e
+                })?,
+                true => {
+                    match Self::delete_node_and_report_remaining_key_if_branch_collapsed(
+                        storage_trie,
+                        &slot,
+                    )
+                    .map_err(TraceParsingError::from)?
+                    {
+                        BranchCollapse::NoCollapse => (),
+                        BranchCollapse::Collapsed { remaining_key } => {
+                            paths_to_not_hash.push(remaining_key)
+                        }
+                    }
+                }
+            };
+        }
+
+        Ok(paths_to_not_hash)
+    }
+
     fn get_trie_trace(trie: &HashedPartialTrie, k: &Nibbles) -> TriePath {
         path_for_query(trie, *k, true).collect()
     }
 
     /// If a branch collapse occurred after a delete, then we must ensure that
     /// the other single child that remains also is not hashed when passed into
-    /// plonky2. Returns the key to the remaining child if a collapse occurred.
+    /// plonky2. Reports the key to the remaining child if a collapse occurred.
     fn delete_node_and_report_remaining_key_if_branch_collapsed(
         trie: &mut HashedPartialTrie,
         delete_k: &Nibbles,
-    ) -> TrieOpResult<Option<Nibbles>> {
+    ) -> TrieOpResult<BranchCollapse> {
         let old_trace = Self::get_trie_trace(trie, delete_k);
         trie.delete(*delete_k)?;
         let new_trace = Self::get_trie_trace(trie, delete_k);
@@ -499,15 +1475,15 @@ impl ProcessedBlockTrace {
 
     /// Comparing the path of the deleted key before and after the deletion,
     /// determine if the deletion resulted in a branch collapsing into a leaf or
-    /// extension node, and return the path to the remaining child if this
+    /// extension node, along with the path to the remaining child if this
     /// occurred.
     fn node_deletion_resulted_in_a_branch_collapse(
         old_path: &TriePath,
         new_path: &TriePath,
-    ) -> Option<Nibbles> {
+    ) -> BranchCollapse {
         // Collapse requires at least 2 nodes.
         if old_path.0.len() < 2 {
-            return None;
+            return BranchCollapse::NoCollapse;
         }
 
         // If the node path length decreased after the delete, then a collapse occurred.
@@ -515,20 +1491,117 @@ impl ProcessedBlockTrace {
         // into an extension node with multiple nodes below it, the query logic will
         // always stop at most one node after the keys diverge, which guarantees that
         // the new trie path will always be shorter if a collapse occurred.
-        let branch_collapse_occurred = old_path.0.len() > new_path.0.len();
+        if old_path.0.len() <= new_path.0.len() {
+            return BranchCollapse::NoCollapse;
+        }
 
         // Now we need to determine the key of the only remaining node after the
-        // collapse.
-        branch_collapse_occurred.then(|| new_path.iter().into_key())
+        // collapse. The query includes the final node, so if the remaining child
+        // got merged into a leaf, this is the full key of that leaf.
+        BranchCollapse::Collapsed {
+            remaining_key: new_path.iter().into_key(),
+        }
+    }
+
+    /// Sets up the decoding of a block, shared by the eager and the streamed
+    /// decoding: checks the block data, and derives the tries and extra data
+    /// the block starts from.
+    fn init_block_decoding<F>(
+        tries: PartialTriePreImages,
+        txn_info: &[ProcessedTxnInfo],
+        p_meta: &ProcessingMeta<F>,
+        other_data: &OtherBlockData,
+    ) -> TraceParsingResult<BlockDecodingInit>
+    where
+        F: CodeHashResolveFunc,
+    {
+        verify_block_hashes(other_data)?;
+        if p_meta.options.validate_receipts {
+            verify_receipts_bloom(txn_info, other_data)?;
+        }
+
+        let curr_block_tries = PartialTrieState {
+            state: tries.state,
+            storage: tries.storage,
+            ..Default::default()
+        };
+
+        Ok(BlockDecodingInit {
+            // This is just a copy of `curr_block_tries`.
+            initial_tries_for_dummies: curr_block_tries.clone(),
+            curr_block_tries,
+            extra_data: ExtraBlockData {
+                checkpoint_state_trie_root: other_data.checkpoint_state_trie_root,
+                txn_number_before: U256::zero(),
+                txn_number_after: U256::zero(),
+                gas_used_before: U256::zero(),
+                gas_used_after: U256::zero(),
+            },
+            fee_recipients: fee_recipient_hashes(p_meta, other_data),
+        })
+    }
+
+    /// Completes the IR of a block once all of its txns got decoded: pads it
+    /// with dummy entries, adds the withdrawals to its last entry and checks
+    /// the state root the block ends with.
+    ///
+    /// Returns whether each entry of the IR is a dummy one.
+    #[allow(clippy::too_many_arguments)]
+    fn finish_block_ir<F>(
+        gen_inputs: &mut Vec<GenerationInputs>,
+        curr_block_tries: &mut PartialTrieState,
+        other_data: &OtherBlockData,
+        final_extra_data: &ExtraBlockData,
+        initial_extra_data: &ExtraBlockData,
+        initial_tries: &PartialTrieState,
+        withdrawals: Vec<(Address, U256)>,
+        p_meta: &ProcessingMeta<F>,
+    ) -> TraceParsingResult<Vec<bool>>
+    where
+        F: CodeHashResolveFunc,
+    {
+        let is_dummy = Self::pad_gen_inputs_with_dummy_inputs_if_needed(
+            gen_inputs,
+            other_data,
+            final_extra_data,
+            initial_extra_data,
+            initial_tries,
+            curr_block_tries,
+            &withdrawals,
+            p_meta.options.ir_padding,
+        )?;
+
+        if !withdrawals.is_empty() {
+            Self::add_withdrawals_to_txns(
+                gen_inputs,
+                curr_block_tries,
+                withdrawals,
+                p_meta.options.create_missing_withdrawal_accounts,
+            )?;
+        }
+
+        verify_final_state_root(other_data, curr_block_tries)?;
+
+        Ok(is_dummy)
     }
 
     /// Pads a generated IR vec with additional "dummy" entries if needed.
-    /// We need to ensure that generated IR always has at least `2` elements,
-    /// and if there are only `0` or `1` elements, then we need to pad so
-    /// that we have two entries in total. These dummy entries serve only to
+    /// By default, we need to ensure that generated IR always has at least `2`
+    /// elements, and if there are only `0` or `1` elements, then we need to pad
+    /// so that we have two entries in total. These dummy entries serve only to
     /// allow the proof generation process to finish. Specifically, we need
     /// at least two entries to generate an agg proof, and we need an agg
     /// proof to generate a block proof. These entries do not mutate state.
+    ///
+    /// The number of entries to pad to can be changed through [IrPadding].
+    ///
+    /// If the block has no txns but has withdrawals, the final dummy entry
+    /// will carry the withdrawals, so its state trie also contains the
+    /// withdrawal accounts.
+    ///
+    /// Returns whether each entry of the padded IR is a dummy one. The entry
+    /// carrying the withdrawals is not, as it differs from the other dummies.
+    #[allow(clippy::too_many_arguments)]
     fn pad_gen_inputs_with_dummy_inputs_if_needed(
         gen_inputs: &mut Vec<GenerationInputs>,
         other_data: &OtherBlockData,
@@ -536,34 +1609,75 @@ impl ProcessedBlockTrace {
         initial_extra_data: &ExtraBlockData,
         initial_tries: &PartialTrieState,
         final_tries: &PartialTrieState,
-    ) {
-        match gen_inputs.len() {
+        withdrawals: &[(Address, U256)],
+        ir_padding: IrPadding,
+    ) -> TraceParsingResult<Vec<bool>> {
+        let num_dummies = ir_padding.num_dummies(gen_inputs.len());
+
+        let num_flagged_dummies = match gen_inputs.len() {
             0 => {
                 debug_assert!(initial_tries.state == final_tries.state);
                 debug_assert!(initial_extra_data == final_extra_data);
-                // We need to pad with two dummy entries.
-                gen_inputs.extend(create_dummy_txn_pair_for_empty_block(
+                // We need to pad with dummy entries only.
+                if withdrawals.is_empty() {
+                    gen_inputs.extend(create_dummy_gen_inputs(
+                        other_data,
+                        final_extra_data,
+                        initial_tries,
+                        num_dummies,
+                    ));
+
+                    num_dummies
+                } else {
+                    // The last dummy will hold the withdrawals, so it needs to
+                    // access the withdrawal accounts.
+                    gen_inputs.extend(create_dummy_gen_inputs(
+                        other_data,
+                        final_extra_data,
+                        initial_tries,
+                        num_dummies.saturating_sub(1),
+                    ));
+
+                    let withdrawal_addrs =
+                        withdrawals.iter().map(|(addr, _)| hash(addr.as_bytes()));
+                    gen_inputs.push(create_dummy_gen_input_with_state_addrs_accessed(
+                        other_data,
+                        final_extra_data,
+                        initial_tries,
+                        withdrawal_addrs,
+                    )?);
+
+                    num_dummies.saturating_sub(1)
+                }
+            }
+            _ => {
+                // The dummy proofs will be prepended to the actual txns.
+                let mut padded_gen_inputs = create_dummy_gen_inputs(
                     other_data,
-                    final_extra_data,
+                    initial_extra_data,
                     initial_tries,
-                ));
-            }
-            1 => {
-                // We just need one dummy entry.
-                // The dummy proof will be prepended to the actual txn.
-                let dummy_txn =
-                    create_dummy_gen_input(other_data, initial_extra_data, initial_tries);
-                gen_inputs.insert(0, dummy_txn)
+                    num_dummies,
+                );
+                padded_gen_inputs.append(gen_inputs);
+                *gen_inputs = padded_gen_inputs;
+
+                num_dummies
             }
-            _ => (),
-        }
+        };
+
+        Ok((0..gen_inputs.len())
+            .map(|idx| idx < num_flagged_dummies)
+            .collect())
     }
 
-    /// The withdrawals are always in the final ir payload.
+    /// The withdrawals are always in the final ir payload. Its state trie must
+    /// already contain the withdrawal accounts (see
+    /// `[pad_gen_inputs_with_dummy_inputs_if_needed]`).
     fn add_withdrawals_to_txns(
         txn_ir: &mut [GenerationInputs],
         final_trie_state: &mut PartialTrieState,
         withdrawals: Vec<(Address, U256)>,
+        create_missing_accounts: bool,
     ) -> TraceParsingResult<()> {
         let withdrawals_with_hashed_addrs_iter = || {
             withdrawals
@@ -571,25 +1685,16 @@ impl ProcessedBlockTrace {
                 .map(|(addr, v)| (*addr, hash(addr.as_bytes()), *v))
         };
 
-        let last_inputs = txn_ir
-            .last_mut()
-            .expect("We cannot have an empty list of payloads.");
-
-        if last_inputs.signed_txn.is_none() {
-            // This is a dummy payload, hence it does not contain yet
-            // state accesses to the withdrawal addresses.
-            let withdrawal_addrs =
-                withdrawals_with_hashed_addrs_iter().map(|(_, h_addr, _)| h_addr);
-            last_inputs.tries.state_trie = create_minimal_state_partial_trie(
-                &final_trie_state.state,
-                withdrawal_addrs,
-                iter::empty(),
-            )?;
-        }
+        let Some(last_inputs) = txn_ir.last_mut() else {
+            return Err(Box::new(TraceParsingError::new(
+                TraceParsingErrorReason::EmptyIrWithWithdrawals(withdrawals.len()),
+            )));
+        };
 
         Self::update_trie_state_from_withdrawals(
             withdrawals_with_hashed_addrs_iter(),
             &mut final_trie_state.state,
+            create_missing_accounts,
         )?;
 
         last_inputs.withdrawals = withdrawals;
@@ -600,24 +1705,46 @@ impl ProcessedBlockTrace {
 
     /// Withdrawals update balances in the account trie, so we need to update
     /// our local trie state.
+    ///
+    /// If `create_missing_accounts` is set, a withdrawal to an account missing
+    /// from the state trie creates it, instead of failing with
+    /// [`MissingWithdrawalAccount`](TraceParsingErrorReason::MissingWithdrawalAccount).
+    ///
+    /// Fails with
+    /// [`WithdrawalBalanceOverflow`](TraceParsingErrorReason::WithdrawalBalanceOverflow)
+    /// if a withdrawal overflows the balance of its recipient.
     fn update_trie_state_from_withdrawals<'a>(
         withdrawals: impl IntoIterator<Item = (Address, HashedAccountAddr, U256)> + 'a,
         state: &mut HashedPartialTrie,
+        create_missing_accounts: bool,
     ) -> TraceParsingResult<()> {
         for (addr, h_addr, amt) in withdrawals {
-            let h_addr_nibs = Nibbles::from_h256_be(h_addr);
+            let h_addr_nibs = AccountNibbles::from_hashed_addr(h_addr).into_inner();
 
-            let acc_bytes = state.get(h_addr_nibs).ok_or_else(|| {
-                let mut e = TraceParsingError::new(
-                    TraceParsingErrorReason::MissingWithdrawalAccount(addr, h_addr, amt),
-                );
-                e.addr(addr);
-                e.h_addr(h_addr);
-                e
-            })?;
-            let mut acc_data = account_from_rlped_bytes(acc_bytes)?;
+            let mut acc_data = match state.get(h_addr_nibs) {
+                Some(acc_bytes) => account_from_rlped_bytes(acc_bytes)?,
+                None if create_missing_accounts => AccountRlp::default(),
+                None => {
+                    let mut e = TraceParsingError::new(
+                        TraceParsingErrorReason::MissingWithdrawalAccount(addr, h_addr, amt),
+                    );
+                    e.set_addr(addr);
+                    e.set_h_addr(h_addr);
+                    return Err(Box::new(e));
+                }
+            };
 
-            acc_data.balance += amt;
+            acc_data.balance = acc_data.balance.checked_add(amt).ok_or_else(|| {
+                let mut e =
+                    TraceParsingError::new(TraceParsingErrorReason::WithdrawalBalanceOverflow {
+                        addr,
+                        balance: acc_data.balance,
+                        amt,
+                    });
+                e.set_addr(addr);
+                e.set_h_addr(h_addr);
+                Box::new(e)
+            })?;
 
             state
                 .insert(h_addr_nibs, rlp::encode(&acc_data).to_vec())
@@ -627,53 +1754,207 @@ impl ProcessedBlockTrace {
         Ok(())
     }
 
-    /// Processes a single transaction in the trace.
+    /// Processes a single transaction in the trace, applying its deltas to
+    /// `curr_block_tries` and returning a snapshot from which its
+    /// [GenerationInputs] can later be created.
+    #[allow(clippy::too_many_arguments)]
     fn process_txn_info(
         txn_idx: usize,
         txn_info: ProcessedTxnInfo,
         curr_block_tries: &mut PartialTrieState,
         extra_data: &mut ExtraBlockData,
-        other_data: &OtherBlockData,
-    ) -> TraceParsingResult<GenerationInputs> {
+        expected_trie_roots: Option<&TrieRoots>,
+        build_receipt_trie: bool,
+        reject_zero_gas_txns: bool,
+        state_diff_observer: Option<&TxnStateDiffObserver>,
+        key_hash_cache: Option<&Mutex<KeyHashCache>>,
+    ) -> TraceParsingResult<TxnTrieSnapshot> {
         trace!("Generating proof IR for txn {}...", txn_idx);
 
-        Self::init_any_needed_empty_storage_tries(
-            &mut curr_block_tries.storage,
-            txn_info
-                .nodes_used_by_txn
-                .storage_accesses
-                .iter()
-                .map(|(k, _)| k),
-            &txn_info
-                .nodes_used_by_txn
-                .state_accounts_with_no_accesses_but_storage_tries,
-        );
+        // Such a txn still increments `txn_number_after`, but its execution is
+        // likely to result in a degenerate segment that the prover rejects.
+        if txn_info.meta.gas_used == 0 {
+            if reject_zero_gas_txns {
+                return Err(Box::new(TraceParsingError::new(
+                    TraceParsingErrorReason::ZeroGasTxn(txn_idx),
+                )));
+            }
+
+            warn!("Txn {} reports no gas used, proving it may fail", txn_idx);
+        }
+
+        let num_storage_writes = txn_info.nodes_used_by_txn.storage_writes.len();
+        let num_state_writes = txn_info.nodes_used_by_txn.state_writes.len();
+        let _span = trace_span!(
+            "process_txn_info",
+            txn_idx,
+            storage_writes = num_storage_writes,
+            state_writes = num_state_writes
+        )
+        .entered();
+
+        trace_span!("init_any_needed_empty_storage_tries", txn_idx).in_scope(|| {
+            Self::init_any_needed_empty_storage_tries(
+                &mut curr_block_tries.storage,
+                &curr_block_tries.state,
+                txn_info
+                    .nodes_used_by_txn
+                    .storage_accesses
+                    .iter()
+                    .map(|(k, _)| k),
+            )
+        })?;
         // For each non-dummy txn, we increment `txn_number_after` by 1, and
         // update `gas_used_after` accordingly.
-        extra_data.txn_number_after += U256::one();
-        extra_data.gas_used_after += txn_info.meta.gas_used.into();
+        extra_data.txn_number_after = extra_data
+            .txn_number_after
+            .checked_add(U256::one())
+            .ok_or_else(|| {
+                TraceParsingError::new(TraceParsingErrorReason::TxnNumberOverflow(
+                    txn_idx,
+                    extra_data.txn_number_after,
+                ))
+            })?;
+        let txn_gas_used = U256::from(txn_info.meta.gas_used);
+        extra_data.gas_used_after = extra_data
+            .gas_used_after
+            .checked_add(txn_gas_used)
+            .ok_or_else(|| {
+                TraceParsingError::new(TraceParsingErrorReason::GasOverflow(
+                    txn_idx,
+                    extra_data.gas_used_after,
+                    txn_gas_used,
+                ))
+            })?;
 
         // Because we need to run delta application before creating the minimal
         // sub-tries (we need to detect if deletes collapsed any branches), we need to
-        // do this clone every iteration.
-        let tries_at_start_of_txn = curr_block_tries.clone();
+        // snapshot the tries every iteration.
+        let tries_at_start_of_txn = curr_block_tries.snapshot_for_txn(&txn_info.nodes_used_by_txn);
+        let mut account_diffs = Vec::new();
 
-        Self::update_txn_and_receipt_tries(curr_block_tries, &txn_info.meta, txn_idx)
+        trace_span!("update_txn_and_receipt_tries", txn_idx)
+            .in_scope(|| {
+                Self::update_txn_and_receipt_tries(
+                    curr_block_tries,
+                    &txn_info.meta,
+                    txn_idx,
+                    build_receipt_trie,
+                )
+            })
             .map_err(TraceParsingError::from)?;
 
-        let delta_out =
-            Self::apply_deltas_to_trie_state(curr_block_tries, &txn_info.nodes_used_by_txn)?;
+        let delta_out = trace_span!(
+            "apply_deltas_to_trie_state",
+            txn_idx,
+            storage_writes = num_storage_writes,
+            state_writes = num_state_writes
+        )
+        .in_scope(|| {
+            Self::apply_deltas_to_trie_state(
+                curr_block_tries,
+                &txn_info.nodes_used_by_txn,
+                state_diff_observer.is_some().then_some(&mut account_diffs),
+                key_hash_cache,
+            )
+        })?;
+        trace!(
+            "Paths to not hash after the deltas of txn {}: {:?}",
+            txn_idx,
+            delta_out
+        );
+
+        let trie_roots_after = calculate_trie_input_hashes(curr_block_tries);
+        if let Some(expected_trie_roots) = expected_trie_roots {
+            verify_trie_roots(expected_trie_roots, &trie_roots_after, build_receipt_trie)?;
+        }
+
+        if let Some(observer) = state_diff_observer {
+            (observer.0)(&TxnStateDiff {
+                txn_idx,
+                nodes_used_by_txn: &txn_info.nodes_used_by_txn,
+                delta_application_out: &delta_out,
+                account_diffs: &account_diffs,
+                trie_roots_after: &trie_roots_after,
+            });
+        }
 
-        let tries = Self::create_minimal_partial_tries_needed_by_txn(
-            &tries_at_start_of_txn,
-            &txn_info.nodes_used_by_txn,
+        let snapshot = TxnTrieSnapshot {
             txn_idx,
+            txn_info,
+            tries_at_start_of_txn,
             delta_out,
-            &other_data.b_data.b_meta.block_beneficiary,
-        )?;
+            trie_roots_after,
+            extra_data: extra_data.clone(),
+        };
 
-        let trie_roots_after = calculate_trie_input_hashes(curr_block_tries);
-        let gen_inputs = GenerationInputs {
+        // After processing a transaction, we update the remaining accumulators
+        // for the next transaction.
+        extra_data.txn_number_before += U256::one();
+        extra_data.gas_used_before = extra_data.gas_used_after;
+
+        Ok(snapshot)
+    }
+
+    /// Creates the [GenerationInputs] of every txn from the snapshots captured
+    /// while processing them, in the order of the snapshots.
+    ///
+    /// Creating the minimal sub-tries is independent for each txn once the
+    /// snapshots are captured, so it can be done in parallel.
+    fn create_gen_inputs_from_txn_snapshots(
+        snapshots: Vec<TxnTrieSnapshot>,
+        other_data: &OtherBlockData,
+        fee_recipients: &[HashedAccountAddr],
+        node_provider: Option<&TrieNodeProvider>,
+    ) -> TraceParsingResult<Vec<GenerationInputs>> {
+        snapshots
+            .into_par_iter()
+            .map(|snapshot| {
+                let txn_idx = snapshot.txn_idx;
+                Self::create_gen_inputs_from_txn_snapshot(
+                    snapshot,
+                    other_data,
+                    fee_recipients,
+                    node_provider,
+                )
+                .map_err(|mut e| {
+                    e.set_txn_idx(txn_idx);
+                    e
+                })
+            })
+            .collect()
+    }
+
+    /// Creates the [GenerationInputs] of a txn from the snapshot captured while
+    /// processing it. This does not depend on any other txn.
+    fn create_gen_inputs_from_txn_snapshot(
+        snapshot: TxnTrieSnapshot,
+        other_data: &OtherBlockData,
+        fee_recipients: &[HashedAccountAddr],
+        node_provider: Option<&TrieNodeProvider>,
+    ) -> TraceParsingResult<GenerationInputs> {
+        let TxnTrieSnapshot {
+            txn_idx,
+            txn_info,
+            tries_at_start_of_txn,
+            delta_out,
+            trie_roots_after,
+            extra_data,
+        } = snapshot;
+
+        let tries =
+            trace_span!("create_minimal_partial_tries_needed_by_txn", txn_idx).in_scope(|| {
+                Self::create_minimal_partial_tries_needed_by_txn(
+                    &tries_at_start_of_txn,
+                    &txn_info.nodes_used_by_txn,
+                    txn_idx,
+                    delta_out,
+                    fee_recipients,
+                    node_provider,
+                )
+            })?;
+
+        Ok(GenerationInputs {
             txn_number_before: extra_data.txn_number_before,
             gas_used_before: extra_data.gas_used_before,
             gas_used_after: extra_data.gas_used_after,
@@ -687,17 +1968,180 @@ impl ProcessedBlockTrace {
             contract_code: txn_info.contract_code_accessed,
             block_metadata: other_data.b_data.b_meta.clone(),
             block_hashes: other_data.b_data.b_hashes.clone(),
-        };
+        })
+    }
+}
 
-        // After processing a transaction, we update the remaining accumulators
-        // for the next transaction.
-        extra_data.txn_number_before += U256::one();
-        extra_data.gas_used_before = extra_data.gas_used_after;
+/// What the decoding of a block starts from, see
+/// [`ProcessedBlockTrace::init_block_decoding`].
+struct BlockDecodingInit {
+    curr_block_tries: PartialTrieState,
+    /// A copy of the tries before the first txn, which the dummy entries use.
+    initial_tries_for_dummies: PartialTrieState,
+    extra_data: ExtraBlockData,
+    /// The hashed addresses of the accounts credited with the fees.
+    fee_recipients: Vec<HashedAccountAddr>,
+}
+
+/// An iterator over the [GenerationInputs] of a block, which only decodes a txn
+/// when its [GenerationInputs] are requested, allowing to start proving the
+/// first txns of a block while the next ones are still being decoded.
+///
+/// Yields exactly the same entries, in the same order, as the eager
+/// [`into_txn_proof_gen_ir`](crate::trace_protocol::BlockTrace::into_txn_proof_gen_ir),
+/// each of them checked against the previous one as done by [`validate_ir`].
+/// Once an error has been yielded, the iterator is exhausted.
+#[derive(Debug)]
+pub struct TxnProofGenIrStream {
+    txn_info: iter::Enumerate<vec::IntoIter<ProcessedTxnInfo>>,
+    num_txns: usize,
+    num_non_dummy_txns: usize,
+    /// The number of dummy entries still to be yielded before the first txn.
+    num_dummies_left: usize,
+    /// Entries generated upfront, yielded before anything else.
+    pending: VecDeque<GenerationInputs>,
+    curr_block_tries: PartialTrieState,
+    initial_tries_for_dummies: PartialTrieState,
+    extra_data: ExtraBlockData,
+    extra_data_for_dummies: ExtraBlockData,
+    other_data: OtherBlockData,
+    /// Added to the last txn of the block.
+    withdrawals: Vec<(Address, U256)>,
+    /// The options of the [`ProcessingMeta`] the stream was created with.
+    options: ProcessingOptions,
+    /// The hashed addresses of the accounts credited with the fees.
+    fee_recipients: Vec<HashedAccountAddr>,
+    /// The number of entries yielded so far.
+    num_yielded: usize,
+    /// The end of the last yielded entry, which the next one must start from.
+    prev_entry_end: Option<IrEntryEnd>,
+}
+
+impl TxnProofGenIrStream {
+    /// Checks the entry about to be yielded against the previous one, as done
+    /// by [`validate_ir`].
+    fn check_next_entry(&mut self, entry: &GenerationInputs) -> TraceParsingResult<()> {
+        let mut violations = Vec::new();
+        check_ir_entry(
+            self.num_yielded,
+            entry,
+            self.prev_entry_end.as_ref(),
+            &mut violations,
+        );
+        self.num_yielded += 1;
+        self.prev_entry_end = Some(IrEntryEnd::of(entry));
+
+        invalid_ir_if_any(violations).map_err(|mut e| {
+            e.set_block_num(self.other_data.b_data.b_meta.block_number);
+            e.set_block_chain_id(self.other_data.b_data.b_meta.block_chain_id);
+            e
+        })
+    }
+
+    /// Returns the next entry, without checking it against the previous one.
+    fn next_unchecked(&mut self) -> Option<TraceParsingResult<GenerationInputs>> {
+        if let Some(gen_inputs) = self.pending.pop_front() {
+            return Some(Ok(gen_inputs));
+        }
+
+        // As in the eager version, the dummy entries are prepended to the txns.
+        if self.num_dummies_left > 0 {
+            self.num_dummies_left -= 1;
+            return Some(Ok(create_dummy_gen_input(
+                &self.other_data,
+                &self.extra_data_for_dummies,
+                &self.initial_tries_for_dummies,
+            )));
+        }
+
+        let (txn_idx, txn_info) = self.txn_info.next()?;
+        Some(self.decode_txn(txn_idx, txn_info))
+    }
+
+    fn decode_txn(
+        &mut self,
+        txn_idx: TxnIdx,
+        txn_info: ProcessedTxnInfo,
+    ) -> TraceParsingResult<GenerationInputs> {
+        check_not_cancelled(self.options.cancellation_flag.as_deref(), txn_idx).map_err(
+            |mut e| {
+                e.set_block_num(self.other_data.b_data.b_meta.block_number);
+                e.set_block_chain_id(self.other_data.b_data.b_meta.block_chain_id);
+                e
+            },
+        )?;
+
+        let expected_trie_roots = self
+            .options
+            .expected_txn_trie_roots
+            .as_ref()
+            .and_then(|roots| roots.get(txn_idx));
+
+        let mut gen_inputs = ProcessedBlockTrace::process_txn_info(
+            txn_idx,
+            txn_info,
+            &mut self.curr_block_tries,
+            &mut self.extra_data,
+            expected_trie_roots,
+            self.options.build_receipt_trie,
+            self.options.reject_zero_gas_txns,
+            self.options.txn_state_diff_observer.as_ref(),
+            self.options.key_hash_cache.as_deref(),
+        )
+        .and_then(|snapshot| {
+            ProcessedBlockTrace::create_gen_inputs_from_txn_snapshot(
+                snapshot,
+                &self.other_data,
+                &self.fee_recipients,
+                self.options.node_provider.as_ref(),
+            )
+        })
+        .map_err(|mut e| {
+            e.set_txn_idx(txn_idx);
+            e.set_block_num(self.other_data.b_data.b_meta.block_number);
+            e.set_block_chain_id(self.other_data.b_data.b_meta.block_chain_id);
+            e
+        })?;
+
+        if txn_idx + 1 == self.num_txns {
+            verify_txn_number_after(&self.extra_data, self.num_non_dummy_txns, &self.other_data)?;
+
+            if !self.withdrawals.is_empty() {
+                ProcessedBlockTrace::add_withdrawals_to_txns(
+                    slice::from_mut(&mut gen_inputs),
+                    &mut self.curr_block_tries,
+                    mem::take(&mut self.withdrawals),
+                    self.options.create_missing_withdrawal_accounts,
+                )?;
+            }
+
+            verify_final_state_root(&self.other_data, &self.curr_block_tries)?;
+        }
 
         Ok(gen_inputs)
     }
 }
 
+impl Iterator for TxnProofGenIrStream {
+    type Item = TraceParsingResult<GenerationInputs>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = self.next_unchecked()?.and_then(|gen_inputs| {
+            self.check_next_entry(&gen_inputs)?;
+            Ok(gen_inputs)
+        });
+
+        if res.is_err() {
+            // The trie state is no longer consistent, so don't yield any further entry.
+            self.pending.clear();
+            self.num_dummies_left = 0;
+            self.txn_info = Vec::new().into_iter().enumerate();
+        }
+
+        Some(res)
+    }
+}
+
 impl StateTrieWrites {
     fn apply_writes_to_state_node(
         &self,
@@ -713,7 +2157,7 @@ impl StateTrieWrites {
                     let mut e = TraceParsingError::new(
                         TraceParsingErrorReason::MissingAccountStorageTrie(h_addr),
                     );
-                    e.h_addr(h_addr);
+                    e.set_h_addr(h_addr);
                     e
                 })?;
 
@@ -726,98 +2170,584 @@ impl StateTrieWrites {
         update_val_if_some(&mut state_node.storage_root, storage_root_hash_change);
         update_val_if_some(&mut state_node.code_hash, self.code_hash);
 
-        Ok(())
+        match self.expected_storage_root {
+            Some(expected) if expected != state_node.storage_root => {
+                let mut e = TraceParsingError::new(TraceParsingErrorReason::TrieRootMismatch {
+                    trie: TrieType::Storage,
+                    expected,
+                    got: state_node.storage_root,
+                });
+                e.set_h_addr(*h_addr);
+                Err(Box::new(e))
+            }
+            _ => Ok(()),
+        }
     }
 }
 
-fn calculate_trie_input_hashes(t_inputs: &PartialTrieState) -> TrieRoots {
-    TrieRoots {
-        state_root: t_inputs.state.hash(),
-        transactions_root: t_inputs.txn.hash(),
-        receipts_root: t_inputs.receipt.hash(),
+/// Applies the deltas of a single txn to the given state and storage tries,
+/// as is done for each txn of a block, which makes it possible to fuzz the
+/// delta application on its own.
+///
+/// The storage trie of every account whose storage gets written to must be
+/// present in `storage`.
+pub fn apply_deltas_to_tries(
+    state: &mut HashedPartialTrie,
+    storage: &mut HashMap<HashedAccountAddr, HashedPartialTrie>,
+    deltas: &NodesUsedByTxn,
+) -> TraceParsingResult<TrieDeltaApplicationOutput> {
+    let mut trie_state = PartialTrieState {
+        state: mem::take(state),
+        storage: mem::take(storage),
+        ..Default::default()
+    };
+    let out = ProcessedBlockTrace::apply_deltas_to_trie_state(&mut trie_state, deltas, None, None);
+
+    *state = trie_state.state;
+    *storage = trie_state.storage;
+
+    out
+}
+
+/// Checks the trie roots computed after a txn against the expected ones,
+/// reporting the first trie that diverged. The receipts root is only checked if
+/// `check_receipts_root` is set, as it is left empty when the receipt trie is
+/// not built.
+fn verify_trie_roots(
+    expected: &TrieRoots,
+    got: &TrieRoots,
+    check_receipts_root: bool,
+) -> TraceParsingResult<()> {
+    let roots = [
+        (TrieType::State, expected.state_root, got.state_root),
+        (
+            TrieType::Txn,
+            expected.transactions_root,
+            got.transactions_root,
+        ),
+        (TrieType::Receipt, expected.receipts_root, got.receipts_root),
+    ];
+
+    match roots.into_iter().find(|(trie, expected, got)| {
+        expected != got && (check_receipts_root || !matches!(trie, TrieType::Receipt))
+    }) {
+        Some((trie, expected, got)) => Err(Box::new(TraceParsingError::new(
+            TraceParsingErrorReason::TrieRootMismatch {
+                trie,
+                expected,
+                got,
+            },
+        ))),
+        None => Ok(()),
     }
 }
 
-// We really want to get a trie with just a hash node here, and this is an easy
-// way to do it.
-fn create_fully_hashed_out_sub_partial_trie(trie: &HashedPartialTrie) -> HashedPartialTrie {
-    // Impossible to actually fail with an empty iter.
-    create_trie_subset(trie, empty::<Nibbles>()).unwrap()
+/// Returns the number of txns of the block which are not dummies, i.e. which
+/// have some bytes to insert into the txn trie.
+fn count_non_dummy_txns(txn_info: &[ProcessedTxnInfo]) -> usize {
+    txn_info
+        .iter()
+        .filter(|txn_info| txn_info.meta.txn_bytes().is_some())
+        .count()
 }
 
-fn create_dummy_txn_pair_for_empty_block(
-    other_data: &OtherBlockData,
+/// Fails with [`TraceParsingErrorReason::Cancelled`] if `cancellation_flag` got
+/// set before processing the txn at `txn_idx`.
+fn check_not_cancelled(
+    cancellation_flag: Option<&AtomicBool>,
+    txn_idx: TxnIdx,
+) -> TraceParsingResult<()> {
+    match cancellation_flag {
+        Some(flag) if flag.load(Ordering::Relaxed) => Err(Box::new(TraceParsingError::new(
+            TraceParsingErrorReason::Cancelled {
+                last_completed_txn: txn_idx.checked_sub(1),
+            },
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that `txn_number_after` accounts for exactly the non-dummy txns of
+/// the block once they have all been processed.
+fn verify_txn_number_after(
     extra_data: &ExtraBlockData,
-    final_tries: &PartialTrieState,
-) -> [GenerationInputs; 2] {
-    [
-        create_dummy_gen_input(other_data, extra_data, final_tries),
-        create_dummy_gen_input(other_data, extra_data, final_tries),
-    ]
+    num_non_dummy_txns: usize,
+    other_data: &OtherBlockData,
+) -> TraceParsingResult<()> {
+    if extra_data.txn_number_after != num_non_dummy_txns.into() {
+        let mut e = TraceParsingError::new(TraceParsingErrorReason::TxnNumberMismatch {
+            expected: num_non_dummy_txns,
+            got: extra_data.txn_number_after,
+        });
+        e.set_block_num(other_data.b_data.b_meta.block_number);
+        e.set_block_chain_id(other_data.b_data.b_meta.block_chain_id);
+        return Err(Box::new(e));
+    }
+
+    Ok(())
 }
 
-fn create_dummy_gen_input(
+/// Returns the hashed addresses of the accounts credited with the fees of the
+/// txns of the block, as per the fee model of `p_meta`.
+fn fee_recipient_hashes<F>(
+    p_meta: &ProcessingMeta<F>,
     other_data: &OtherBlockData,
-    extra_data: &ExtraBlockData,
-    final_tries: &PartialTrieState,
-) -> GenerationInputs {
-    let sub_tries = create_dummy_proof_trie_inputs(
-        final_tries,
-        create_fully_hashed_out_sub_partial_trie(&final_tries.state),
-    );
-    create_dummy_gen_input_common(other_data, extra_data, sub_tries)
+) -> Vec<HashedAccountAddr>
+where
+    F: CodeHashResolveFunc,
+{
+    p_meta
+        .fee_model()
+        .fee_recipients(other_data.b_data.b_meta.block_beneficiary)
+        .iter()
+        .map(|addr| hash(addr.as_bytes()))
+        .collect()
 }
 
-fn create_dummy_gen_input_common(
+/// Checks that the union of the logs blooms of the receipts of the block
+/// matches the block bloom.
+fn verify_receipts_bloom(
+    txn_info: &[ProcessedTxnInfo],
     other_data: &OtherBlockData,
-    extra_data: &ExtraBlockData,
-    sub_tries: TrieInputs,
-) -> GenerationInputs {
-    let trie_roots_after = TrieRoots {
-        state_root: sub_tries.state_trie.hash(),
-        transactions_root: sub_tries.transactions_trie.hash(),
-        receipts_root: sub_tries.receipts_trie.hash(),
-    };
+) -> TraceParsingResult<()> {
+    let mut got = Bloom::default();
+    for (txn_idx, txn_info) in txn_info.iter().enumerate() {
+        let receipt_bloom =
+            receipt_bloom(txn_info.meta.receipt_node_bytes()).map_err(|mut e| {
+                e.set_txn_idx(txn_idx);
+                e
+            })?;
 
-    // Sanity checks
-    assert_eq!(
-        extra_data.txn_number_before, extra_data.txn_number_after,
-        "Txn numbers before/after differ in a dummy payload with no txn!"
-    );
-    assert_eq!(
-        extra_data.gas_used_before, extra_data.gas_used_after,
-        "Gas used before/after differ in a dummy payload with no txn!"
-    );
+        for (acc, word) in got.iter_mut().zip(receipt_bloom) {
+            *acc |= word;
+        }
+    }
 
-    GenerationInputs {
-        signed_txn: None,
-        tries: sub_tries,
-        trie_roots_after,
-        checkpoint_state_trie_root: extra_data.checkpoint_state_trie_root,
-        block_metadata: other_data.b_data.b_meta.clone(),
-        block_hashes: other_data.b_data.b_hashes.clone(),
-        txn_number_before: extra_data.txn_number_before,
-        gas_used_before: extra_data.gas_used_before,
-        gas_used_after: extra_data.gas_used_after,
-        contract_code: HashMap::default(),
-        withdrawals: vec![], // this is set after creating dummy payloads
+    let expected = other_data.b_data.b_meta.block_bloom;
+    match expected == got {
+        true => Ok(()),
+        false => {
+            let mut e = TraceParsingError::new(TraceParsingErrorReason::ReceiptsBloomMismatch {
+                expected,
+                got,
+            });
+            e.set_block_num(other_data.b_data.b_meta.block_number);
+            Err(Box::new(e))
+        }
     }
 }
 
-fn create_dummy_proof_trie_inputs(
-    final_tries_at_end_of_block: &PartialTrieState,
-    state_trie: HashedPartialTrie,
-) -> TrieInputs {
-    let partial_sub_storage_tries: Vec<_> = final_tries_at_end_of_block
-        .storage
-        .iter()
-        .map(|(hashed_acc_addr, s_trie)| {
-            (
-                *hashed_acc_addr,
-                create_fully_hashed_out_sub_partial_trie(s_trie),
-            )
+/// Decodes the logs bloom of a receipt trie node, either a legacy receipt or a
+/// typed one (prefixed by its type).
+fn receipt_bloom(receipt_node_bytes: &[u8]) -> TraceParsingResult<Bloom> {
+    let receipt = rlp::decode::<LegacyReceiptRlp>(receipt_node_bytes)
+        .or_else(|err| match receipt_node_bytes.split_first() {
+            Some((_, typed_receipt)) => rlp::decode(typed_receipt),
+            None => Err(err),
+        })
+        .map_err(|_| {
+            Box::new(TraceParsingError::new(
+                TraceParsingErrorReason::ReceiptDecode(hex::encode(receipt_node_bytes)),
+            ))
+        })?;
+
+    if receipt.bloom.len() != 256 {
+        return Err(Box::new(TraceParsingError::new(
+            TraceParsingErrorReason::ReceiptDecode(hex::encode(receipt_node_bytes)),
+        )));
+    }
+
+    Ok(core::array::from_fn(|i| {
+        U256::from_big_endian(&receipt.bloom[32 * i..32 * (i + 1)])
+    }))
+}
+
+/// Checks that the previous block hashes hold the hashes of the
+/// [`NUM_PREV_BLOCK_HASHES`] ancestors of the block, with the oldest first.
+///
+/// Blocks closer to genesis have fewer ancestors, in which case the leading
+/// entries, which do not correspond to any block, must be empty.
+fn verify_block_hashes(other_data: &OtherBlockData) -> TraceParsingResult<()> {
+    let block_num = other_data.b_data.b_meta.block_number;
+    let prev_hashes = &other_data.b_data.b_hashes.prev_hashes;
+
+    let reason = if prev_hashes.len() != NUM_PREV_BLOCK_HASHES {
+        Some(TraceParsingErrorReason::WrongNumberOfPrevBlockHashes(
+            prev_hashes.len(),
+        ))
+    } else {
+        let num_ancestors = block_num.min(NUM_PREV_BLOCK_HASHES.into()).as_usize();
+        let first_ancestor_idx = NUM_PREV_BLOCK_HASHES - num_ancestors;
+
+        prev_hashes.iter().enumerate().find_map(|(idx, h)| {
+            match (idx >= first_ancestor_idx, h.is_zero()) {
+                (false, false) => {
+                    Some(TraceParsingErrorReason::UnexpectedPrevBlockHash { idx, block_num })
+                }
+                (true, true) => {
+                    Some(TraceParsingErrorReason::MissingPrevBlockHash { idx, block_num })
+                }
+                _ => None,
+            }
+        })
+    };
+
+    match reason {
+        Some(reason) => {
+            let mut e = TraceParsingError::new(reason);
+            e.set_block_num(block_num);
+            e.set_block_chain_id(other_data.b_data.b_meta.block_chain_id);
+            Err(Box::new(e))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Checks the state trie root at the end of the block against
+/// [`OtherBlockData::final_state_trie_root`], if provided.
+fn verify_final_state_root(
+    other_data: &OtherBlockData,
+    final_tries: &PartialTrieState,
+) -> TraceParsingResult<()> {
+    let Some(expected) = other_data.final_state_trie_root else {
+        return Ok(());
+    };
+
+    let got = final_tries.state.hash();
+    if expected != got {
+        let mut e = TraceParsingError::new(TraceParsingErrorReason::FinalStateRootMismatch {
+            expected,
+            got,
+        });
+        e.set_block_num(other_data.b_data.b_meta.block_number);
+        e.set_block_chain_id(other_data.b_data.b_meta.block_chain_id);
+        return Err(Box::new(e));
+    }
+
+    Ok(())
+}
+
+/// Checks the consistency of the consecutive entries of the IR of a block,
+/// returning an
+/// [`InvalidIr`](TraceParsingErrorReason::InvalidIr) error listing every
+/// violated invariant, if any:
+/// - a dummy entry must not use any gas;
+/// - the gas used and txn number before an entry must be the ones after the
+///   previous entry;
+/// - the input tries of an entry must have the roots the previous entry ended
+///   with.
+///
+/// This is done on the IR returned by
+/// [`into_txn_proof_gen_ir`](crate::trace_protocol::BlockTrace::into_txn_proof_gen_ir),
+/// and on the entries of a [`TxnProofGenIrStream`] as they are yielded.
+pub fn validate_ir(gen_inputs: &[GenerationInputs]) -> TraceParsingResult<()> {
+    let mut violations = Vec::new();
+    let mut prev_end = None;
+
+    for (idx, entry) in gen_inputs.iter().enumerate() {
+        check_ir_entry(idx, entry, prev_end.as_ref(), &mut violations);
+        prev_end = Some(IrEntryEnd::of(entry));
+    }
+
+    invalid_ir_if_any(violations)
+}
+
+/// What an IR entry ended with, which the next entry must start from.
+#[derive(Clone, Debug)]
+struct IrEntryEnd {
+    gas_used_after: U256,
+    txn_number_after: U256,
+    trie_roots_after: TrieRoots,
+}
+
+impl IrEntryEnd {
+    fn of(entry: &GenerationInputs) -> Self {
+        Self {
+            gas_used_after: entry.gas_used_after,
+            txn_number_after: entry.txn_number_before
+                + U256::from(entry.signed_txn.is_some() as u8),
+            trie_roots_after: entry.trie_roots_after.clone(),
+        }
+    }
+}
+
+/// Pushes the invariants of [`validate_ir`] violated by the entry at `idx` to
+/// `violations`, given the end of the previous entry, if any.
+fn check_ir_entry(
+    idx: usize,
+    entry: &GenerationInputs,
+    prev: Option<&IrEntryEnd>,
+    violations: &mut Vec<IrInvariantViolation>,
+) {
+    if entry.signed_txn.is_none() && entry.gas_used_before != entry.gas_used_after {
+        violations.push(IrInvariantViolation::DummyGasUsed {
+            idx,
+            gas_used_before: entry.gas_used_before,
+            gas_used_after: entry.gas_used_after,
+        });
+    }
+
+    let Some(prev) = prev else {
+        return;
+    };
+
+    if entry.gas_used_before != prev.gas_used_after {
+        violations.push(IrInvariantViolation::GasUsedDiscontinuity {
+            idx,
+            expected: prev.gas_used_after,
+            got: entry.gas_used_before,
+        });
+    }
+
+    if entry.txn_number_before != prev.txn_number_after {
+        violations.push(IrInvariantViolation::TxnNumberDiscontinuity {
+            idx,
+            expected: prev.txn_number_after,
+            got: entry.txn_number_before,
+        });
+    }
+
+    for (trie, expected, input_trie) in [
+        (
+            TrieType::State,
+            prev.trie_roots_after.state_root,
+            &entry.tries.state_trie,
+        ),
+        (
+            TrieType::Txn,
+            prev.trie_roots_after.transactions_root,
+            &entry.tries.transactions_trie,
+        ),
+        (
+            TrieType::Receipt,
+            prev.trie_roots_after.receipts_root,
+            &entry.tries.receipts_trie,
+        ),
+    ] {
+        let got = trie_root_hash(input_trie);
+        if got != expected {
+            violations.push(IrInvariantViolation::TrieRootDiscontinuity {
+                idx,
+                trie,
+                expected,
+                got,
+            });
+        }
+    }
+}
+
+fn invalid_ir_if_any(violations: Vec<IrInvariantViolation>) -> TraceParsingResult<()> {
+    match violations.is_empty() {
+        true => Ok(()),
+        false => Err(Box::new(TraceParsingError::new(
+            TraceParsingErrorReason::InvalidIr(violations),
+        ))),
+    }
+}
+
+/// Computes the roots of the tries after a txn.
+///
+/// This does not re-hash the whole tries: [`HashedPartialTrie`] caches the
+/// hash of every node, and applying a delta only replaces the nodes along the
+/// path of the updated key. Only those are hashed again, while the untouched
+/// subtries reuse the hashes computed after the previous txns.
+fn calculate_trie_input_hashes(t_inputs: &PartialTrieState) -> TrieRoots {
+    TrieRoots {
+        state_root: t_inputs.state.hash(),
+        transactions_root: t_inputs.txn.hash(),
+        receipts_root: t_inputs.receipt.hash(),
+    }
+}
+
+/// Turns the tries a block ended on into the starting tries of the next
+/// block, filling in any node hashed out of them but present in the
+/// pre-images of the next block.
+fn carry_tries_forward(
+    prev_final_tries: PartialTrieState,
+    pre_images: PartialTriePreImages,
+) -> TraceParsingResult<PartialTriePreImages> {
+    let PartialTrieState {
+        state, mut storage, ..
+    } = prev_final_tries;
+
+    if state.hash() != pre_images.state.hash() {
+        return Err(Box::new(TraceParsingError::new(
+            TraceParsingErrorReason::TrieRootMismatch {
+                trie: TrieType::State,
+                expected: pre_images.state.hash(),
+                got: state.hash(),
+            },
+        )));
+    }
+
+    for (h_addr, pre_image) in pre_images.storage {
+        match storage.get_mut(&h_addr) {
+            // The storage root of the account was already checked as part of the
+            // state trie, so both tries share the same root.
+            Some(trie) => *trie = fill_in_hash_nodes(trie, &pre_image),
+            None => {
+                storage.insert(h_addr, pre_image);
+            }
+        }
+    }
+
+    Ok(PartialTriePreImages {
+        state: fill_in_hash_nodes(&state, &pre_images.state),
+        storage,
+    })
+}
+
+/// Returns `trie` with each of its hash nodes replaced by the corresponding
+/// sub-trie of `other` if it is not hashed out there. Both tries must have the
+/// same root hash.
+fn fill_in_hash_nodes(trie: &HashedPartialTrie, other: &HashedPartialTrie) -> HashedPartialTrie {
+    match (&**trie, &**other) {
+        (Node::Hash(_), n) if !matches!(n, Node::Hash(_)) => other.clone(),
+        (
+            Node::Branch { children, value },
+            Node::Branch {
+                children: other_children,
+                ..
+            },
+        ) => {
+            let children = std::array::from_fn(|i| {
+                Arc::new(Box::new(fill_in_hash_nodes(
+                    &children[i],
+                    &other_children[i],
+                )))
+            });
+            HashedPartialTrie::new(Node::Branch {
+                children,
+                value: value.clone(),
+            })
+        }
+        (
+            Node::Extension { nibbles, child },
+            Node::Extension {
+                child: other_child, ..
+            },
+        ) => HashedPartialTrie::new(Node::Extension {
+            nibbles: *nibbles,
+            child: Arc::new(Box::new(fill_in_hash_nodes(child, other_child))),
+        }),
+        _ => trie.clone(),
+    }
+}
+
+// We really want to get a trie with just a hash node here, and this is an easy
+// way to do it.
+fn create_fully_hashed_out_sub_partial_trie(trie: &HashedPartialTrie) -> HashedPartialTrie {
+    match &**trie {
+        // Already as hashed out as it can be.
+        Node::Empty | Node::Hash(_) => trie.clone(),
+        // Unlike `create_trie_subset`, this doesn't need to walk the whole trie.
+        _ => HashedPartialTrie::new(Node::Hash(trie.hash())),
+    }
+}
+
+/// Returns the root hash of `trie`, without rehashing an empty or hashed out
+/// trie.
+fn trie_root_hash(trie: &HashedPartialTrie) -> TrieRootHash {
+    match &**trie {
+        Node::Empty => EMPTY_TRIE_HASH,
+        Node::Hash(h) => *h,
+        _ => trie.hash(),
+    }
+}
+
+fn create_dummy_gen_inputs(
+    other_data: &OtherBlockData,
+    extra_data: &ExtraBlockData,
+    final_tries: &PartialTrieState,
+    num_dummies: usize,
+) -> Vec<GenerationInputs> {
+    // All the dummies are identical, so only build the first one.
+    match num_dummies {
+        0 => Vec::new(),
+        _ => vec![create_dummy_gen_input(other_data, extra_data, final_tries); num_dummies],
+    }
+}
+
+fn create_dummy_gen_input(
+    other_data: &OtherBlockData,
+    extra_data: &ExtraBlockData,
+    final_tries: &PartialTrieState,
+) -> GenerationInputs {
+    let sub_tries = create_dummy_proof_trie_inputs(
+        final_tries,
+        create_fully_hashed_out_sub_partial_trie(&final_tries.state),
+    );
+    create_dummy_gen_input_common(other_data, extra_data, sub_tries)
+}
+
+fn create_dummy_gen_input_with_state_addrs_accessed(
+    other_data: &OtherBlockData,
+    extra_data: &ExtraBlockData,
+    final_tries: &PartialTrieState,
+    account_addrs_accessed: impl Iterator<Item = HashedAccountAddr>,
+) -> TraceParsingResult<GenerationInputs> {
+    let sub_tries = create_dummy_proof_trie_inputs(
+        final_tries,
+        create_minimal_state_partial_trie(
+            &final_tries.state,
+            account_addrs_accessed,
+            iter::empty(),
+            None,
+        )?,
+    );
+    Ok(create_dummy_gen_input_common(
+        other_data, extra_data, sub_tries,
+    ))
+}
+
+fn create_dummy_gen_input_common(
+    other_data: &OtherBlockData,
+    extra_data: &ExtraBlockData,
+    sub_tries: TrieInputs,
+) -> GenerationInputs {
+    let trie_roots_after = TrieRoots {
+        state_root: trie_root_hash(&sub_tries.state_trie),
+        transactions_root: trie_root_hash(&sub_tries.transactions_trie),
+        receipts_root: trie_root_hash(&sub_tries.receipts_trie),
+    };
+
+    // The consistency of the txn numbers and gas used is checked by `validate_ir`
+    // once the whole block is assembled.
+    GenerationInputs {
+        signed_txn: None,
+        tries: sub_tries,
+        trie_roots_after,
+        checkpoint_state_trie_root: extra_data.checkpoint_state_trie_root,
+        block_metadata: other_data.b_data.b_meta.clone(),
+        block_hashes: other_data.b_data.b_hashes.clone(),
+        txn_number_before: extra_data.txn_number_before,
+        gas_used_before: extra_data.gas_used_before,
+        gas_used_after: extra_data.gas_used_after,
+        contract_code: HashMap::default(),
+        withdrawals: vec![], // this is set after creating dummy payloads
+    }
+}
+
+/// Only the storage tries present in `final_tries_at_end_of_block` are passed
+/// on. The pre-images may omit the storage tries of the accounts no txn
+/// accesses, and the kernel treats a missing storage trie as hashed out to the
+/// storage root of its account.
+fn create_dummy_proof_trie_inputs(
+    final_tries_at_end_of_block: &PartialTrieState,
+    state_trie: HashedPartialTrie,
+) -> TrieInputs {
+    let mut partial_sub_storage_tries: Vec<_> = final_tries_at_end_of_block
+        .storage
+        .iter()
+        .map(|(hashed_acc_addr, s_trie)| {
+            (
+                *hashed_acc_addr,
+                create_fully_hashed_out_sub_partial_trie(s_trie),
+            )
         })
         .collect();
+    // Don't let the iteration order of the map leak into the IR.
+    partial_sub_storage_tries.sort_unstable_by_key(|(hashed_acc_addr, _)| *hashed_acc_addr);
 
     TrieInputs {
         state_trie,
@@ -831,27 +2761,56 @@ fn create_dummy_proof_trie_inputs(
     }
 }
 
+/// Creates the minimal state sub-trie containing the accessed accounts and the
+/// additional paths.
+///
+/// An accessed account may sit under a node which is already hashed out in
+/// `state_trie`, e.g. a withdrawal recipient of a dummy payload. The hashed
+/// node is then kept as is, rather than failing. The additional paths must
+/// however be fully expandable, possibly with the help of `node_provider`.
 fn create_minimal_state_partial_trie(
     state_trie: &HashedPartialTrie,
     state_accesses: impl Iterator<Item = HashedNodeAddr>,
     additional_state_trie_paths_to_not_hash: impl Iterator<Item = Nibbles>,
+    node_provider: Option<&TrieNodeProvider>,
 ) -> TraceParsingResult<HashedPartialTrie> {
     create_trie_subset_wrapped(
         state_trie,
         state_accesses
             .into_iter()
-            .map(Nibbles::from_h256_be)
+            .map(|h_addr| stop_key_at_hash_node(state_trie, Nibbles::from_h256_be(h_addr)))
             .chain(additional_state_trie_paths_to_not_hash),
         TrieType::State,
+        node_provider,
     )
 }
 
+/// Returns the key of the first `Hash` node on the path of `k` in `trie` if
+/// there is one, and `k` otherwise.
+fn stop_key_at_hash_node(trie: &HashedPartialTrie, k: Nibbles) -> Nibbles {
+    let path: TriePath = path_for_query(trie, k, false).collect();
+    match path.iter().last() {
+        Some(TrieSegment::Hash) => {
+            let hash_node_k = path.iter().into_key();
+            trace!(
+                "Key {:x} is under the hash node at {:x}, which is kept hashed",
+                k,
+                hash_node_k
+            );
+
+            hash_node_k
+        }
+        _ => k,
+    }
+}
+
 // TODO!!!: We really need to be appending the empty storage tries to the base
 // trie somewhere else! This is a big hack!
 fn create_minimal_storage_partial_tries<'a>(
     storage_tries: &HashMap<HashedAccountAddr, HashedPartialTrie>,
     accesses_per_account: impl Iterator<Item = &'a (HashedAccountAddr, Vec<HashedStorageAddrNibbles>)>,
     additional_storage_trie_paths_to_not_hash: &HashMap<HashedAccountAddr, Vec<Nibbles>>,
+    node_provider: Option<&TrieNodeProvider>,
 ) -> TraceParsingResult<Vec<(HashedAccountAddr, HashedPartialTrie)>> {
     accesses_per_account
         .map(|(h_addr, mem_accesses)| {
@@ -870,19 +2829,34 @@ fn create_minimal_storage_partial_tries<'a>(
                 base_storage_trie,
                 storage_slots_to_not_hash,
                 TrieType::Storage,
+                node_provider,
             )?;
 
             Ok((*h_addr, partial_storage_trie))
         })
-        .collect::<TraceParsingResult<_>>()
+        .collect::<TraceParsingResult<Vec<_>>>()
+        .map(|mut partial_storage_tries| {
+            // The accesses come from the txn traces, which are keyed by address in a map,
+            // so don't let their order leak into the IR.
+            partial_storage_tries.sort_unstable_by_key(|(h_addr, _)| *h_addr);
+            partial_storage_tries
+        })
 }
 
+/// Creates the subset of `trie` needed by `accesses`.
+///
+/// If an access goes through a `Hash` node, the missing nodes are fetched from
+/// `node_provider` when set, and the subset is created again from `trie` with
+/// these nodes filled in. This is repeated until either the subset can be
+/// created, or a missing node can't be provided, in which case this fails with
+/// [`MissingKeysCreatingSubPartialTrie`](TraceParsingErrorReason::MissingKeysCreatingSubPartialTrie).
 fn create_trie_subset_wrapped(
     trie: &HashedPartialTrie,
     accesses: impl Iterator<Item = Nibbles>,
     trie_type: TrieType,
+    node_provider: Option<&TrieNodeProvider>,
 ) -> TraceParsingResult<HashedPartialTrie> {
-    create_trie_subset(trie, accesses).map_err(|trie_err| {
+    let missing_keys_err = |trie_err| {
         let key = match trie_err {
             SubsetTrieError::UnexpectedKey(key, _) => key,
         };
@@ -890,10 +2864,77 @@ fn create_trie_subset_wrapped(
         Box::new(TraceParsingError::new(
             TraceParsingErrorReason::MissingKeysCreatingSubPartialTrie(key, trie_type),
         ))
-    })
+    };
+
+    let Some(node_provider) = node_provider else {
+        return create_trie_subset(trie, accesses).map_err(missing_keys_err);
+    };
+
+    let accesses: Vec<_> = accesses.collect();
+    let mut expanded_trie = Cow::Borrowed(trie);
+    loop {
+        let trie_err = match create_trie_subset(&*expanded_trie, accesses.iter().copied()) {
+            Ok(subset) => return Ok(subset),
+            Err(trie_err) => trie_err,
+        };
+
+        // Every iteration replaces at least one `Hash` node, so this terminates.
+        let mut expanded_any = false;
+        for k in &accesses {
+            if let Some(expanded) = expand_hash_node_on_path(&expanded_trie, *k, node_provider) {
+                trace!(
+                    "Expanded a hash node on the path of key {:x} in the {} trie",
+                    k,
+                    trie_type
+                );
+                expanded_trie = Cow::Owned(expanded);
+                expanded_any = true;
+            }
+        }
+
+        if !expanded_any {
+            return Err(missing_keys_err(trie_err));
+        }
+    }
 }
 
-fn account_from_rlped_bytes(bytes: &[u8]) -> TraceParsingResult<AccountRlp> {
+/// Returns a copy of `trie` where the `Hash` node in the way of `k`, if any, is
+/// replaced by the node fetched from `node_provider`. Returns `None` if there
+/// is no such node, or if the provider can't provide it.
+fn expand_hash_node_on_path(
+    trie: &HashedPartialTrie,
+    mut k: Nibbles,
+    node_provider: &TrieNodeProvider,
+) -> Option<HashedPartialTrie> {
+    match &**trie {
+        Node::Hash(h) if !k.is_empty() => node_provider.fetch(*h),
+        Node::Branch { children, value } if !k.is_empty() => {
+            let nib = k.pop_next_nibble_front() as usize;
+            let child = expand_hash_node_on_path(&children[nib], k, node_provider)?;
+
+            let mut children = children.clone();
+            children[nib] = Arc::new(Box::new(child));
+            Some(HashedPartialTrie::new(Node::Branch {
+                children,
+                value: value.clone(),
+            }))
+        }
+        Node::Extension { nibbles, child }
+            if k.count > nibbles.count && k.nibbles_are_identical_up_to_smallest_count(nibbles) =>
+        {
+            k.pop_nibbles_front(nibbles.count);
+            let child = expand_hash_node_on_path(child, k, node_provider)?;
+
+            Some(HashedPartialTrie::new(Node::Extension {
+                nibbles: *nibbles,
+                child: Arc::new(Box::new(child)),
+            }))
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn account_from_rlped_bytes(bytes: &[u8]) -> TraceParsingResult<AccountRlp> {
     rlp::decode(bytes).map_err(|err| {
         Box::new(TraceParsingError::new(
             TraceParsingErrorReason::AccountDecode(hex::encode(bytes), err.to_string()),
@@ -901,11 +2942,1856 @@ fn account_from_rlped_bytes(bytes: &[u8]) -> TraceParsingResult<AccountRlp> {
     })
 }
 
-impl TxnMetaState {
-    fn txn_bytes(&self) -> Vec<u8> {
-        match self.txn_bytes.as_ref() {
-            Some(v) => v.clone(),
-            None => Vec::default(),
+#[cfg(test)]
+mod tests {
+    use mpt_trie::builder::RlpEncodedTrie;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{
+        fee_model::FeeModel,
+        trace_protocol::{BlockTrace, ContractCodeUsage},
+    };
+
+    #[derive(Deserialize)]
+    struct ProverInput {
+        block_trace: BlockTrace,
+        other_data: OtherBlockData,
+    }
+
+    fn resolve_code_hash_fn(_: &CodeHash) -> Vec<u8> {
+        unreachable!("The code of processed block traces is already resolved")
+    }
+
+    fn block_input() -> ProverInput {
+        let bytes = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/benches/block_input.json"
+        ))
+        .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[test]
+    fn streamed_ir_matches_eager_ir() {
+        let prover_input = block_input();
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn);
+
+        let eager = prover_input
+            .block_trace
+            .clone()
+            .into_txn_proof_gen_ir(&p_meta, prover_input.other_data.clone())
+            .unwrap();
+        let streamed = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir_stream(&p_meta, prover_input.other_data)
+            .unwrap()
+            .collect::<TraceParsingResult<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(eager.len(), streamed.len());
+        for (eager, streamed) in eager.iter().zip(streamed.iter()) {
+            assert_eq!(
+                serde_json::to_value(eager).unwrap(),
+                serde_json::to_value(streamed).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn gen_inputs_created_in_parallel_match_the_sequential_ones() {
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn);
+        let other_data = block_input().other_data;
+
+        // Runs the sequential pass over the txns of a freshly processed block.
+        let snapshots = || {
+            let block = block_input()
+                .block_trace
+                .into_processed_block_trace(&p_meta, &other_data)
+                .unwrap();
+            let BlockDecodingInit {
+                mut curr_block_tries,
+                mut extra_data,
+                fee_recipients,
+                ..
+            } = ProcessedBlockTrace::init_block_decoding(
+                block.tries,
+                &block.txn_info,
+                &p_meta,
+                &other_data,
+            )
+            .unwrap();
+
+            let snapshots = block
+                .txn_info
+                .into_iter()
+                .enumerate()
+                .map(|(txn_idx, txn_info)| {
+                    ProcessedBlockTrace::process_txn_info(
+                        txn_idx,
+                        txn_info,
+                        &mut curr_block_tries,
+                        &mut extra_data,
+                        None,
+                        p_meta.options.build_receipt_trie,
+                        p_meta.options.reject_zero_gas_txns,
+                        None,
+                        None,
+                    )
+                    .unwrap()
+                })
+                .collect::<Vec<_>>();
+            (snapshots, fee_recipients)
+        };
+
+        let (txn_snapshots, fee_recipients) = snapshots();
+        let parallel = ProcessedBlockTrace::create_gen_inputs_from_txn_snapshots(
+            txn_snapshots,
+            &other_data,
+            &fee_recipients,
+            None,
+        )
+        .unwrap();
+
+        let (txn_snapshots, fee_recipients) = snapshots();
+        let sequential = txn_snapshots
+            .into_iter()
+            .map(|snapshot| {
+                ProcessedBlockTrace::create_gen_inputs_from_txn_snapshot(
+                    snapshot,
+                    &other_data,
+                    &fee_recipients,
+                    None,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert!(parallel.len() > 1);
+        assert_eq!(
+            serde_json::to_value(parallel).unwrap(),
+            serde_json::to_value(sequential).unwrap()
+        );
+    }
+
+    #[test]
+    fn streamed_ir_entries_are_checked_against_the_previous_one() {
+        let prover_input = block_input();
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn);
+
+        let mut stream = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir_stream(&p_meta, prover_input.other_data)
+            .unwrap();
+        let first = stream.next().unwrap().unwrap();
+
+        // Pretend that the first entry used some more gas.
+        stream.prev_entry_end.as_mut().unwrap().gas_used_after += U256::one();
+
+        let err = stream.next().unwrap().unwrap_err();
+        assert_eq!(
+            err.reason(),
+            &TraceParsingErrorReason::InvalidIr(vec![IrInvariantViolation::GasUsedDiscontinuity {
+                idx: 1,
+                expected: first.gas_used_after + U256::one(),
+                got: first.gas_used_after,
+            }])
+        );
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn deduped_ir_expands_to_the_eager_ir_and_is_smaller() {
+        let prover_input = block_input();
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn);
+
+        let eager = prover_input
+            .block_trace
+            .clone()
+            .into_txn_proof_gen_ir(&p_meta, prover_input.other_data.clone())
+            .unwrap();
+        let deduped = prover_input
+            .block_trace
+            .into_deduped_txn_proof_gen_ir(&p_meta, prover_input.other_data)
+            .unwrap();
+
+        let eager_size = serde_json::to_vec(&eager).unwrap().len();
+        let deduped_size = serde_json::to_vec(&deduped).unwrap().len();
+        assert!(deduped_size < eager_size);
+
+        let expanded = deduped.expand();
+        assert_eq!(eager.len(), expanded.len());
+        for (eager, expanded) in eager.iter().zip(expanded.iter()) {
+            assert_eq!(
+                serde_json::to_value(eager).unwrap(),
+                serde_json::to_value(expanded).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn returned_trie_roots_are_the_roots_after_each_ir_entry() {
+        let prover_input = block_input();
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn);
+
+        let (txn_ir, trie_roots) = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir_with_roots(&p_meta, prover_input.other_data)
+            .unwrap();
+
+        assert_eq!(txn_ir.len(), trie_roots.len());
+        for (gen_inputs, roots) in txn_ir.iter().zip(trie_roots.iter()) {
+            assert_eq!(&gen_inputs.trie_roots_after, roots);
         }
     }
+
+    #[test]
+    fn gas_used_per_ir_entry_adds_up_to_the_block_gas_used() {
+        let prover_input = block_input();
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn);
+        let block_gas_used = prover_input.other_data.b_data.b_meta.block_gas_used;
+
+        let (txn_ir, gas_used) = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir_with_gas_used(&p_meta, prover_input.other_data)
+            .unwrap();
+
+        assert_eq!(txn_ir.len(), gas_used.len());
+        assert!(gas_used.iter().all(|&gas| gas > 0));
+        assert_eq!(U256::from(gas_used.iter().sum::<u64>()), block_gas_used);
+    }
+
+    #[test]
+    fn corrupted_receipt_bloom_is_rejected_when_validating_receipts() {
+        let prover_input = block_input();
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn).with_validate_receipts(true);
+
+        let mut processed = prover_input
+            .block_trace
+            .into_processed(&p_meta, &prover_input.other_data)
+            .unwrap();
+        verify_receipts_bloom(&processed.txn_info, &prover_input.other_data).unwrap();
+
+        // Clear the bloom of a receipt which has logs.
+        let meta = processed
+            .txn_info
+            .iter_mut()
+            .map(|txn_info| &mut txn_info.meta)
+            .find(|meta| receipt_bloom(meta.receipt_node_bytes()).unwrap() != Bloom::default())
+            .unwrap();
+        let (tx_type, mut receipt) = match rlp::decode::<LegacyReceiptRlp>(&meta.receipt_node_bytes)
+        {
+            Ok(receipt) => (0, receipt),
+            Err(_) => (
+                meta.receipt_node_bytes[0],
+                rlp::decode(&meta.receipt_node_bytes[1..]).unwrap(),
+            ),
+        };
+        receipt.bloom = vec![0; 256].into();
+        meta.receipt_node_bytes = receipt.encode(tx_type);
+
+        let err = processed
+            .into_txn_proof_gen_ir(&p_meta, prover_input.other_data)
+            .unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::ReceiptsBloomMismatch { .. }
+        ));
+    }
+
+    #[test]
+    fn block_witness_json_is_processed_like_the_block_trace() {
+        let prover_input = block_input();
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn);
+
+        let witness_json = serde_json::to_vec(&prover_input.block_trace).unwrap();
+        let from_witness = ProcessedBlockTrace::from_block_witness_json(
+            &witness_json,
+            &p_meta,
+            &prover_input.other_data,
+        )
+        .unwrap()
+        .into_txn_proof_gen_ir(&p_meta, prover_input.other_data.clone())
+        .unwrap();
+        let eager = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir(&p_meta, prover_input.other_data.clone())
+            .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(eager).unwrap(),
+            serde_json::to_value(from_witness).unwrap()
+        );
+
+        let mut witness: serde_json::Value = serde_json::from_slice(&witness_json).unwrap();
+        witness.as_object_mut().unwrap().remove("txn_info");
+        let err = ProcessedBlockTrace::from_block_witness_json(
+            &serde_json::to_vec(&witness).unwrap(),
+            &p_meta,
+            &prover_input.other_data,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::BlockWitnessDecode(msg) if msg.contains("txn_info")
+        ));
+    }
+
+    #[test]
+    fn num_txn_proof_gen_ir_matches_the_decoded_ir_len() {
+        let prover_input = block_input();
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn).with_ir_padding(IrPadding {
+            min_len: 2,
+            multiple_of: 4,
+        });
+
+        let expected_len = prover_input.block_trace.num_txn_proof_gen_ir(&p_meta);
+        let gen_inputs = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir(&p_meta, prover_input.other_data)
+            .unwrap();
+
+        assert_eq!(gen_inputs.len(), expected_len);
+    }
+
+    #[test]
+    fn dummy_flags_mark_the_padding_entries() {
+        let prover_input = block_input();
+        let num_txns = prover_input.block_trace.txn_info.len();
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn).with_ir_padding(IrPadding {
+            min_len: 2,
+            multiple_of: 4,
+        });
+
+        let (txn_ir, is_dummy) = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir_with_dummy_flags(&p_meta, prover_input.other_data)
+            .unwrap();
+
+        assert_eq!(txn_ir.len(), is_dummy.len());
+        let num_dummies = p_meta.options.ir_padding.num_dummies(num_txns);
+        assert!(num_dummies > 0);
+        assert_eq!(is_dummy.iter().filter(|&&dummy| dummy).count(), num_dummies);
+        for (gen_inputs, dummy) in txn_ir.iter().zip(is_dummy) {
+            assert_eq!(gen_inputs.signed_txn.is_none(), dummy);
+        }
+    }
+
+    #[test]
+    fn state_diff_observer_sees_every_txn_in_order() {
+        let prover_input = block_input();
+        let num_txns = prover_input.block_trace.txn_info.len();
+
+        let observed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn).with_txn_state_diff_observer({
+            let observed = observed.clone();
+            move |diff| {
+                observed
+                    .lock()
+                    .unwrap()
+                    .push((diff.txn_idx(), diff.trie_roots_after().state_root))
+            }
+        });
+
+        let gen_inputs = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir(&p_meta, prover_input.other_data)
+            .unwrap();
+
+        let observed = observed.lock().unwrap();
+        assert_eq!(
+            observed
+                .iter()
+                .map(|(txn_idx, _)| *txn_idx)
+                .collect::<Vec<_>>(),
+            (0..num_txns).collect::<Vec<_>>()
+        );
+        // Dummy entries, if any, are prepended to the txns.
+        assert_eq!(
+            observed[0].1,
+            gen_inputs[gen_inputs.len() - num_txns]
+                .trie_roots_after
+                .state_root
+        );
+    }
+
+    #[test]
+    fn account_diffs_start_from_the_accounts_in_the_txn_state_trie() {
+        let prover_input = block_input();
+        let num_txns = prover_input.block_trace.txn_info.len();
+
+        let observed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn).with_txn_state_diff_observer({
+            let observed = observed.clone();
+            move |diff| {
+                assert_eq!(diff.account_diffs().len(), diff.changed_accounts().count());
+                observed
+                    .lock()
+                    .unwrap()
+                    .extend(diff.account_diffs().iter().map(|acc_diff| {
+                        (
+                            diff.txn_idx(),
+                            acc_diff.h_addr,
+                            rlp::encode(&acc_diff.before).to_vec(),
+                        )
+                    }))
+            }
+        });
+
+        let gen_inputs = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir(&p_meta, prover_input.other_data)
+            .unwrap();
+
+        let observed = observed.lock().unwrap();
+        assert!(!observed.is_empty());
+        for (txn_idx, h_addr, before) in observed.iter() {
+            let state_trie = &gen_inputs[gen_inputs.len() - num_txns + txn_idx]
+                .tries
+                .state_trie;
+            let expected = state_trie
+                .get(AccountNibbles::from_hashed_addr(*h_addr).into_inner())
+                .unwrap_or(&EMPTY_ACCOUNT_BYTES_RLPED);
+            assert_eq!(before.as_slice(), expected);
+        }
+    }
+
+    #[test]
+    fn flagged_storage_paths_belong_to_accounts_written_by_the_txn() {
+        let prover_input = block_input();
+
+        let p_meta =
+            ProcessingMeta::new(resolve_code_hash_fn).with_txn_state_diff_observer(|diff| {
+                let written_accounts: Vec<_> =
+                    diff.storage_writes().map(|(h_addr, _, _)| h_addr).collect();
+                assert!(diff
+                    .delta_application_output()
+                    .additional_storage_trie_paths_to_not_hash()
+                    .keys()
+                    .all(|h_addr| written_accounts.contains(&h_addr)));
+            });
+
+        prover_input
+            .block_trace
+            .into_txn_proof_gen_ir(&p_meta, prover_input.other_data)
+            .unwrap();
+    }
+
+    #[test]
+    fn wrong_final_state_root_is_rejected() {
+        let mut prover_input = block_input();
+        prover_input.other_data.final_state_trie_root = Some(H256::zero());
+
+        let err = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir(
+                &ProcessingMeta::new(resolve_code_hash_fn),
+                prover_input.other_data,
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::FinalStateRootMismatch { expected, .. }
+                if *expected == H256::zero()
+        ));
+    }
+
+    #[test]
+    fn zero_gas_txns_are_rejected_when_requested() {
+        let mut prover_input = block_input();
+        prover_input.block_trace.txn_info[1].meta.gas_used = 0;
+
+        let err = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir(
+                &ProcessingMeta::new(resolve_code_hash_fn).with_reject_zero_gas_txns(true),
+                prover_input.other_data,
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::ZeroGasTxn(1)
+        ));
+    }
+
+    #[test]
+    fn overflowing_accumulators_are_rejected() {
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn);
+        let other_data = block_input().other_data;
+
+        // Processes the first txn of the block, starting from the accumulators
+        // edited by `edit`.
+        let process_first_txn = |edit: fn(&mut ExtraBlockData)| {
+            let block = block_input()
+                .block_trace
+                .into_processed_block_trace(&p_meta, &other_data)
+                .unwrap();
+            let BlockDecodingInit {
+                mut curr_block_tries,
+                mut extra_data,
+                ..
+            } = ProcessedBlockTrace::init_block_decoding(
+                block.tries,
+                &block.txn_info,
+                &p_meta,
+                &other_data,
+            )
+            .unwrap();
+            edit(&mut extra_data);
+
+            let txn_info = block.txn_info.into_iter().next().unwrap();
+            let txn_gas_used = U256::from(txn_info.meta.gas_used);
+            let err = ProcessedBlockTrace::process_txn_info(
+                0,
+                txn_info,
+                &mut curr_block_tries,
+                &mut extra_data,
+                None,
+                p_meta.options.build_receipt_trie,
+                p_meta.options.reject_zero_gas_txns,
+                None,
+                None,
+            )
+            .unwrap_err();
+            (err, txn_gas_used)
+        };
+
+        let (err, _) = process_first_txn(|extra_data| extra_data.txn_number_after = U256::MAX);
+        assert_eq!(
+            err.reason(),
+            &TraceParsingErrorReason::TxnNumberOverflow(0, U256::MAX)
+        );
+
+        let (err, txn_gas_used) =
+            process_first_txn(|extra_data| extra_data.gas_used_after = U256::MAX);
+        assert_eq!(
+            err.reason(),
+            &TraceParsingErrorReason::GasOverflow(0, U256::MAX, txn_gas_used)
+        );
+    }
+
+    #[test]
+    fn eip_7702_authorization_sets_the_delegation_code_hash() {
+        let h_addr = hash(Address::repeat_byte(1).as_bytes());
+        let delegate = Address::repeat_byte(2);
+        let mut account = AccountRlp {
+            nonce: 3.into(),
+            balance: 100.into(),
+            ..Default::default()
+        };
+
+        let authorize = |account: &mut AccountRlp, delegate| {
+            StateTrieWrites {
+                balance: None,
+                nonce: Some(account.nonce + U256::one()),
+                storage_trie_change: false,
+                code_hash: Some(ContractCodeUsage::Delegate(delegate).get_code_hash()),
+                expected_storage_root: None,
+            }
+            .apply_writes_to_state_node(account, &h_addr, &HashMap::new())
+            .unwrap();
+        };
+
+        authorize(&mut account, delegate);
+        let delegation_code = [&[0xef, 0x01, 0x00][..], delegate.as_bytes()].concat();
+        let expected = AccountRlp {
+            nonce: 4.into(),
+            balance: 100.into(),
+            code_hash: hash(&delegation_code),
+            ..Default::default()
+        };
+        assert_eq!(rlp::encode(&account), rlp::encode(&expected));
+
+        // Delegating to the zero address clears the delegation.
+        authorize(&mut account, Address::zero());
+        assert_eq!(account.code_hash, EMPTY_CODE_HASH);
+        assert_eq!(account.nonce, 5.into());
+    }
+
+    #[test]
+    fn deltas_built_from_the_public_constructors_get_applied() {
+        let h_addr = hash(Address::repeat_byte(1).as_bytes());
+        let slot = Nibbles::from_h256_be(H256::from_low_u64_be(1));
+        let slot_val = rlp::encode(&U256::from(3)).to_vec();
+
+        let mut state = HashedPartialTrie::default();
+        state
+            .insert(
+                AccountNibbles::from_hashed_addr(h_addr).into_inner(),
+                rlp::encode(&AccountRlp::default()).to_vec(),
+            )
+            .unwrap();
+        let mut storage = HashMap::from([(h_addr, HashedPartialTrie::default())]);
+
+        let deltas = NodesUsedByTxn::default()
+            .with_state_write(
+                h_addr,
+                StateTrieWrites::default()
+                    .with_balance(U256::from(5))
+                    .with_storage_trie_change(true),
+            )
+            .with_storage_writes(h_addr, vec![(slot, slot_val.clone())]);
+        apply_deltas_to_tries(&mut state, &mut storage, &deltas).unwrap();
+
+        let mut expected_storage_trie = HashedPartialTrie::default();
+        expected_storage_trie
+            .insert(StorageNibbles::from_slot(&slot).into_inner(), slot_val)
+            .unwrap();
+        assert_eq!(storage[&h_addr].hash(), expected_storage_trie.hash());
+
+        let account = account_from_rlped_bytes(
+            state
+                .get(AccountNibbles::from_hashed_addr(h_addr).into_inner())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(account.balance, U256::from(5));
+        assert_eq!(account.storage_root, expected_storage_trie.hash());
+    }
+
+    #[test]
+    fn storage_root_mismatch_names_the_account() {
+        let h_addr = hash(Address::repeat_byte(1).as_bytes());
+        let mut storage_trie = HashedPartialTrie::default();
+        storage_trie
+            .insert(
+                StorageNibbles::from_hashed_slot(H256::repeat_byte(2)).into_inner(),
+                rlp::encode(&U256::one()).to_vec(),
+            )
+            .unwrap();
+        let storage_tries = HashMap::from([(h_addr, storage_trie.clone())]);
+
+        let apply_writes = |expected_storage_root| {
+            StateTrieWrites {
+                balance: None,
+                nonce: None,
+                storage_trie_change: true,
+                code_hash: None,
+                expected_storage_root: Some(expected_storage_root),
+            }
+            .apply_writes_to_state_node(
+                &mut AccountRlp::default(),
+                &h_addr,
+                &storage_tries,
+            )
+        };
+
+        apply_writes(storage_trie.hash()).unwrap();
+
+        let err = apply_writes(EMPTY_TRIE_HASH).unwrap_err();
+        assert_eq!(err.h_addr(), Some(h_addr));
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::TrieRootMismatch {
+                trie: TrieType::Storage,
+                expected,
+                got,
+            } if *expected == EMPTY_TRIE_HASH && *got == storage_trie.hash()
+        ));
+    }
+
+    #[test]
+    fn setting_the_cancellation_flag_aborts_processing() {
+        let prover_input = block_input();
+        let flag = Arc::new(AtomicBool::new(false));
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn).with_cancellation_flag(flag.clone());
+
+        let mut stream = prover_input
+            .block_trace
+            .clone()
+            .into_txn_proof_gen_ir_stream(&p_meta, prover_input.other_data.clone())
+            .unwrap();
+        for _ in 0..3 {
+            stream.next().unwrap().unwrap();
+        }
+
+        flag.store(true, Ordering::Relaxed);
+        let err = stream.next().unwrap().unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::Cancelled {
+                last_completed_txn: Some(2)
+            }
+        ));
+
+        let err = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir(&p_meta, prover_input.other_data)
+            .unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::Cancelled {
+                last_completed_txn: None
+            }
+        ));
+    }
+
+    #[test]
+    fn withdrawal_recipient_under_a_hashed_out_node_is_kept_hashed() {
+        let recipient_k = AccountNibbles::from_addr(&Address::repeat_byte(1)).into_inner();
+        let first_nibble = recipient_k.get_nibble(0);
+        let other_k = (2..)
+            .map(|b| AccountNibbles::from_addr(&Address::repeat_byte(b)).into_inner())
+            .find(|k| k.get_nibble(0) != first_nibble)
+            .unwrap();
+
+        // The subtrie holding the recipient got collapsed into a single hash node.
+        let mut state_trie = HashedPartialTrie::default();
+        state_trie
+            .insert(other_k, rlp::encode(&AccountRlp::default()).to_vec())
+            .unwrap();
+        state_trie
+            .insert(Nibbles::from_nibble(first_nibble), H256::repeat_byte(9))
+            .unwrap();
+
+        let sub_trie = create_dummy_gen_input_with_state_addrs_accessed(
+            &block_input().other_data,
+            &ExtraBlockData {
+                checkpoint_state_trie_root: H256::zero(),
+                txn_number_before: U256::zero(),
+                txn_number_after: U256::zero(),
+                gas_used_before: U256::zero(),
+                gas_used_after: U256::zero(),
+            },
+            &PartialTrieState {
+                state: state_trie.clone(),
+                ..Default::default()
+            },
+            once(hash(Address::repeat_byte(1).as_bytes())),
+        )
+        .unwrap()
+        .tries
+        .state_trie;
+
+        assert_eq!(sub_trie.hash(), state_trie.hash());
+        assert!(matches!(
+            path_for_query(&sub_trie, recipient_k, false).last(),
+            Some(TrieSegment::Hash)
+        ));
+
+        // Additional paths still need to be expandable.
+        assert!(create_minimal_state_partial_trie(
+            &state_trie,
+            iter::empty(),
+            once(recipient_k),
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn missing_trie_nodes_are_fetched_from_the_node_provider() {
+        let keys: Vec<_> = (1..=4)
+            .map(|b| AccountNibbles::from_addr(&Address::repeat_byte(b)).into_inner())
+            .collect();
+        let mut full_trie = HashedPartialTrie::default();
+        for k in &keys {
+            full_trie
+                .insert(*k, rlp::encode(&AccountRlp::default()).to_vec())
+                .unwrap();
+        }
+
+        // Only the first account is known, the others are hashed out.
+        let partial_trie = create_trie_subset(&full_trie, once(keys[0])).unwrap();
+        let subset = |node_provider: Option<&TrieNodeProvider>| {
+            create_trie_subset_wrapped(
+                &partial_trie,
+                keys[1..].iter().copied(),
+                TrieType::State,
+                node_provider,
+            )
+        };
+
+        let err = subset(Some(&TrieNodeProvider::new(|_| None))).unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::MissingKeysCreatingSubPartialTrie(_, TrieType::State)
+        ));
+
+        let nodes: HashMap<_, _> = RlpEncodedTrie::from_trie(&full_trie)
+            .nodes
+            .into_iter()
+            .map(|node| (hash(&node), node))
+            .collect();
+        let node_provider = TrieNodeProvider::new(move |h| nodes.get(&h).cloned());
+        let sub_trie = subset(Some(&node_provider)).unwrap();
+
+        assert_eq!(sub_trie.hash(), full_trie.hash());
+        for k in &keys[1..] {
+            assert!(sub_trie.get(*k).is_some());
+        }
+    }
+
+    #[test]
+    fn withdrawals_without_ir_entries_are_rejected() {
+        let err = ProcessedBlockTrace::add_withdrawals_to_txns(
+            &mut [],
+            &mut PartialTrieState::default(),
+            vec![(Address::zero(), 1.into())],
+            false,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::EmptyIrWithWithdrawals(1)
+        ));
+    }
+
+    #[test]
+    fn withdrawals_of_an_empty_block_are_carried_by_the_last_ir_entry() {
+        let addr = Address::repeat_byte(1);
+        let h_addr = hash(addr.as_bytes());
+        let withdrawals = vec![(addr, U256::from(7))];
+
+        let mut state = HashedPartialTrie::default();
+        state
+            .insert(
+                Nibbles::from_h256_be(h_addr),
+                rlp::encode(&AccountRlp::default()).to_vec(),
+            )
+            .unwrap();
+        let mut final_state = state.clone();
+        ProcessedBlockTrace::update_trie_state_from_withdrawals(
+            once((addr, h_addr, U256::from(7))),
+            &mut final_state,
+            false,
+        )
+        .unwrap();
+
+        let mut other_data = block_input().other_data;
+        other_data.b_data.parent_beacon_block_root = None;
+        other_data.b_data.withdrawals = withdrawals.clone();
+        other_data.final_state_trie_root = Some(final_state.hash());
+
+        let decode = |min_len| {
+            let block = ProcessedBlockTrace {
+                tries: PartialTriePreImages {
+                    state: state.clone(),
+                    storage: HashMap::default(),
+                },
+                txn_info: Vec::new(),
+                withdrawals: withdrawals.clone(),
+            };
+            let p_meta = ProcessingMeta::new(resolve_code_hash_fn).with_ir_padding(IrPadding {
+                min_len,
+                multiple_of: 1,
+            });
+            block
+                .into_txn_proof_gen_ir_with_dummy_flags(&p_meta, other_data.clone())
+                .unwrap()
+        };
+
+        for min_len in [1, 2] {
+            let (txn_ir, is_dummy) = decode(min_len);
+            assert_eq!(txn_ir.len(), min_len);
+
+            // The entry carrying the withdrawals is not a dummy.
+            let mut expected_is_dummy = vec![true; min_len - 1];
+            expected_is_dummy.push(false);
+            assert_eq!(is_dummy, expected_is_dummy);
+
+            let (last, dummies) = txn_ir.split_last().unwrap();
+            assert_eq!(last.withdrawals, withdrawals);
+            assert_eq!(last.trie_roots_after.state_root, final_state.hash());
+            for dummy in dummies {
+                assert!(dummy.withdrawals.is_empty());
+                assert_eq!(dummy.trie_roots_after.state_root, state.hash());
+            }
+        }
+    }
+
+    #[test]
+    fn storage_tries_never_accessed_can_be_omitted_from_the_pre_images() {
+        let slot = storage_slot(1);
+        let mut storage_trie = HashedPartialTrie::default();
+        storage_trie
+            .insert(
+                StorageNibbles::from_slot(&slot).into_inner(),
+                rlp::encode(&U256::one()).to_vec(),
+            )
+            .unwrap();
+
+        // Every account has storage, but only the one of the first account is in
+        // the pre-images, as the other ones are never accessed.
+        let h_addrs: Vec<_> = (1..=8)
+            .map(|b| hash(Address::repeat_byte(b).as_bytes()))
+            .collect();
+        let mut state = HashedPartialTrie::default();
+        for h_addr in &h_addrs {
+            let account = AccountRlp {
+                storage_root: storage_trie.hash(),
+                ..Default::default()
+            };
+            state
+                .insert(
+                    Nibbles::from_h256_be(*h_addr),
+                    rlp::encode(&account).to_vec(),
+                )
+                .unwrap();
+        }
+
+        let mut other_data = block_input().other_data;
+        other_data.b_data.parent_beacon_block_root = None;
+        other_data.b_data.withdrawals = Vec::new();
+        other_data.final_state_trie_root = Some(state.hash());
+
+        let block = ProcessedBlockTrace {
+            tries: PartialTriePreImages {
+                state: state.clone(),
+                storage: HashMap::from([(h_addrs[0], storage_trie.clone())]),
+            },
+            txn_info: vec![ProcessedTxnInfo {
+                nodes_used_by_txn: NodesUsedByTxn::default()
+                    .with_state_access(h_addrs[0])
+                    .with_storage_reads(h_addrs[0], [slot]),
+                contract_code_accessed: HashMap::new(),
+                meta: TxnMetaState::new(Some(vec![1]), vec![2], 21000),
+            }],
+            withdrawals: Vec::new(),
+        };
+
+        let txn_ir = block
+            .into_txn_proof_gen_ir(&ProcessingMeta::new(resolve_code_hash_fn), other_data)
+            .unwrap();
+
+        assert_eq!(txn_ir.len(), 2);
+        for gen_inputs in &txn_ir {
+            assert_eq!(gen_inputs.tries.state_trie.hash(), state.hash());
+            let [(h_addr, trie)] = &gen_inputs.tries.storage_tries[..] else {
+                panic!("Only the accessed storage trie should be passed on");
+            };
+            assert_eq!(*h_addr, h_addrs[0]);
+            assert_eq!(trie.hash(), storage_trie.hash());
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialized_errors_keep_their_context_fields() {
+        let round_trip = |reason: TraceParsingErrorReason| {
+            let mut err = TraceParsingError::new(reason);
+            err.set_block_num(U256::from(19240650))
+                .set_block_chain_id(U256::one())
+                .set_txn_idx(3)
+                .set_addr(Address::repeat_byte(1))
+                .set_h_addr(H256::repeat_byte(2))
+                .set_slot(U512::from(4))
+                .set_slot_value(U512::MAX)
+                .with_context("endpoint", "http://localhost:8545".to_string());
+
+            let json = serde_json::to_string(&err).unwrap();
+            let decoded: TraceParsingError = serde_json::from_str(&json).unwrap();
+
+            assert_eq!(decoded.block_num(), err.block_num());
+            assert_eq!(decoded.block_chain_id(), err.block_chain_id());
+            assert_eq!(decoded.txn_idx(), err.txn_idx());
+            assert_eq!(decoded.addr(), err.addr());
+            assert_eq!(decoded.h_addr(), err.h_addr());
+            assert_eq!(decoded.slot(), err.slot());
+            assert_eq!(decoded.slot_value(), err.slot_value());
+            assert!(decoded.context().eq(err.context()));
+            assert_eq!(decoded.to_string(), err.to_string());
+
+            decoded
+        };
+
+        let missing_trie =
+            || TraceParsingErrorReason::MissingAccountStorageTrie(H256::repeat_byte(2));
+        assert_eq!(round_trip(missing_trie()).reason(), &missing_trie());
+
+        // Only the message of a compact parsing error is kept.
+        let decoded = round_trip(TraceParsingErrorReason::CompactParsingError(
+            CompactParsingError::InvalidOpcode(0xff),
+        ));
+        assert_eq!(
+            decoded.reason(),
+            &TraceParsingErrorReason::CompactParsingError(CompactParsingError::Message(
+                CompactParsingError::InvalidOpcode(0xff).to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn miscounted_txn_number_after_is_rejected() {
+        let other_data = block_input().other_data;
+        let extra_data = ExtraBlockData {
+            checkpoint_state_trie_root: other_data.checkpoint_state_trie_root,
+            txn_number_before: 2.into(),
+            txn_number_after: 3.into(),
+            gas_used_before: U256::zero(),
+            gas_used_after: U256::zero(),
+        };
+
+        verify_txn_number_after(&extra_data, 3, &other_data).unwrap();
+        assert!(matches!(
+            verify_txn_number_after(&extra_data, 2, &other_data)
+                .unwrap_err()
+                .reason(),
+            TraceParsingErrorReason::TxnNumberMismatch { expected: 2, got }
+                if *got == 3.into()
+        ));
+    }
+
+    #[test]
+    fn malformed_block_hashes_are_rejected() {
+        let mut other_data = block_input().other_data;
+        other_data.b_data.b_hashes.prev_hashes.pop();
+        assert!(matches!(
+            verify_block_hashes(&other_data).unwrap_err().reason(),
+            TraceParsingErrorReason::WrongNumberOfPrevBlockHashes(255)
+        ));
+
+        let mut other_data = block_input().other_data;
+        other_data.b_data.b_hashes.prev_hashes[255] = H256::zero();
+        assert!(matches!(
+            verify_block_hashes(&other_data).unwrap_err().reason(),
+            TraceParsingErrorReason::MissingPrevBlockHash { idx: 255, .. }
+        ));
+    }
+
+    #[test]
+    fn block_hashes_before_genesis_must_be_empty() {
+        let mut other_data = block_input().other_data;
+        other_data.b_data.b_meta.block_number = 2.into();
+        other_data.b_data.b_hashes.prev_hashes = vec![H256::zero(); 256];
+        other_data.b_data.b_hashes.prev_hashes[254] = H256::repeat_byte(1);
+        other_data.b_data.b_hashes.prev_hashes[255] = H256::repeat_byte(2);
+        verify_block_hashes(&other_data).unwrap();
+
+        other_data.b_data.b_hashes.prev_hashes[253] = H256::repeat_byte(3);
+        assert!(matches!(
+            verify_block_hashes(&other_data).unwrap_err().reason(),
+            TraceParsingErrorReason::UnexpectedPrevBlockHash { idx: 253, .. }
+        ));
+    }
+
+    #[test]
+    fn storage_tries_are_sorted_by_hashed_address() {
+        let h_addrs = [3, 1, 4, 2].map(H256::repeat_byte);
+        let storage = h_addrs
+            .iter()
+            .map(|h_addr| (*h_addr, HashedPartialTrie::default()))
+            .collect();
+        let accesses: Vec<_> = h_addrs.iter().map(|h_addr| (*h_addr, vec![])).collect();
+
+        let mut sorted_h_addrs = h_addrs.to_vec();
+        sorted_h_addrs.sort();
+        let h_addrs_of = |storage_tries: &[(H256, HashedPartialTrie)]| {
+            storage_tries
+                .iter()
+                .map(|(h_addr, _)| *h_addr)
+                .collect::<Vec<_>>()
+        };
+
+        let minimal_storage_tries =
+            create_minimal_storage_partial_tries(&storage, accesses.iter(), &HashMap::new(), None)
+                .unwrap();
+        assert_eq!(h_addrs_of(&minimal_storage_tries), sorted_h_addrs);
+
+        let trie_state = PartialTrieState {
+            storage,
+            ..Default::default()
+        };
+        let dummy_trie_inputs =
+            create_dummy_proof_trie_inputs(&trie_state, HashedPartialTrie::default());
+        assert_eq!(h_addrs_of(&dummy_trie_inputs.storage_tries), sorted_h_addrs);
+    }
+
+    #[test]
+    fn empty_trie_and_account_constants_match_their_encoding() {
+        assert_eq!(HashedPartialTrie::default().hash(), EMPTY_TRIE_HASH);
+        assert_eq!(
+            rlp::encode(&AccountRlp::default()).as_ref(),
+            EMPTY_ACCOUNT_BYTES_RLPED
+        );
+    }
+
+    #[test]
+    fn fully_hashed_out_sub_trie_matches_an_empty_trie_subset() {
+        let mut trie = HashedPartialTrie::default();
+        for i in 1..=3 {
+            trie.insert(
+                Nibbles::from_h256_be(H256::repeat_byte(i)),
+                EMPTY_ACCOUNT_BYTES_RLPED.to_vec(),
+            )
+            .unwrap();
+        }
+
+        for trie in [HashedPartialTrie::default(), trie] {
+            let hashed_out = create_fully_hashed_out_sub_partial_trie(&trie);
+            assert_eq!(
+                hashed_out,
+                create_trie_subset(&trie, iter::empty::<Nibbles>()).unwrap()
+            );
+            assert_eq!(trie_root_hash(&hashed_out), trie.hash());
+        }
+    }
+
+    #[test]
+    fn init_any_needed_empty_storage_tries_seeds_missing_storage_tries() {
+        let h_addr_with_storage = H256::repeat_byte(1);
+        let h_addr_without_storage = H256::repeat_byte(2);
+        let h_addr_already_present = H256::repeat_byte(3);
+        let s_root = H256::repeat_byte(4);
+
+        let mut present_trie = HashedPartialTrie::default();
+        present_trie
+            .insert(Nibbles::from_h256_be(H256::repeat_byte(5)), vec![1])
+            .unwrap();
+        let present_trie_hash = present_trie.hash();
+
+        let mut storage_tries = HashMap::from([(h_addr_already_present, present_trie)]);
+        let mut state = HashedPartialTrie::default();
+        for (h_addr, storage_root) in [
+            (h_addr_with_storage, s_root),
+            (h_addr_without_storage, EMPTY_TRIE_HASH),
+        ] {
+            let account = AccountRlp {
+                storage_root,
+                ..Default::default()
+            };
+            state
+                .insert(
+                    Nibbles::from_h256_be(h_addr),
+                    rlp::encode(&account).to_vec(),
+                )
+                .unwrap();
+        }
+
+        ProcessedBlockTrace::init_any_needed_empty_storage_tries(
+            &mut storage_tries,
+            &state,
+            [
+                h_addr_with_storage,
+                h_addr_without_storage,
+                h_addr_already_present,
+                H256::repeat_byte(6),
+            ]
+            .iter(),
+        )
+        .unwrap();
+
+        assert_eq!(storage_tries.len(), 4);
+        assert_eq!(storage_tries[&h_addr_with_storage].hash(), s_root);
+        assert_eq!(storage_tries[&H256::repeat_byte(6)].hash(), EMPTY_TRIE_HASH);
+        assert_eq!(
+            storage_tries[&h_addr_without_storage].hash(),
+            EMPTY_TRIE_HASH
+        );
+        assert_eq!(
+            storage_tries[&h_addr_already_present].hash(),
+            present_trie_hash
+        );
+    }
+
+    #[test]
+    fn verify_trie_roots_reports_first_diverging_trie() {
+        let expected = TrieRoots {
+            state_root: H256::repeat_byte(1),
+            transactions_root: H256::repeat_byte(2),
+            receipts_root: H256::repeat_byte(3),
+        };
+        assert!(verify_trie_roots(&expected, &expected, true).is_ok());
+
+        let got = TrieRoots {
+            receipts_root: H256::repeat_byte(4),
+            ..expected.clone()
+        };
+        let err = verify_trie_roots(&expected, &got, true).unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::TrieRootMismatch {
+                trie: TrieType::Receipt,
+                expected,
+                got,
+            } if *expected == H256::repeat_byte(3) && *got == H256::repeat_byte(4)
+        ));
+    }
+
+    #[test]
+    fn cancun_block_starts_from_the_parent_state() {
+        // The beacon roots contract, with a root already in its ring buffers.
+        let beacon_roots_h_addr =
+            hash(&hex::decode("000F3df6D732807Ef1319fB7B8bB8522d0Beac02").unwrap());
+        let mut beacon_roots_storage = HashedPartialTrie::default();
+        beacon_roots_storage
+            .insert(
+                StorageNibbles::from_slot(&storage_slot(1)).into_inner(),
+                rlp::encode(&U256::from(1_710_338_123)).to_vec(),
+            )
+            .unwrap();
+
+        let sender_h_addr = hash(Address::repeat_byte(1).as_bytes());
+        let mut state = HashedPartialTrie::default();
+        for (h_addr, storage_root) in [
+            (beacon_roots_h_addr, beacon_roots_storage.hash()),
+            (sender_h_addr, EMPTY_TRIE_HASH),
+        ] {
+            let account = AccountRlp {
+                storage_root,
+                ..Default::default()
+            };
+            state
+                .insert(
+                    Nibbles::from_h256_be(h_addr),
+                    rlp::encode(&account).to_vec(),
+                )
+                .unwrap();
+        }
+
+        let mut other_data = block_input().other_data;
+        // Cancun activation timestamp on mainnet.
+        other_data.b_data.b_meta.block_timestamp = U256::from(1_710_338_135);
+        other_data.b_data.parent_beacon_block_root = Some(H256::repeat_byte(0xab));
+        other_data.b_data.withdrawals = Vec::new();
+        other_data.final_state_trie_root = Some(state.hash());
+
+        let block = ProcessedBlockTrace {
+            tries: PartialTriePreImages {
+                state: state.clone(),
+                storage: HashMap::from([(beacon_roots_h_addr, beacon_roots_storage)]),
+            },
+            txn_info: vec![ProcessedTxnInfo {
+                nodes_used_by_txn: NodesUsedByTxn::default().with_state_access(sender_h_addr),
+                contract_code_accessed: HashMap::new(),
+                meta: TxnMetaState::new(Some(vec![1]), vec![2], 21000),
+            }],
+            withdrawals: Vec::new(),
+        };
+
+        // The final state root is checked as well, so this also fails if the
+        // beacon root writes get applied.
+        let txn_ir = block
+            .into_txn_proof_gen_ir(&ProcessingMeta::new(resolve_code_hash_fn), other_data)
+            .unwrap();
+        assert_eq!(txn_ir[0].tries.state_trie.hash(), state.hash());
+    }
+
+    #[test]
+    fn withdrawal_to_a_missing_account_creates_it_only_when_enabled() {
+        let addr = Address::repeat_byte(1);
+        let h_addr = hash(addr.as_bytes());
+        let withdrawal = || once((addr, h_addr, U256::from(7)));
+
+        let mut state = HashedPartialTrie::default();
+        let err = ProcessedBlockTrace::update_trie_state_from_withdrawals(
+            withdrawal(),
+            &mut state,
+            false,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::MissingWithdrawalAccount(..)
+        ));
+
+        ProcessedBlockTrace::update_trie_state_from_withdrawals(withdrawal(), &mut state, true)
+            .unwrap();
+        let acc_data =
+            account_from_rlped_bytes(state.get(Nibbles::from_h256_be(h_addr)).unwrap()).unwrap();
+        let default_acc_data = AccountRlp::default();
+        assert_eq!(acc_data.balance, U256::from(7));
+        assert_eq!(acc_data.nonce, default_acc_data.nonce);
+        assert_eq!(acc_data.storage_root, default_acc_data.storage_root);
+        assert_eq!(acc_data.code_hash, default_acc_data.code_hash);
+    }
+
+    #[test]
+    fn withdrawal_overflowing_the_balance_is_rejected() {
+        let addr = Address::repeat_byte(1);
+        let h_addr = hash(addr.as_bytes());
+        let balance = U256::MAX - 1;
+
+        let mut state = HashedPartialTrie::default();
+        state
+            .insert(
+                Nibbles::from_h256_be(h_addr),
+                rlp::encode(&AccountRlp {
+                    balance,
+                    ..Default::default()
+                })
+                .to_vec(),
+            )
+            .unwrap();
+
+        let err = ProcessedBlockTrace::update_trie_state_from_withdrawals(
+            once((addr, h_addr, U256::from(2))),
+            &mut state,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.reason(),
+            &TraceParsingErrorReason::WithdrawalBalanceOverflow {
+                addr,
+                balance,
+                amt: U256::from(2),
+            }
+        );
+    }
+
+    #[test]
+    fn receipt_trie_is_left_empty_when_not_built() {
+        let meta = TxnMetaState::new(Some(vec![1]), vec![2], 21000);
+        let mut trie_state = PartialTrieState::default();
+        ProcessedBlockTrace::update_txn_and_receipt_tries(&mut trie_state, &meta, 0, false)
+            .unwrap();
+
+        let got = calculate_trie_input_hashes(&trie_state);
+        assert_eq!(got.receipts_root, EMPTY_TRIE_HASH);
+        assert_ne!(got.transactions_root, EMPTY_TRIE_HASH);
+
+        let expected = TrieRoots {
+            receipts_root: H256::repeat_byte(1),
+            ..got.clone()
+        };
+        assert!(verify_trie_roots(&expected, &got, false).is_ok());
+    }
+
+    fn storage_paths_to_not_hash_after_slot_writes(
+        slot_writes: Vec<(HashedStorageAddrNibbles, Vec<u8>)>,
+    ) -> HashMap<H256, Vec<Nibbles>> {
+        let h_addr = H256::repeat_byte(1);
+
+        let mut storage_trie = HashedPartialTrie::default();
+        for slot in [storage_slot(1), storage_slot(2)] {
+            storage_trie
+                .insert(
+                    Nibbles::from_h256_be(hash(&slot.bytes_be())),
+                    rlp::encode(&U256::one()).to_vec(),
+                )
+                .unwrap();
+        }
+
+        let mut trie_state = PartialTrieState {
+            storage: HashMap::from([(h_addr, storage_trie)]),
+            ..Default::default()
+        };
+        let deltas = NodesUsedByTxn {
+            storage_writes: vec![(h_addr, slot_writes)],
+            ..Default::default()
+        };
+
+        ProcessedBlockTrace::apply_deltas_to_trie_state(&mut trie_state, &deltas, None, None)
+            .unwrap()
+            .additional_storage_trie_paths_to_not_hash
+    }
+
+    fn storage_slot(slot: u64) -> HashedStorageAddrNibbles {
+        Nibbles::from_h256_be(H256::from_low_u64_be(slot))
+    }
+
+    #[test]
+    fn slot_written_then_zeroed_is_applied_as_a_single_delete() {
+        let single_delete = storage_paths_to_not_hash_after_slot_writes(vec![(
+            storage_slot(1),
+            ZERO_STORAGE_SLOT_VAL_RLPED.to_vec(),
+        )]);
+        assert!(!single_delete.is_empty());
+
+        let written_then_zeroed = storage_paths_to_not_hash_after_slot_writes(vec![
+            (storage_slot(1), rlp::encode(&U256::from(5)).to_vec()),
+            (storage_slot(1), ZERO_STORAGE_SLOT_VAL_RLPED.to_vec()),
+        ]);
+        assert_eq!(written_then_zeroed, single_delete);
+    }
+
+    #[test]
+    fn branch_collapsing_into_a_leaf_or_an_extension_reports_the_remaining_key() {
+        let delete_collapsing_root = |keys: &[u64]| {
+            let mut trie = HashedPartialTrie::default();
+            for &k in keys {
+                trie.insert(Nibbles::from(k), vec![1]).unwrap();
+            }
+            ProcessedBlockTrace::delete_node_and_report_remaining_key_if_branch_collapsed(
+                &mut trie,
+                &Nibbles::from(0x12_u64),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            delete_collapsing_root(&[0x12, 0x34]),
+            BranchCollapse::Collapsed {
+                remaining_key: Nibbles::from(0x34_u64)
+            }
+        );
+        assert_eq!(
+            delete_collapsing_root(&[0x12, 0x345, 0x346]),
+            BranchCollapse::Collapsed {
+                remaining_key: Nibbles::from(0x34_u64)
+            }
+        );
+        assert_eq!(
+            delete_collapsing_root(&[0x12, 0x34, 0x56]),
+            BranchCollapse::NoCollapse
+        );
+    }
+
+    #[test]
+    fn fee_recipients_are_kept_in_the_minimal_state_trie() {
+        /// Credits the fees to the given accounts only, ignoring the block
+        /// beneficiary.
+        #[derive(Debug)]
+        struct FixedFeeRecipients(Vec<Address>);
+
+        impl FeeModel for FixedFeeRecipients {
+            fn fee_recipients(&self, _beneficiary: Address) -> Vec<Address> {
+                self.0.clone()
+            }
+        }
+
+        let sender = hash(Address::repeat_byte(1).as_bytes());
+        let treasury = Address::repeat_byte(2);
+        let treasury_k = AccountNibbles::from_addr(&treasury).into_inner();
+
+        let mut state = HashedPartialTrie::default();
+        for k in [
+            AccountNibbles::from_hashed_addr(sender).into_inner(),
+            treasury_k,
+        ] {
+            state
+                .insert(k, rlp::encode(&AccountRlp::default()).to_vec())
+                .unwrap();
+        }
+        let tries = PartialTrieState {
+            state,
+            ..Default::default()
+        };
+        let nodes_used = NodesUsedByTxn::default().with_state_access(sender);
+
+        let last_segment_to_treasury = |fee_recipients: Vec<Address>| {
+            let p_meta = ProcessingMeta::new(resolve_code_hash_fn)
+                .with_fee_model(FixedFeeRecipients(fee_recipients));
+            let state_trie = ProcessedBlockTrace::create_minimal_partial_tries_needed_by_txn(
+                &tries,
+                &nodes_used,
+                0,
+                TrieDeltaApplicationOutput::default(),
+                &fee_recipient_hashes(&p_meta, &block_input().other_data),
+                None,
+            )
+            .unwrap()
+            .state_trie;
+
+            path_for_query(&state_trie, treasury_k, true).last()
+        };
+
+        assert!(matches!(
+            last_segment_to_treasury(vec![treasury]),
+            Some(TrieSegment::Leaf(_))
+        ));
+        assert!(matches!(
+            last_segment_to_treasury(vec![]),
+            Some(TrieSegment::Hash)
+        ));
+    }
+
+    #[test]
+    fn slot_zeroed_then_written_does_not_report_a_collapse() {
+        let zeroed_then_written = storage_paths_to_not_hash_after_slot_writes(vec![
+            (storage_slot(1), ZERO_STORAGE_SLOT_VAL_RLPED.to_vec()),
+            (storage_slot(1), rlp::encode(&U256::from(5)).to_vec()),
+        ]);
+        assert!(zeroed_then_written.is_empty());
+    }
+
+    #[test]
+    fn txn_snapshot_only_keeps_the_accessed_storage_tries() {
+        let accessed = H256::repeat_byte(1);
+        let not_accessed = H256::repeat_byte(2);
+
+        let mut storage_trie = HashedPartialTrie::default();
+        storage_trie
+            .insert(
+                Nibbles::from_h256_be(hash(&storage_slot(1).bytes_be())),
+                rlp::encode(&U256::one()).to_vec(),
+            )
+            .unwrap();
+
+        let trie_state = PartialTrieState {
+            storage: HashMap::from([
+                (accessed, storage_trie.clone()),
+                (not_accessed, storage_trie),
+            ]),
+            ..Default::default()
+        };
+        let nodes_used = NodesUsedByTxn {
+            storage_accesses: vec![(accessed, vec![storage_slot(1)])],
+            ..Default::default()
+        };
+
+        let snapshot = trie_state.snapshot_for_txn(&nodes_used);
+
+        assert_eq!(snapshot.state.hash(), trie_state.state.hash());
+        assert_eq!(snapshot.storage.len(), 1);
+        assert_eq!(
+            snapshot.storage[&accessed].hash(),
+            trie_state.storage[&accessed].hash()
+        );
+    }
+
+    #[test]
+    fn self_destruct_of_an_account_without_a_storage_trie_deletes_it() {
+        let destroyed = H256::repeat_byte(1);
+        let remaining = H256::repeat_byte(2);
+
+        let mut trie_state = PartialTrieState::default();
+        for h_addr in [destroyed, remaining] {
+            trie_state
+                .state
+                .insert(
+                    Nibbles::from_h256_be(h_addr),
+                    EMPTY_ACCOUNT_BYTES_RLPED.to_vec(),
+                )
+                .unwrap();
+        }
+        let deltas = NodesUsedByTxn {
+            self_destructed_accounts: vec![destroyed],
+            ..Default::default()
+        };
+
+        let out =
+            ProcessedBlockTrace::apply_deltas_to_trie_state(&mut trie_state, &deltas, None, None)
+                .unwrap();
+
+        assert!(trie_state
+            .state
+            .get(Nibbles::from_h256_be(destroyed))
+            .is_none());
+        assert!(trie_state
+            .state
+            .get(Nibbles::from_h256_be(remaining))
+            .is_some());
+        assert!(!out.additional_state_trie_paths_to_not_hash.is_empty());
+    }
+
+    #[test]
+    fn eip_6780_self_destruct_deletes_an_account_created_in_the_same_txn() {
+        let created = H256::repeat_byte(1);
+        let remaining = H256::repeat_byte(2);
+
+        let mut trie_state = PartialTrieState::default();
+        trie_state
+            .state
+            .insert(
+                Nibbles::from_h256_be(remaining),
+                EMPTY_ACCOUNT_BYTES_RLPED.to_vec(),
+            )
+            .unwrap();
+        let deltas = NodesUsedByTxn::default()
+            .with_state_write(
+                created,
+                StateTrieWrites::default()
+                    .with_nonce(U256::one())
+                    .with_code_hash(hash(&[0x60, 0x00, 0xff])),
+            )
+            .with_self_destructed_account(created)
+            .with_eip_6780(true);
+
+        ProcessedBlockTrace::apply_deltas_to_trie_state(&mut trie_state, &deltas, None, None)
+            .unwrap();
+
+        assert!(trie_state
+            .state
+            .get(Nibbles::from_h256_be(created))
+            .is_none());
+        assert!(trie_state
+            .state
+            .get(Nibbles::from_h256_be(remaining))
+            .is_some());
+    }
+
+    #[test]
+    fn eip_6780_self_destruct_keeps_a_pre_existing_contract() {
+        let destroyed = H256::repeat_byte(1);
+        let c_hash = hash(&[0x60, 0x00, 0xff]);
+        let account_before = AccountRlp {
+            balance: 5.into(),
+            code_hash: c_hash,
+            ..Default::default()
+        };
+
+        let self_destruct = |eip_6780, written_balance: Option<U256>| {
+            let mut trie_state = PartialTrieState::default();
+            trie_state
+                .state
+                .insert(
+                    Nibbles::from_h256_be(destroyed),
+                    rlp::encode(&account_before).to_vec(),
+                )
+                .unwrap();
+            let mut deltas = NodesUsedByTxn::default()
+                .with_self_destructed_account(destroyed)
+                .with_eip_6780(eip_6780);
+            if let Some(balance) = written_balance {
+                deltas = deltas
+                    .with_state_write(destroyed, StateTrieWrites::default().with_balance(balance));
+            }
+
+            ProcessedBlockTrace::apply_deltas_to_trie_state(&mut trie_state, &deltas, None, None)
+                .unwrap();
+            trie_state
+                .state
+                .get(Nibbles::from_h256_be(destroyed))
+                .map(|bytes| account_from_rlped_bytes(bytes).unwrap())
+        };
+
+        // The balance is swept even though the trace doesn't write it.
+        let account_after = self_destruct(true, None).unwrap();
+        assert_eq!(account_after.balance, U256::zero());
+        assert_eq!(account_after.code_hash, c_hash);
+
+        // The contract is its own beneficiary.
+        let account_after = self_destruct(true, Some(5.into())).unwrap();
+        assert_eq!(account_after.balance, 5.into());
+
+        assert!(self_destruct(false, None).is_none());
+    }
+
+    #[test]
+    fn validate_ir_lists_every_violated_invariant() {
+        let prover_input = block_input();
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn);
+
+        let mut gen_inputs = prover_input
+            .block_trace
+            .into_txn_proof_gen_ir(&p_meta, prover_input.other_data)
+            .unwrap();
+        validate_ir(&gen_inputs).unwrap();
+
+        gen_inputs[1].gas_used_before += U256::one();
+        gen_inputs[2].tries.state_trie = HashedPartialTrie::default();
+
+        let err = validate_ir(&gen_inputs).unwrap_err();
+        let TraceParsingErrorReason::InvalidIr(violations) = err.reason() else {
+            panic!("Unexpected error: {err}");
+        };
+        assert_eq!(
+            violations,
+            &vec![
+                IrInvariantViolation::GasUsedDiscontinuity {
+                    idx: 1,
+                    expected: gen_inputs[0].gas_used_after,
+                    got: gen_inputs[1].gas_used_before,
+                },
+                IrInvariantViolation::TrieRootDiscontinuity {
+                    idx: 2,
+                    trie: TrieType::State,
+                    expected: gen_inputs[1].trie_roots_after.state_root,
+                    got: EMPTY_TRIE_HASH,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn every_error_reason_has_a_distinct_code() {
+        let reasons = [
+            TraceParsingErrorReason::AccountDecode(String::new(), String::new()),
+            TraceParsingErrorReason::ReceiptDecode(String::new()),
+            TraceParsingErrorReason::ReceiptsBloomMismatch {
+                expected: Bloom::default(),
+                got: Bloom::default(),
+            },
+            TraceParsingErrorReason::BlockWitnessDecode(String::new()),
+            TraceParsingErrorReason::RlpDecode(String::new()),
+            TraceParsingErrorReason::MissingAccountStorageTrie(H256::zero()),
+            TraceParsingErrorReason::StorageRootMismatch {
+                h_addr: H256::zero(),
+                in_account: H256::zero(),
+                in_image: H256::zero(),
+            },
+            TraceParsingErrorReason::CodeHashMismatch {
+                expected: H256::zero(),
+                got: H256::zero(),
+            },
+            TraceParsingErrorReason::MissingPreImageRoot(H256::zero()),
+            TraceParsingErrorReason::NonExistentTrieEntry(
+                TrieType::State,
+                Nibbles::default(),
+                H256::zero(),
+            ),
+            TraceParsingErrorReason::MissingKeysCreatingSubPartialTrie(
+                Nibbles::default(),
+                TrieType::State,
+            ),
+            TraceParsingErrorReason::MissingWithdrawalAccount(
+                Address::zero(),
+                H256::zero(),
+                U256::zero(),
+            ),
+            TraceParsingErrorReason::WithdrawalBalanceOverflow {
+                addr: Address::zero(),
+                balance: U256::zero(),
+                amt: U256::zero(),
+            },
+            TraceParsingErrorReason::EmptyIrWithWithdrawals(0),
+            TraceParsingErrorReason::GasOverflow(0, U256::zero(), U256::zero()),
+            TraceParsingErrorReason::GasUsedOutOfRange {
+                entry_idx: 0,
+                gas_used_before: U256::zero(),
+                gas_used_after: U256::zero(),
+            },
+            TraceParsingErrorReason::TxnNumberOverflow(0, U256::zero()),
+            TraceParsingErrorReason::ZeroGasTxn(0),
+            TraceParsingErrorReason::Cancelled {
+                last_completed_txn: None,
+            },
+            TraceParsingErrorReason::TxnNumberMismatch {
+                expected: 0,
+                got: U256::zero(),
+            },
+            TraceParsingErrorReason::TrieRootMismatch {
+                trie: TrieType::State,
+                expected: H256::zero(),
+                got: H256::zero(),
+            },
+            TraceParsingErrorReason::FinalStateRootMismatch {
+                expected: H256::zero(),
+                got: H256::zero(),
+            },
+            TraceParsingErrorReason::WrongNumberOfPrevBlockHashes(0),
+            TraceParsingErrorReason::UnexpectedPrevBlockHash {
+                idx: 0,
+                block_num: U256::zero(),
+            },
+            TraceParsingErrorReason::MissingPrevBlockHash {
+                idx: 0,
+                block_num: U256::zero(),
+            },
+            TraceParsingErrorReason::TrieOpError(TrieOpError::HashNodeInsertError(H256::zero())),
+            TraceParsingErrorReason::InvalidIr(vec![]),
+            TraceParsingErrorReason::CompactParsingError(CompactParsingError::MissingHeader),
+        ];
+        let codes: std::collections::HashSet<_> =
+            reasons.iter().map(|reason| reason.code()).collect();
+        assert_eq!(codes.len(), reasons.len());
+
+        let trie_types = [
+            TrieType::State,
+            TrieType::Storage,
+            TrieType::Receipt,
+            TrieType::Txn,
+            TrieType::Code,
+        ];
+        let codes: std::collections::HashSet<_> =
+            trie_types.iter().map(|trie| trie.code()).collect();
+        assert_eq!(codes.len(), trie_types.len());
+    }
+
+    #[test]
+    fn error_reasons_can_be_bucketed_by_trie_type_and_code() {
+        let missing_keys = |trie| {
+            TraceParsingErrorReason::MissingKeysCreatingSubPartialTrie(Nibbles::default(), trie)
+        };
+        let reasons = [
+            missing_keys(TrieType::State),
+            missing_keys(TrieType::Storage),
+            missing_keys(TrieType::State),
+        ];
+        assert_eq!(reasons[0], reasons[2]);
+        assert_ne!(reasons[0], reasons[1]);
+
+        let mut buckets: HashMap<(TrieType, &str), usize> = HashMap::new();
+        for reason in &reasons {
+            if let TraceParsingErrorReason::MissingKeysCreatingSubPartialTrie(_, trie) = reason {
+                *buckets.entry((*trie, reason.code())).or_default() += 1;
+            }
+        }
+        assert_eq!(
+            buckets,
+            HashMap::from([
+                ((TrieType::State, "missing_keys_creating_sub_trie"), 2),
+                ((TrieType::Storage, "missing_keys_creating_sub_trie"), 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn error_context_is_displayed_after_the_built_in_fields() {
+        let mut err = TraceParsingError::new(TraceParsingErrorReason::RlpDecode("bad".into()));
+        err.set_txn_idx(3)
+            .with_context("Endpoint", "http://localhost:8545".into());
+
+        assert_eq!(
+            err.context().collect::<Vec<_>>(),
+            vec![("Endpoint", "http://localhost:8545")]
+        );
+        assert!(err
+            .to_string()
+            .ends_with("Txn idx: 3\nEndpoint: http://localhost:8545\n"));
+    }
+
+    #[test]
+    fn block_range_of_a_single_block_matches_eager_ir() {
+        let prover_input = block_input();
+        let p_meta = ProcessingMeta::new(resolve_code_hash_fn);
+
+        let eager = prover_input
+            .block_trace
+            .clone()
+            .into_txn_proof_gen_ir(&p_meta, prover_input.other_data.clone())
+            .unwrap();
+        let processed = prover_input
+            .block_trace
+            .into_processed(&p_meta, &prover_input.other_data)
+            .unwrap();
+        let range = ProcessedBlockTrace::process_block_range(
+            once((processed, prover_input.other_data)),
+            &p_meta,
+        )
+        .unwrap();
+
+        assert_eq!(range.len(), 1);
+        assert_eq!(
+            serde_json::to_value(&range[0]).unwrap(),
+            serde_json::to_value(&eager).unwrap()
+        );
+    }
+
+    #[test]
+    fn carried_tries_are_filled_in_from_the_next_pre_images() {
+        let keys: Vec<_> = (1..=4)
+            .map(|b| AccountNibbles::from_addr(&Address::repeat_byte(b)).into_inner())
+            .collect();
+        let mut full_trie = HashedPartialTrie::default();
+        for k in &keys {
+            full_trie
+                .insert(*k, rlp::encode(&AccountRlp::default()).to_vec())
+                .unwrap();
+        }
+        let carried_trie = create_trie_subset(&full_trie, once(keys[0])).unwrap();
+
+        let tries = carry_tries_forward(
+            PartialTrieState {
+                state: carried_trie.clone(),
+                ..Default::default()
+            },
+            PartialTriePreImages {
+                state: full_trie.clone(),
+                storage: HashMap::default(),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(tries.state, full_trie);
+
+        let err = carry_tries_forward(
+            PartialTrieState {
+                state: carried_trie,
+                ..Default::default()
+            },
+            PartialTriePreImages::default(),
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::TrieRootMismatch {
+                trie: TrieType::State,
+                ..
+            }
+        ));
+    }
 }
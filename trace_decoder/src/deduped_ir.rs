@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use evm_arithmetization::GenerationInputs;
+use serde::{Deserialize, Serialize};
+
+use crate::decoding::TraceParsingResult;
+use crate::processed_block_trace::ProcessingMeta;
+use crate::trace_protocol::BlockTrace;
+use crate::types::{CodeHash, CodeHashResolveFunc, OtherBlockData};
+
+/// The [GenerationInputs] of all the txns of a block, where each contract code
+/// accessed by the block is stored only once.
+///
+/// Every [GenerationInputs] otherwise embeds all the contract code its txn
+/// accessed, so a contract called by many txns of the block ends up being
+/// duplicated as many times. Here, the per-txn entries only reference the code
+/// they need by hash, and [`DedupedBlockIr::expand`] restores the regular
+/// [GenerationInputs] before they get handed to the prover.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DedupedBlockIr {
+    contract_code: HashMap<CodeHash, Vec<u8>>,
+    txn_ir: Vec<DedupedTxnIr>,
+}
+
+/// The [GenerationInputs] of a single txn, stripped of its contract code.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct DedupedTxnIr {
+    /// Always has an empty `contract_code`.
+    gen_inputs: GenerationInputs,
+    contract_code_hashes: Vec<CodeHash>,
+}
+
+impl DedupedBlockIr {
+    /// Appends the [GenerationInputs] of the next txn of the block, moving its
+    /// contract code to the block-level code.
+    pub fn push(&mut self, mut gen_inputs: GenerationInputs) {
+        let contract_code_hashes = gen_inputs
+            .contract_code
+            .drain()
+            .map(|(c_hash, code)| {
+                self.contract_code.entry(c_hash).or_insert(code);
+                c_hash
+            })
+            .collect();
+
+        self.txn_ir.push(DedupedTxnIr {
+            gen_inputs,
+            contract_code_hashes,
+        });
+    }
+
+    /// Returns the number of txn entries.
+    pub fn len(&self) -> usize {
+        self.txn_ir.len()
+    }
+
+    /// Returns `true` if there are no txn entries.
+    pub fn is_empty(&self) -> bool {
+        self.txn_ir.is_empty()
+    }
+
+    /// Returns the unique contract code accessed by the block, by code hash.
+    pub fn contract_code(&self) -> &HashMap<CodeHash, Vec<u8>> {
+        &self.contract_code
+    }
+
+    /// Returns the regular [GenerationInputs] of the txn entry at `idx`, with
+    /// all the contract code it accessed.
+    pub fn expand_txn(&self, idx: usize) -> Option<GenerationInputs> {
+        self.txn_ir
+            .get(idx)
+            .map(|txn_ir| Self::with_contract_code(txn_ir.clone(), &self.contract_code))
+    }
+
+    /// Returns the regular [GenerationInputs] of all the txn entries, in order.
+    pub fn expand(self) -> Vec<GenerationInputs> {
+        let Self {
+            contract_code,
+            txn_ir,
+        } = self;
+
+        txn_ir
+            .into_iter()
+            .map(|txn_ir| Self::with_contract_code(txn_ir, &contract_code))
+            .collect()
+    }
+
+    fn with_contract_code(
+        txn_ir: DedupedTxnIr,
+        contract_code: &HashMap<CodeHash, Vec<u8>>,
+    ) -> GenerationInputs {
+        let DedupedTxnIr {
+            mut gen_inputs,
+            contract_code_hashes,
+        } = txn_ir;
+
+        // Every hash was inserted along with its code in `push`.
+        gen_inputs.contract_code = contract_code_hashes
+            .into_iter()
+            .map(|c_hash| (c_hash, contract_code[&c_hash].clone()))
+            .collect();
+
+        gen_inputs
+    }
+}
+
+impl FromIterator<GenerationInputs> for DedupedBlockIr {
+    fn from_iter<I: IntoIterator<Item = GenerationInputs>>(iter: I) -> Self {
+        let mut deduped = Self::default();
+        for gen_inputs in iter {
+            deduped.push(gen_inputs);
+        }
+
+        deduped
+    }
+}
+
+impl BlockTrace {
+    /// Like [`BlockTrace::into_txn_proof_gen_ir`], but stores each contract
+    /// code accessed by the block only once (see [DedupedBlockIr]).
+    ///
+    /// The txns are decoded one at a time, so the duplicated code of the whole
+    /// block is never held in memory at once.
+    pub fn into_deduped_txn_proof_gen_ir<F>(
+        self,
+        p_meta: &ProcessingMeta<F>,
+        other_data: OtherBlockData,
+    ) -> TraceParsingResult<DedupedBlockIr>
+    where
+        F: CodeHashResolveFunc,
+    {
+        self.into_txn_proof_gen_ir_stream(p_meta, other_data)?
+            .collect()
+    }
+}
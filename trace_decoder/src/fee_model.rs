@@ -0,0 +1,39 @@
+use std::fmt::Debug;
+
+use ethereum_types::Address;
+
+/// Determines which accounts get credited with the fees of the txns of a
+/// block.
+///
+/// The decoder does not credit the fees itself, as the balance changes are
+/// part of the txn traces, but the credited accounts must be fully present in
+/// the minimal state trie of every txn so that the prover can update them. The
+/// accounts returned here are hence always kept in the minimal state tries,
+/// whether or not the trace of the txn reports an access to them.
+///
+/// The default is [`EthereumFeeModel`]. A chain with different fee
+/// distribution rules can set its own model through
+/// [`ProcessingMeta::with_fee_model`](crate::processed_block_trace::ProcessingMeta::with_fee_model):
+/// - a chain burning all the fees credits no account, and returns an empty
+///   list;
+/// - a chain splitting the fees between the block beneficiary and a treasury
+///   returns both addresses.
+///
+/// Note that this only affects the decoding: the kernel still credits the
+/// block beneficiary with the priority fees as on Ethereum.
+pub trait FeeModel: Debug + Send + Sync {
+    /// Returns the accounts credited with the fees of each txn of a block
+    /// whose beneficiary is `beneficiary`.
+    fn fee_recipients(&self, beneficiary: Address) -> Vec<Address>;
+}
+
+/// The Ethereum fee model: the priority fees of each txn go to the block
+/// beneficiary, and the base fees are burnt.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EthereumFeeModel;
+
+impl FeeModel for EthereumFeeModel {
+    fn fee_recipients(&self, beneficiary: Address) -> Vec<Address> {
+        vec![beneficiary]
+    }
+}
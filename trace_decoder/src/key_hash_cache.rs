@@ -0,0 +1,168 @@
+//! A cache of the keys of storage slots in their storage tries, which can be
+//! shared by the processing of several blocks.
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use mpt_trie::nibbles::Nibbles;
+
+use crate::types::StorageNibbles;
+
+/// A bounded cache from storage slots to their key in the storage tries, i.e.
+/// the nibbles of their keccak hash.
+///
+/// Txns of a block often access the same slots (e.g. the balances of a popular
+/// token), and every access otherwise hashes the slot again, both when
+/// processing the traces and when applying the deltas of the txn. With a cache
+/// set through
+/// [`ProcessingMeta::with_key_hash_cache`](crate::processed_block_trace::ProcessingMeta::with_key_hash_cache),
+/// each slot is only hashed once for as long as it remains cached.
+///
+/// The cache holds at most `capacity` slots. Eviction approximates LRU with two
+/// generations of `capacity / 2` slots each: once the current one is full, the
+/// previous one is dropped and the current one takes its place. A slot found in
+/// the previous generation moves back to the current one. With a capacity below
+/// 2, nothing gets cached.
+///
+/// When shared, the cache is only locked for each lookup, so that concurrent
+/// processing does not wait on the whole processing of a block.
+#[derive(Debug)]
+pub struct KeyHashCache {
+    generation_capacity: usize,
+    current: HashMap<Nibbles, StorageNibbles>,
+    previous: HashMap<Nibbles, StorageNibbles>,
+    num_lookups: usize,
+    num_hashes: usize,
+}
+
+impl KeyHashCache {
+    /// Creates an empty cache holding up to `capacity` slots.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            generation_capacity: capacity / 2,
+            current: HashMap::new(),
+            previous: HashMap::new(),
+            num_lookups: 0,
+            num_hashes: 0,
+        }
+    }
+
+    /// Returns the key of `slot` in its storage trie, only hashing the slot if
+    /// it isn't cached.
+    pub fn storage_key(&mut self, slot: &Nibbles) -> StorageNibbles {
+        self.num_lookups += 1;
+        if let Some(key) = self.current.get(slot) {
+            return *key;
+        }
+
+        let key = self.previous.remove(slot).unwrap_or_else(|| {
+            self.num_hashes += 1;
+            StorageNibbles::from_slot(slot)
+        });
+
+        if self.generation_capacity == 0 {
+            return key;
+        }
+        if self.current.len() >= self.generation_capacity {
+            self.previous = mem::take(&mut self.current);
+        }
+        self.current.insert(*slot, key);
+
+        key
+    }
+
+    /// Returns the number of slots currently cached.
+    pub fn len(&self) -> usize {
+        self.current.len() + self.previous.len()
+    }
+
+    /// Returns `true` if no slot is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of keys looked up so far.
+    pub const fn num_lookups(&self) -> usize {
+        self.num_lookups
+    }
+
+    /// Returns the number of slots hashed so far, i.e. of lookups which missed
+    /// the cache.
+    pub const fn num_hashes(&self) -> usize {
+        self.num_hashes
+    }
+}
+
+/// Locks a shared cache. A panic while holding the lock cannot leave the cache
+/// inconsistent, so a poisoned lock is simply taken over.
+fn lock_key_hash_cache(cache: &Mutex<KeyHashCache>) -> MutexGuard<'_, KeyHashCache> {
+    cache.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns the key of `slot` in its storage trie, through `cache` if set. The
+/// cache is only locked for the lookup.
+pub(crate) fn storage_key(cache: Option<&Mutex<KeyHashCache>>, slot: &Nibbles) -> StorageNibbles {
+    match cache {
+        Some(cache) => lock_key_hash_cache(cache).storage_key(slot),
+        None => StorageNibbles::from_slot(slot),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_types::H256;
+
+    use super::*;
+
+    fn slot(i: u64) -> Nibbles {
+        Nibbles::from_h256_be(H256::from_low_u64_be(i))
+    }
+
+    #[test]
+    fn cached_keys_match_the_hashed_slots() {
+        let mut cache = KeyHashCache::new(16);
+
+        for i in [1, 2, 1, 3, 2, 1] {
+            assert_eq!(
+                cache.storage_key(&slot(i)),
+                StorageNibbles::from_slot(&slot(i))
+            );
+        }
+
+        assert_eq!(cache.num_lookups(), 6);
+        assert_eq!(cache.num_hashes(), 3);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn cache_stays_within_its_capacity() {
+        let mut cache = KeyHashCache::new(4);
+
+        for i in 0..100 {
+            cache.storage_key(&slot(i));
+            assert!(cache.len() <= 4);
+        }
+
+        // The most recently used slots are still cached.
+        cache.storage_key(&slot(99));
+        assert_eq!(cache.num_hashes(), 100);
+    }
+
+    #[test]
+    fn cache_below_two_slots_caches_nothing() {
+        for capacity in [0, 1] {
+            let mut cache = KeyHashCache::new(capacity);
+
+            for i in [1, 1, 2] {
+                assert_eq!(
+                    cache.storage_key(&slot(i)),
+                    StorageNibbles::from_slot(&slot(i))
+                );
+            }
+
+            assert_eq!(cache.len(), 0);
+            assert_eq!(cache.num_hashes(), 3);
+        }
+    }
+}
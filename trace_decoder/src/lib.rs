@@ -108,6 +108,9 @@
 //! - if there are no withdrawals, no dummy transactions are added
 //! - if there are withdrawals, one dummy transaction is added at the end, with
 //!   all the withdrawals in it.
+//!
+//! The minimum number of IR entries (`2` by default) can be changed through
+//! [ProcessingMeta::with_ir_padding](processed_block_trace::ProcessingMeta::with_ir_padding).
 
 #![feature(linked_list_cursors)]
 #![feature(trait_alias)]
@@ -127,7 +130,16 @@ use {
 pub mod compact;
 /// Defines the main functions used to generate the IR.
 pub mod decoding;
+/// Defines a block-level IR which stores the contract code shared by several
+/// txns only once.
+pub mod deduped_ir;
 mod deserializers;
+/// Defines how the fees of the txns of a block are distributed, which
+/// determines the accounts kept in the minimal state tries.
+pub mod fee_model;
+/// Defines a cache of the hashed keys of storage slots, which can be shared
+/// by the processing of several blocks.
+pub mod key_hash_cache;
 /// Defines functions that processes a [BlockTrace] so that it is easier to turn
 /// the block transactions into IRs.
 pub mod processed_block_trace;
@@ -1,33 +1,49 @@
-use std::collections::{HashMap, HashSet};
-use std::fmt::Debug;
+use std::borrow::Cow;
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::fmt::{self, Debug, Display, Formatter};
 use std::iter::once;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
 
 use ethereum_types::{Address, H256, U256};
 use evm_arithmetization::generation::mpt::{AccountRlp, LegacyReceiptRlp};
+use evm_arithmetization::proof::TrieRoots;
 use evm_arithmetization::GenerationInputs;
+use mpt_trie::builder::PartialTrieBuilder;
 use mpt_trie::nibbles::Nibbles;
 use mpt_trie::partial_trie::{HashedPartialTrie, PartialTrie};
+use rlp::Rlp;
 
 use crate::compact::compact_prestate_processing::{
     process_compact_prestate_debug, CompactParsingError, CompactParsingResult,
     PartialTriePreImages, ProcessedCompactOutput,
 };
-use crate::decoding::{TraceParsingError, TraceParsingResult};
+use crate::decoding::{
+    account_from_rlped_bytes, IrPadding, TraceParsingError, TraceParsingErrorReason,
+    TraceParsingResult, TrieNodeProvider, TrieType, TxnProofGenIrStream, TxnStateDiff,
+    TxnStateDiffObserver,
+};
+use crate::fee_model::{EthereumFeeModel, FeeModel};
+use crate::key_hash_cache::{storage_key, KeyHashCache};
 use crate::trace_protocol::{
-    BlockTrace, BlockTraceTriePreImages, CombinedPreImages, ContractCodeUsage,
+    BlockTrace, BlockTraceTriePreImages, CombinedPreImages, ContractCodeUsage, RlpNodesPreImages,
     SeparateStorageTriesPreImage, SeparateTriePreImage, SeparateTriePreImages, TrieCompact,
     TrieUncompressed, TxnInfo,
 };
 use crate::types::{
-    CodeHash, CodeHashResolveFunc, HashedAccountAddr, HashedNodeAddr, HashedStorageAddrNibbles,
-    OtherBlockData, TrieRootHash, EMPTY_CODE_HASH, EMPTY_TRIE_HASH,
+    CodeHash, CodeHashResolveFunc, HashedAccountAddr, HashedNodeAddr, HashedStorageAddr,
+    HashedStorageAddrNibbles, OtherBlockData, StorageNibbles, TrieRootHash, EMPTY_CODE_HASH,
+    EMPTY_TRIE_HASH, NUM_CANCUN_PRECOMPILES,
 };
 use crate::utils::{
-    hash, print_value_and_hash_nodes_of_storage_trie, print_value_and_hash_nodes_of_trie,
+    eip_7702_delegation_code, hash, print_value_and_hash_nodes_of_storage_trie,
+    print_value_and_hash_nodes_of_trie,
 };
 
+/// A [BlockTrace] whose trie pre-images and txn traces have been processed,
+/// ready to be turned into the [GenerationInputs] of its txns.
 #[derive(Debug)]
-pub(crate) struct ProcessedBlockTrace {
+pub struct ProcessedBlockTrace {
     pub(crate) tries: PartialTriePreImages,
     pub(crate) txn_info: Vec<ProcessedTxnInfo>,
     pub(crate) withdrawals: Vec<(Address, U256)>,
@@ -46,16 +62,119 @@ impl BlockTrace {
     where
         F: CodeHashResolveFunc,
     {
-        let processed_block_trace =
-            self.into_processed_block_trace(p_meta, other_data.b_data.withdrawals.clone())?;
+        let processed_block_trace = self.into_processed_block_trace(p_meta, &other_data)?;
+
+        processed_block_trace.into_txn_proof_gen_ir(p_meta, other_data)
+    }
+
+    /// Like [`BlockTrace::into_txn_proof_gen_ir`], but also returns the trie
+    /// roots after each [GenerationInputs], i.e. their `trie_roots_after`, in
+    /// the same order.
+    pub fn into_txn_proof_gen_ir_with_roots<F>(
+        self,
+        p_meta: &ProcessingMeta<F>,
+        other_data: OtherBlockData,
+    ) -> TraceParsingResult<(Vec<GenerationInputs>, Vec<TrieRoots>)>
+    where
+        F: CodeHashResolveFunc,
+    {
+        let txn_ir = self.into_txn_proof_gen_ir(p_meta, other_data)?;
+        let trie_roots = txn_ir
+            .iter()
+            .map(|gen_inputs| gen_inputs.trie_roots_after.clone())
+            .collect();
+
+        Ok((txn_ir, trie_roots))
+    }
+
+    /// Like [`BlockTrace::into_txn_proof_gen_ir`], but also returns the gas
+    /// used by each [GenerationInputs], in the same order. Dummy entries use no
+    /// gas.
+    pub fn into_txn_proof_gen_ir_with_gas_used<F>(
+        self,
+        p_meta: &ProcessingMeta<F>,
+        other_data: OtherBlockData,
+    ) -> TraceParsingResult<(Vec<GenerationInputs>, Vec<u64>)>
+    where
+        F: CodeHashResolveFunc,
+    {
+        let txn_ir = self.into_txn_proof_gen_ir(p_meta, other_data)?;
+        let gas_used = txn_ir
+            .iter()
+            .enumerate()
+            .map(|(entry_idx, gen_inputs)| ir_entry_gas_used(entry_idx, gen_inputs))
+            .collect::<TraceParsingResult<_>>()?;
+
+        Ok((txn_ir, gas_used))
+    }
+
+    /// Like [`BlockTrace::into_txn_proof_gen_ir`], but also returns whether
+    /// each [GenerationInputs] is a dummy entry padding the IR (see
+    /// [IrPadding]), in the same order.
+    ///
+    /// The dummy entries of a block are all identical, so a prover only needs
+    /// to prove one of them and may reuse its proof for the others. In a block
+    /// without txns, the entry carrying the withdrawals of the block differs
+    /// from the dummy entries, and is hence not flagged as one.
+    pub fn into_txn_proof_gen_ir_with_dummy_flags<F>(
+        self,
+        p_meta: &ProcessingMeta<F>,
+        other_data: OtherBlockData,
+    ) -> TraceParsingResult<(Vec<GenerationInputs>, Vec<bool>)>
+    where
+        F: CodeHashResolveFunc,
+    {
+        let processed_block_trace = self.into_processed_block_trace(p_meta, &other_data)?;
+
+        processed_block_trace.into_txn_proof_gen_ir_with_dummy_flags(p_meta, other_data)
+    }
+
+    /// Returns the number of [GenerationInputs] that
+    /// [`BlockTrace::into_txn_proof_gen_ir`] produces for this block, without
+    /// decoding it.
+    ///
+    /// This only depends on the number of txns and on the IR padding, as the
+    /// withdrawals never add an entry: they are carried by the last one.
+    pub fn num_txn_proof_gen_ir<F>(&self, p_meta: &ProcessingMeta<F>) -> usize
+    where
+        F: CodeHashResolveFunc,
+    {
+        p_meta.options.ir_padding.padded_len(self.txn_info.len())
+    }
+
+    /// Like [`BlockTrace::into_txn_proof_gen_ir`], but returns an iterator
+    /// yielding the [GenerationInputs] of one transaction at a time, decoding
+    /// each one only when requested.
+    pub fn into_txn_proof_gen_ir_stream<F>(
+        self,
+        p_meta: &ProcessingMeta<F>,
+        other_data: OtherBlockData,
+    ) -> TraceParsingResult<TxnProofGenIrStream>
+    where
+        F: CodeHashResolveFunc,
+    {
+        let processed_block_trace = self.into_processed_block_trace(p_meta, &other_data)?;
 
-        processed_block_trace.into_txn_proof_gen_ir(other_data)
+        processed_block_trace.into_txn_proof_gen_ir_stream(p_meta, other_data)
     }
 
-    fn into_processed_block_trace<F>(
+    /// Processes the trie pre-images and txn traces of the block, without
+    /// generating any IR yet. The withdrawals are taken from `other_data`.
+    pub fn into_processed<F>(
         self,
         p_meta: &ProcessingMeta<F>,
-        withdrawals: Vec<(Address, U256)>,
+        other_data: &OtherBlockData,
+    ) -> TraceParsingResult<ProcessedBlockTrace>
+    where
+        F: CodeHashResolveFunc,
+    {
+        self.into_processed_block_trace(p_meta, other_data)
+    }
+
+    pub(crate) fn into_processed_block_trace<F>(
+        self,
+        p_meta: &ProcessingMeta<F>,
+        other_data: &OtherBlockData,
     ) -> TraceParsingResult<ProcessedBlockTrace>
     where
         F: CodeHashResolveFunc,
@@ -91,8 +210,13 @@ impl BlockTrace {
         let mut code_hash_resolver = CodeHashResolving {
             client_code_hash_resolve_f: &p_meta.resolve_code_hash_fn,
             extra_code_hash_mappings: code_db,
+            code_hashes_written: HashMap::default(),
+            validate_code_hashes: p_meta.options.validate_code_hashes,
         };
 
+        let withdrawals = other_data.b_data.withdrawals.clone();
+        let eip_6780 = other_data.b_data.is_cancun();
+        let precompiles = p_meta.precompiles();
         let last_tx_idx = self.txn_info.len().saturating_sub(1);
 
         let txn_info = self
@@ -114,10 +238,17 @@ impl BlockTrace {
                 t.into_processed_txn_info(
                     &all_accounts_in_pre_image,
                     &extra_state_accesses,
+                    &precompiles,
+                    eip_6780,
                     &mut code_hash_resolver,
+                    p_meta.options.key_hash_cache.as_deref(),
                 )
+                .map_err(|mut e| {
+                    e.set_txn_idx(i);
+                    e
+                })
             })
-            .collect::<Vec<_>>();
+            .collect::<TraceParsingResult<Vec<_>>>()?;
 
         Ok(ProcessedBlockTrace {
             tries: pre_image_data.tries,
@@ -127,6 +258,130 @@ impl BlockTrace {
     }
 }
 
+impl ProcessedBlockTrace {
+    /// Builds the processed trace of a block from its witness, i.e. a
+    /// JSON-serialized [BlockTrace] holding the trie pre-images (e.g. as a
+    /// compact state image), the code of the accessed contracts and the traces
+    /// of each txn. The header data and withdrawals of the block are taken from
+    /// `other_data`.
+    ///
+    /// Fails with
+    /// [`BlockWitnessDecode`](TraceParsingErrorReason::BlockWitnessDecode)
+    /// if the witness is not a valid [BlockTrace], e.g. due to a missing
+    /// field, and with
+    /// [`CompactParsingError`](TraceParsingErrorReason::CompactParsingError)
+    /// if its compact state image can't be parsed.
+    pub fn from_block_witness_json<F>(
+        witness_json: &[u8],
+        p_meta: &ProcessingMeta<F>,
+        other_data: &OtherBlockData,
+    ) -> TraceParsingResult<Self>
+    where
+        F: CodeHashResolveFunc,
+    {
+        let block_trace: BlockTrace = serde_json::from_slice(witness_json).map_err(|err| {
+            Box::new(TraceParsingError::new(
+                TraceParsingErrorReason::BlockWitnessDecode(err.to_string()),
+            ))
+        })?;
+
+        block_trace.into_processed(p_meta, other_data)
+    }
+
+    /// Returns the hashed addresses of all the accounts the block accesses,
+    /// along with the hashed slots it accesses in the storage of each account,
+    /// without building any trie.
+    ///
+    /// Accessed accounts include the ones written to or self-destructed, as
+    /// well as the withdrawal recipients. Accounts whose storage is accessed
+    /// are also part of the accessed accounts.
+    pub fn accessed_keys(
+        &self,
+    ) -> (
+        HashSet<HashedAccountAddr>,
+        HashMap<HashedAccountAddr, HashSet<HashedStorageAddr>>,
+    ) {
+        let mut accounts = HashSet::new();
+        let mut storage: HashMap<_, HashSet<_>> = HashMap::new();
+
+        for txn_info in &self.txn_info {
+            let nodes = &txn_info.nodes_used_by_txn;
+
+            accounts.extend(nodes.state_accesses.iter().copied());
+            accounts.extend(nodes.state_writes.iter().map(|(h_addr, _)| *h_addr));
+            accounts.extend(nodes.self_destructed_accounts.iter().copied());
+
+            // Unlike the accessed slots, the written slots are not hashed yet.
+            let slots_accessed = nodes.storage_accesses.iter().flat_map(|(h_addr, slots)| {
+                slots
+                    .iter()
+                    .map(move |slot| (*h_addr, HashedStorageAddr::from(*slot)))
+            });
+            let slots_written = nodes.storage_writes.iter().flat_map(|(h_addr, writes)| {
+                writes
+                    .iter()
+                    .map(move |(slot, _)| (*h_addr, hash(&slot.bytes_be())))
+            });
+
+            for (h_addr, h_slot) in slots_accessed.chain(slots_written) {
+                accounts.insert(h_addr);
+                storage.entry(h_addr).or_default().insert(h_slot);
+            }
+        }
+
+        accounts.extend(
+            self.withdrawals
+                .iter()
+                .map(|(addr, _)| hash(addr.as_bytes())),
+        );
+
+        (accounts, storage)
+    }
+
+    /// Returns a summary of the block, meant for logging. The block number and
+    /// chain id are taken from `other_data`.
+    pub fn summary(&self, other_data: &OtherBlockData) -> BlockTraceSummary {
+        BlockTraceSummary {
+            block_number: other_data.b_data.b_meta.block_number,
+            chain_id: other_data.b_data.b_meta.block_chain_id,
+            num_txns: self.txn_info.len(),
+            num_withdrawals: self.withdrawals.len(),
+            num_accessed_accounts: self.accessed_keys().0.len(),
+        }
+    }
+}
+
+/// A summary of a [ProcessedBlockTrace], as returned by
+/// [`ProcessedBlockTrace::summary`], whose [Display] fits on a single line.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BlockTraceSummary {
+    /// The number of the block.
+    pub block_number: U256,
+    /// The id of the chain of the block.
+    pub chain_id: U256,
+    /// The number of txns in the block.
+    pub num_txns: usize,
+    /// The number of withdrawals in the block.
+    pub num_withdrawals: usize,
+    /// The number of distinct accounts accessed by the block, as returned by
+    /// [`ProcessedBlockTrace::accessed_keys`].
+    pub num_accessed_accounts: usize,
+}
+
+impl Display for BlockTraceSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "block={} chain_id={} txns={} withdrawals={} accessed_accounts={}",
+            self.block_number,
+            self.chain_id,
+            self.num_txns,
+            self.num_withdrawals,
+            self.num_accessed_accounts
+        )
+    }
+}
+
 #[derive(Debug)]
 struct ProcessedBlockTracePreImages {
     tries: PartialTriePreImages,
@@ -148,19 +403,116 @@ impl From<ProcessedCompactOutput> for ProcessedBlockTracePreImages {
     }
 }
 
+/// Returns the gas used by an IR entry, i.e. the difference between its gas
+/// used after and before.
+fn ir_entry_gas_used(entry_idx: usize, gen_inputs: &GenerationInputs) -> TraceParsingResult<u64> {
+    gen_inputs
+        .gas_used_after
+        .checked_sub(gen_inputs.gas_used_before)
+        .and_then(|gas_used| gas_used.try_into().ok())
+        .ok_or_else(|| {
+            Box::new(TraceParsingError::new(
+                TraceParsingErrorReason::GasUsedOutOfRange {
+                    entry_idx,
+                    gas_used_before: gen_inputs.gas_used_before,
+                    gas_used_after: gen_inputs.gas_used_after,
+                },
+            ))
+        })
+}
+
 fn process_block_trace_trie_pre_images(
     block_trace_pre_images: BlockTraceTriePreImages,
 ) -> TraceParsingResult<ProcessedBlockTracePreImages> {
     match block_trace_pre_images {
         BlockTraceTriePreImages::Separate(t) => process_separate_trie_pre_images(t),
         BlockTraceTriePreImages::Combined(t) => process_combined_trie_pre_images(t),
+        BlockTraceTriePreImages::RlpNodes(t) => process_rlp_nodes_pre_images(t),
     }
 }
 
 fn process_combined_trie_pre_images(
     tries: CombinedPreImages,
 ) -> TraceParsingResult<ProcessedBlockTracePreImages> {
-    Ok(process_compact_trie(tries.compact).map_err(TraceParsingError::from)?)
+    let pre_images = process_compact_trie(tries.compact).map_err(TraceParsingError::from)?;
+    verify_storage_roots_of_pre_images(&pre_images.tries)?;
+
+    Ok(pre_images)
+}
+
+/// Rebuilds the state and storage tries from their RLP-encoded nodes.
+///
+/// Whether the nodes cover every key accessed by the block is only checked
+/// later on, when creating the minimal tries of the txn accessing them.
+fn process_rlp_nodes_pre_images(
+    pre_images: RlpNodesPreImages,
+) -> TraceParsingResult<ProcessedBlockTracePreImages> {
+    let RlpNodesPreImages { state_root, nodes } = pre_images;
+
+    // The trie builder expects every node to be a branch, extension or leaf.
+    let nodes = nodes
+        .into_iter()
+        .map(|node| match Rlp::new(&node).item_count() {
+            Ok(2 | 17) => Ok((hash(&node), node)),
+            Ok(_) => Err(rlp::DecoderError::RlpIncorrectListLen),
+            Err(err) => Err(err),
+        })
+        .collect::<Result<HashMap<_, _>, _>>()
+        .map_err(|err| Box::new(TraceParsingError::from(err)))?;
+
+    if state_root != EMPTY_TRIE_HASH && !nodes.contains_key(&state_root) {
+        return Err(Box::new(TraceParsingError::new(
+            TraceParsingErrorReason::MissingPreImageRoot(state_root),
+        )));
+    }
+
+    let builder = PartialTrieBuilder::new(state_root, nodes);
+    let state: HashedPartialTrie = builder.build_at(state_root);
+
+    let mut storage = HashMap::new();
+    for (k, v) in state.items() {
+        let Some(bytes) = v.as_val() else {
+            continue;
+        };
+
+        let storage_root = account_from_rlped_bytes(bytes)?.storage_root;
+        storage.insert(k.into(), builder.build_at(storage_root));
+    }
+
+    Ok(ProcessedBlockTracePreImages {
+        tries: PartialTriePreImages { state, storage },
+        extra_code_hash_mappings: None,
+    })
+}
+
+/// Checks that each storage trie of the pre-images hashes to the storage root
+/// of its account, so that a bad witness gets pinned to the offending account
+/// instead of failing later on with a generic trie error.
+fn verify_storage_roots_of_pre_images(tries: &PartialTriePreImages) -> TraceParsingResult<()> {
+    for (k, v) in tries.state.items() {
+        let Some(bytes) = v.as_val() else {
+            continue;
+        };
+
+        let h_addr: HashedAccountAddr = k.into();
+        let Some(storage_trie) = tries.storage.get(&h_addr) else {
+            continue;
+        };
+
+        let in_account = account_from_rlped_bytes(bytes)?.storage_root;
+        let in_image = storage_trie.hash();
+        if in_account != in_image {
+            return Err(Box::new(TraceParsingError::new(
+                TraceParsingErrorReason::StorageRootMismatch {
+                    h_addr,
+                    in_account,
+                    in_image,
+                },
+            )));
+        }
+    }
+
+    Ok(())
 }
 
 fn process_separate_trie_pre_images(
@@ -227,13 +579,54 @@ fn process_compact_trie(trie: TrieCompact) -> CompactParsingResult<ProcessedBloc
     Ok(out.into())
 }
 
-/// Structure storing a function turning a `CodeHash` into bytes.
+/// Structure storing a function turning a `CodeHash` into bytes, along with
+/// additional options for processing the block trace.
 #[derive(Debug)]
 pub struct ProcessingMeta<F>
 where
     F: CodeHashResolveFunc,
 {
     resolve_code_hash_fn: F,
+    pub(crate) options: ProcessingOptions,
+}
+
+/// The options of a [`ProcessingMeta`], i.e. everything but the code hash
+/// resolving function, which a [`TxnProofGenIrStream`] keeps a copy of.
+#[derive(Clone, Debug)]
+pub(crate) struct ProcessingOptions {
+    pub(crate) ir_padding: IrPadding,
+    pub(crate) expected_txn_trie_roots: Option<Vec<TrieRoots>>,
+    pub(crate) build_receipt_trie: bool,
+    pub(crate) create_missing_withdrawal_accounts: bool,
+    pub(crate) reject_zero_gas_txns: bool,
+    pub(crate) txn_state_diff_observer: Option<TxnStateDiffObserver>,
+    pub(crate) cancellation_flag: Option<Arc<AtomicBool>>,
+    /// The Cancun precompiles when not set.
+    pub(crate) precompiles: Option<HashSet<Address>>,
+    pub(crate) validate_receipts: bool,
+    pub(crate) validate_code_hashes: bool,
+    /// The [EthereumFeeModel] when not set.
+    pub(crate) fee_model: Option<Arc<dyn FeeModel>>,
+    pub(crate) node_provider: Option<TrieNodeProvider>,
+    pub(crate) key_hash_cache: Option<Arc<Mutex<KeyHashCache>>>,
+}
+
+impl ProcessingOptions {
+    const DEFAULT: Self = Self {
+        ir_padding: IrPadding::DEFAULT,
+        expected_txn_trie_roots: None,
+        build_receipt_trie: true,
+        create_missing_withdrawal_accounts: false,
+        reject_zero_gas_txns: false,
+        txn_state_diff_observer: None,
+        cancellation_flag: None,
+        precompiles: None,
+        validate_receipts: false,
+        validate_code_hashes: true,
+        fee_model: None,
+        node_provider: None,
+        key_hash_cache: None,
+    };
 }
 
 impl<F> ProcessingMeta<F>
@@ -245,8 +638,167 @@ where
     pub const fn new(resolve_code_hash_fn: F) -> Self {
         Self {
             resolve_code_hash_fn,
+            options: ProcessingOptions::DEFAULT,
+        }
+    }
+
+    /// Sets how the generated IR gets padded with dummy payloads.
+    pub fn with_ir_padding(mut self, ir_padding: IrPadding) -> Self {
+        self.options.ir_padding = ir_padding;
+        self
+    }
+
+    /// Sets the trie roots expected after each txn, indexed by txn index.
+    ///
+    /// When set, the roots computed after each txn are checked against these,
+    /// and processing fails with
+    /// [`TrieRootMismatch`](crate::decoding::TraceParsingErrorReason::TrieRootMismatch)
+    /// at the first txn that diverged. Txns without an entry are not checked.
+    pub fn with_expected_txn_trie_roots(mut self, expected_roots: Vec<TrieRoots>) -> Self {
+        self.options.expected_txn_trie_roots = Some(expected_roots);
+        self
+    }
+
+    /// Sets whether the receipt trie gets built. Defaults to `true`.
+    ///
+    /// When disabled, no receipt is ever inserted and the receipts root of
+    /// every generated IR entry is the empty trie hash. The receipts roots
+    /// supplied through [`Self::with_expected_txn_trie_roots`] are then not
+    /// checked either.
+    pub fn with_build_receipt_trie(mut self, build_receipt_trie: bool) -> Self {
+        self.options.build_receipt_trie = build_receipt_trie;
+        self
+    }
+
+    /// Sets whether a withdrawal to an account missing from the state trie
+    /// creates it, with the withdrawn amount as its balance. Defaults to
+    /// `false`, in which case such a withdrawal fails with
+    /// [`MissingWithdrawalAccount`](crate::decoding::TraceParsingErrorReason::MissingWithdrawalAccount).
+    pub fn with_create_missing_withdrawal_accounts(mut self, create: bool) -> Self {
+        self.options.create_missing_withdrawal_accounts = create;
+        self
+    }
+
+    /// Sets whether a txn reporting no gas used makes processing fail with
+    /// [`ZeroGasTxn`](crate::decoding::TraceParsingErrorReason::ZeroGasTxn).
+    /// Defaults to `false`, in which case such a txn is only logged as a
+    /// warning and processed like any other, although proving it may fail.
+    pub fn with_reject_zero_gas_txns(mut self, reject: bool) -> Self {
+        self.options.reject_zero_gas_txns = reject;
+        self
+    }
+
+    /// Sets a callback called with the [`TxnStateDiff`] of every txn, right
+    /// after its deltas got applied to the tries.
+    pub fn with_txn_state_diff_observer(
+        mut self,
+        observer: impl Fn(&TxnStateDiff) + Send + Sync + 'static,
+    ) -> Self {
+        self.options.txn_state_diff_observer = Some(TxnStateDiffObserver::new(observer));
+        self
+    }
+
+    /// Sets a flag which aborts the processing of the block once set. It is
+    /// checked before each txn, and processing then fails with
+    /// [`Cancelled`](crate::decoding::TraceParsingErrorReason::Cancelled).
+    pub fn with_cancellation_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.options.cancellation_flag = Some(flag);
+        self
+    }
+
+    /// Sets the addresses of the precompiled contracts, replacing the Cancun
+    /// ones (`0x01` through `0x0a`) used by default.
+    ///
+    /// Precompiles have no bytecode, so any code usage a trace reports for one
+    /// of these addresses is dropped instead of ending up in the contract code
+    /// of the txn.
+    pub fn with_precompiles(mut self, precompiles: impl IntoIterator<Item = Address>) -> Self {
+        self.options.precompiles = Some(precompiles.into_iter().collect());
+        self
+    }
+
+    /// Sets whether the logs blooms of the receipts of the block get checked
+    /// against the block one. Defaults to `false`, as this requires decoding
+    /// every receipt.
+    ///
+    /// When enabled, processing fails with
+    /// [`ReceiptsBloomMismatch`](crate::decoding::TraceParsingErrorReason::ReceiptsBloomMismatch)
+    /// if the union of the receipt blooms differs from the block bloom.
+    pub fn with_validate_receipts(mut self, validate: bool) -> Self {
+        self.options.validate_receipts = validate;
+        self
+    }
+
+    /// Sets whether the code resolved for each code hash read by a txn gets
+    /// checked to actually hash to it. Defaults to `true`, and can be
+    /// disabled for trusted inputs.
+    ///
+    /// When enabled, processing fails with
+    /// [`CodeHashMismatch`](crate::decoding::TraceParsingErrorReason::CodeHashMismatch)
+    /// on the first code not matching its hash, instead of the prover failing
+    /// on it much later. Code resolved as empty for a non-empty code hash is
+    /// reported as missing instead, through
+    /// [`NonExistentTrieEntry`](crate::decoding::TraceParsingErrorReason::NonExistentTrieEntry)
+    /// with [`TrieType::Code`].
+    pub fn with_validate_code_hashes(mut self, validate: bool) -> Self {
+        self.options.validate_code_hashes = validate;
+        self
+    }
+
+    /// Sets how the fees of the txns are distributed, which determines the
+    /// accounts always kept in their minimal state tries. Defaults to
+    /// [`EthereumFeeModel`].
+    pub fn with_fee_model(mut self, fee_model: impl FeeModel + 'static) -> Self {
+        self.options.fee_model = Some(Arc::new(fee_model));
+        self
+    }
+
+    /// Returns the addresses of the precompiled contracts.
+    pub(crate) fn precompiles(&self) -> Cow<'_, HashSet<Address>> {
+        match &self.options.precompiles {
+            Some(precompiles) => Cow::Borrowed(precompiles),
+            None => Cow::Owned(
+                (1..=NUM_CANCUN_PRECOMPILES)
+                    .map(Address::from_low_u64_be)
+                    .collect(),
+            ),
         }
     }
+
+    /// Returns the fee model distributing the fees of the txns.
+    pub(crate) fn fee_model(&self) -> &dyn FeeModel {
+        self.options
+            .fee_model
+            .as_deref()
+            .unwrap_or(&EthereumFeeModel)
+    }
+
+    /// Sets a callback returning the RLP encoding of a trie node given its
+    /// hash, e.g. from a local database, or `None` if it is unknown.
+    ///
+    /// By default, processing fails with
+    /// [`MissingKeysCreatingSubPartialTrie`](crate::decoding::TraceParsingErrorReason::MissingKeysCreatingSubPartialTrie)
+    /// when the minimal tries of a txn need a node which the pre-images only
+    /// have as a hash. With a provider set, such nodes are fetched from it
+    /// instead, so that slightly incomplete pre-images can still be processed.
+    /// A provided node which does not hash to the requested hash is ignored.
+    pub fn with_node_provider(
+        mut self,
+        provider: impl Fn(H256) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        self.options.node_provider = Some(TrieNodeProvider::new(provider));
+        self
+    }
+
+    /// Sets a cache of the keys of the storage slots in their storage tries,
+    /// so that a slot accessed by several txns only gets hashed once.
+    ///
+    /// The cache is shared, so that it can be reused when processing the next
+    /// blocks, and its counters show how many hashes it saved.
+    pub fn with_key_hash_cache(mut self, cache: Arc<Mutex<KeyHashCache>>) -> Self {
+        self.options.key_hash_cache = Some(cache);
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -266,18 +818,93 @@ struct CodeHashResolving<F> {
     /// trace. If there are any txns that create contracts, then they will also
     /// get added here as we process the deltas.
     extra_code_hash_mappings: HashMap<CodeHash, Vec<u8>>,
+
+    /// The accounts that had their code written so far in the block, for each
+    /// code hash.
+    code_hashes_written: HashMap<CodeHash, HashSet<HashedAccountAddr>>,
+
+    /// Whether to check that the resolved code hashes to the requested hash.
+    validate_code_hashes: bool,
 }
 
 impl<F: CodeHashResolveFunc> CodeHashResolving<F> {
-    fn resolve(&mut self, c_hash: &CodeHash) -> Vec<u8> {
-        match self.extra_code_hash_mappings.get(c_hash) {
+    fn resolve(&mut self, c_hash: &CodeHash) -> TraceParsingResult<Vec<u8>> {
+        let code = match self.extra_code_hash_mappings.get(c_hash) {
             Some(code) => code.clone(),
             None => (self.client_code_hash_resolve_f)(c_hash),
+        };
+
+        if self.validate_code_hashes {
+            if code.is_empty() && *c_hash != EMPTY_CODE_HASH {
+                return Err(Box::new(TraceParsingError::new(
+                    TraceParsingErrorReason::NonExistentTrieEntry(
+                        TrieType::Code,
+                        Nibbles::from_h256_be(*c_hash),
+                        EMPTY_TRIE_HASH,
+                    ),
+                )));
+            }
+
+            let got = hash(&code);
+            if got != *c_hash {
+                return Err(Box::new(TraceParsingError::new(
+                    TraceParsingErrorReason::CodeHashMismatch {
+                        expected: *c_hash,
+                        got,
+                    },
+                )));
+            }
         }
+
+        Ok(code)
     }
 
-    fn insert_code(&mut self, c_hash: H256, code: Vec<u8>) {
+    fn insert_code(&mut self, c_hash: H256, code: Vec<u8>, h_addr: HashedAccountAddr) {
+        // The new code replaces any code written earlier to the account.
+        for writers in self.code_hashes_written.values_mut() {
+            writers.remove(&h_addr);
+        }
+
         self.extra_code_hash_mappings.insert(c_hash, code);
+        self.code_hashes_written
+            .entry(c_hash)
+            .or_default()
+            .insert(h_addr);
+    }
+
+    /// Returns the hash of the code written to `h_addr` so far in the block, if
+    /// any.
+    fn code_hash_written_to(&self, h_addr: &HashedAccountAddr) -> Option<CodeHash> {
+        self.code_hashes_written
+            .iter()
+            .find(|(_, writers)| writers.contains(h_addr))
+            .map(|(c_hash, _)| *c_hash)
+    }
+
+    /// Removes the code mapping of a self-destructed account, unless some
+    /// other account (either from the pre-image or created earlier in the
+    /// block) still uses the same code.
+    fn remove_code_of_destroyed_account(
+        &mut self,
+        c_hash: &CodeHash,
+        destroyed_h_addr: &HashedAccountAddr,
+        all_accounts_in_pre_image: &[(HashedAccountAddr, AccountRlp)],
+    ) {
+        if let Some(writers) = self.code_hashes_written.get_mut(c_hash) {
+            writers.remove(destroyed_h_addr);
+        }
+
+        let used_by_other_pre_image_account = all_accounts_in_pre_image
+            .iter()
+            .any(|(h_addr, data)| h_addr != destroyed_h_addr && data.code_hash == *c_hash);
+        let written_by_other_account = self
+            .code_hashes_written
+            .get(c_hash)
+            .is_some_and(|writers| !writers.is_empty());
+
+        if !used_by_other_pre_image_account && !written_by_other_account {
+            self.extra_code_hash_mappings.remove(c_hash);
+        }
     }
 }
 
@@ -286,9 +913,12 @@ impl TxnInfo {
         self,
         all_accounts_in_pre_image: &[(HashedAccountAddr, AccountRlp)],
         extra_state_accesses: &[HashedAccountAddr],
+        precompiles: &HashSet<Address>,
+        eip_6780: bool,
         code_hash_resolver: &mut CodeHashResolving<F>,
-    ) -> ProcessedTxnInfo {
-        let mut nodes_used_by_txn = NodesUsedByTxn::default();
+        key_hash_cache: Option<&Mutex<KeyHashCache>>,
+    ) -> TraceParsingResult<ProcessedTxnInfo> {
+        let mut nodes_used_by_txn = NodesUsedByTxn::default().with_eip_6780(eip_6780);
         let mut contract_code_accessed = create_empty_code_access_map();
 
         for (addr, trace) in self.traces {
@@ -307,12 +937,17 @@ impl TxnInfo {
             nodes_used_by_txn.storage_accesses.push((
                 hashed_addr,
                 storage_access_keys
-                    .map(|k| Nibbles::from_h256_be(hash(&k.0)))
+                    .map(|k| storage_key(key_hash_cache, &Nibbles::from_h256_be(k)).into_inner())
                     .collect(),
             ));
 
+            // Some trace producers wrongly report code for the precompiles, which
+            // have none.
+            let code_usage = trace.code_usage.filter(|_| !precompiles.contains(&addr));
+
             let storage_trie_change = !storage_writes.is_empty();
-            let code_change = trace.code_usage.is_some();
+            let code_change = code_usage.is_some();
+            let code_hash = code_usage.as_ref().map(|usage| usage.get_code_hash());
             let state_write_occurred = trace.balance.is_some()
                 || trace.nonce.is_some()
                 || storage_trie_change
@@ -323,7 +958,8 @@ impl TxnInfo {
                     balance: trace.balance,
                     nonce: trace.nonce,
                     storage_trie_change,
-                    code_hash: trace.code_usage.as_ref().map(|usage| usage.get_code_hash()),
+                    code_hash,
+                    expected_storage_root: trace.storage_root,
                 };
 
                 nodes_used_by_txn
@@ -342,27 +978,69 @@ impl TxnInfo {
 
             nodes_used_by_txn.state_accesses.push(hashed_addr);
 
-            if let Some(c_usage) = trace.code_usage {
+            let self_destructed = trace
+                .self_destructed
+                .map_or(false, |self_destructed| self_destructed);
+
+            // The code of the account at the start of the txn, which is either
+            // written by an earlier txn of the block or from the pre-image. This
+            // must be looked up before the code written by this txn is recorded.
+            let code_hash_before_txn = self_destructed
+                .then(|| {
+                    code_hash_resolver
+                        .code_hash_written_to(&hashed_addr)
+                        .or_else(|| {
+                            all_accounts_in_pre_image
+                                .iter()
+                                .find(|(h_addr, _)| *h_addr == hashed_addr)
+                                .map(|(_, data)| data.code_hash)
+                        })
+                })
+                .flatten();
+
+            if let Some(c_usage) = code_usage {
                 match c_usage {
                     ContractCodeUsage::Read(c_hash) => {
-                        contract_code_accessed
-                            .entry(c_hash)
-                            .or_insert_with(|| code_hash_resolver.resolve(&c_hash));
+                        if let Entry::Vacant(entry) = contract_code_accessed.entry(c_hash) {
+                            entry.insert(code_hash_resolver.resolve(&c_hash)?);
+                        }
                     }
                     ContractCodeUsage::Write(c_bytes) => {
                         let c_hash = hash(&c_bytes);
 
                         contract_code_accessed.insert(c_hash, c_bytes.0.clone());
-                        code_hash_resolver.insert_code(c_hash, c_bytes.0);
+                        code_hash_resolver.insert_code(c_hash, c_bytes.0, hashed_addr);
+                    }
+                    ContractCodeUsage::Delegate(delegate) => {
+                        let c_bytes = eip_7702_delegation_code(&delegate);
+                        let c_hash = hash(&c_bytes);
+
+                        contract_code_accessed.insert(c_hash, c_bytes.clone());
+                        code_hash_resolver.insert_code(c_hash, c_bytes, hashed_addr);
                     }
                 }
             }
 
-            if trace
-                .self_destructed
-                .map_or(false, |self_destructed| self_destructed)
-            {
+            if self_destructed {
                 nodes_used_by_txn.self_destructed_accounts.push(hashed_addr);
+
+                // The code is still kept for this txn (as it got executed), but it
+                // should not outlive the account for the rest of the block. With
+                // EIP-6780, a contract which already had code at the start of the
+                // txn is however never deleted, as during the delta application.
+                let outlived_by_its_code =
+                    eip_6780 && code_hash_before_txn.is_some_and(|h| h != EMPTY_CODE_HASH);
+                let destroyed_code_hash = code_hash
+                    .or(code_hash_before_txn)
+                    .filter(|_| !outlived_by_its_code);
+
+                if let Some(c_hash) = destroyed_code_hash.filter(|h| *h != EMPTY_CODE_HASH) {
+                    code_hash_resolver.remove_code_of_destroyed_account(
+                        &c_hash,
+                        &hashed_addr,
+                        all_accounts_in_pre_image,
+                    );
+                }
             }
         }
 
@@ -370,54 +1048,34 @@ impl TxnInfo {
             nodes_used_by_txn.state_accesses.push(hashed_addr);
         }
 
-        let accounts_with_storage_accesses: HashSet<_> = HashSet::from_iter(
-            nodes_used_by_txn
-                .storage_accesses
-                .iter()
-                .filter(|(_, slots)| !slots.is_empty())
-                .map(|(addr, _)| *addr),
-        );
-
-        let all_accounts_with_non_empty_storage = all_accounts_in_pre_image
-            .iter()
-            .filter(|(_, data)| data.storage_root != EMPTY_TRIE_HASH);
-
-        let accounts_with_storage_but_no_storage_accesses = all_accounts_with_non_empty_storage
-            .filter(|&(addr, _data)| !accounts_with_storage_accesses.contains(addr))
-            .map(|(addr, data)| (*addr, data.storage_root));
-
-        nodes_used_by_txn
-            .state_accounts_with_no_accesses_but_storage_tries
-            .extend(accounts_with_storage_but_no_storage_accesses);
-
         let txn_bytes = match self.meta.byte_code.is_empty() {
             false => Some(self.meta.byte_code),
             true => None,
         };
 
         let receipt_node_bytes =
-            process_rlped_receipt_node_bytes(self.meta.new_receipt_trie_node_byte);
+            process_rlped_receipt_node_bytes(self.meta.new_receipt_trie_node_byte)?;
 
-        let new_meta_state = TxnMetaState {
-            txn_bytes,
-            receipt_node_bytes,
-            gas_used: self.meta.gas_used,
-        };
+        let new_meta_state = TxnMetaState::new(txn_bytes, receipt_node_bytes, self.meta.gas_used);
 
-        ProcessedTxnInfo {
+        Ok(ProcessedTxnInfo {
             nodes_used_by_txn,
             contract_code_accessed,
             meta: new_meta_state,
-        }
+        })
     }
 }
 
-fn process_rlped_receipt_node_bytes(raw_bytes: Vec<u8>) -> Vec<u8> {
+fn process_rlped_receipt_node_bytes(raw_bytes: Vec<u8>) -> TraceParsingResult<Vec<u8>> {
     match rlp::decode::<LegacyReceiptRlp>(&raw_bytes) {
-        Ok(_) => raw_bytes,
+        Ok(_) => Ok(raw_bytes),
         Err(_) => {
             // Must be non-legacy.
-            rlp::decode::<Vec<u8>>(&raw_bytes).unwrap()
+            rlp::decode::<Vec<u8>>(&raw_bytes).map_err(|_| {
+                Box::new(TraceParsingError::new(
+                    TraceParsingErrorReason::ReceiptDecode(hex::encode(&raw_bytes)),
+                ))
+            })
         }
     }
 }
@@ -426,34 +1084,797 @@ fn create_empty_code_access_map() -> HashMap<CodeHash, Vec<u8>> {
     HashMap::from_iter(once((EMPTY_CODE_HASH, Vec::new())))
 }
 
-pub(crate) type StorageAccess = Vec<HashedStorageAddrNibbles>;
-pub(crate) type StorageWrite = Vec<(HashedStorageAddrNibbles, Vec<u8>)>;
+/// The keys of the storage slots accessed in an account, i.e. the hashes of
+/// the slots.
+pub type StorageAccess = Vec<HashedStorageAddrNibbles>;
+/// The slots written in an account, along with their RLP encoded values. An
+/// empty value deletes the slot.
+pub type StorageWrite = Vec<(HashedStorageAddrNibbles, Vec<u8>)>;
 
+/// The state and storage trie nodes accessed and written by a txn.
+///
 /// Note that "*_accesses" includes writes.
 #[derive(Debug, Default)]
-pub(crate) struct NodesUsedByTxn {
+pub struct NodesUsedByTxn {
     pub(crate) state_accesses: Vec<HashedNodeAddr>,
     pub(crate) state_writes: Vec<(HashedAccountAddr, StateTrieWrites)>,
 
     // Note: All entries in `storage_writes` also appear in `storage_accesses`.
     pub(crate) storage_accesses: Vec<(HashedAccountAddr, StorageAccess)>,
     pub(crate) storage_writes: Vec<(HashedAccountAddr, StorageWrite)>,
-    pub(crate) state_accounts_with_no_accesses_but_storage_tries:
-        HashMap<HashedAccountAddr, TrieRootHash>,
     pub(crate) self_destructed_accounts: Vec<HashedAccountAddr>,
+
+    /// Whether the self-destructs follow EIP-6780, i.e. only delete the
+    /// accounts created in the same txn, as in post-Cancun blocks.
+    pub(crate) eip_6780: bool,
 }
 
-#[derive(Debug)]
-pub(crate) struct StateTrieWrites {
+/// The writes of a txn to an account.
+#[derive(Debug, Default)]
+pub struct StateTrieWrites {
     pub(crate) balance: Option<U256>,
     pub(crate) nonce: Option<U256>,
     pub(crate) storage_trie_change: bool,
     pub(crate) code_hash: Option<CodeHash>,
+    /// If set, the storage root of the account must match it once the writes
+    /// are applied.
+    pub(crate) expected_storage_root: Option<TrieRootHash>,
+}
+
+impl NodesUsedByTxn {
+    /// Records an access to the account with the given hashed address.
+    pub fn with_state_access(mut self, h_addr: HashedAccountAddr) -> Self {
+        self.state_accesses.push(h_addr);
+        self
+    }
+
+    /// Records writes to the account with the given hashed address, along with
+    /// the access to it.
+    pub fn with_state_write(mut self, h_addr: HashedAccountAddr, writes: StateTrieWrites) -> Self {
+        self.state_accesses.push(h_addr);
+        self.state_writes.push((h_addr, writes));
+        self
+    }
+
+    /// Records reads of the given slots of an account.
+    pub fn with_storage_reads(
+        mut self,
+        h_addr: HashedAccountAddr,
+        slots: impl IntoIterator<Item = Nibbles>,
+    ) -> Self {
+        self.storage_accesses.push((
+            h_addr,
+            slots
+                .into_iter()
+                .map(|slot| StorageNibbles::from_slot(&slot).into_inner())
+                .collect(),
+        ));
+        self
+    }
+
+    /// Records writes to the given slots of an account, along with the
+    /// accesses to them.
+    pub fn with_storage_writes(mut self, h_addr: HashedAccountAddr, writes: StorageWrite) -> Self {
+        self = self.with_storage_reads(h_addr, writes.iter().map(|(slot, _)| *slot));
+        self.storage_writes.push((h_addr, writes));
+        self
+    }
+
+    /// Records the self-destruction of the account with the given hashed
+    /// address.
+    pub fn with_self_destructed_account(mut self, h_addr: HashedAccountAddr) -> Self {
+        self.self_destructed_accounts.push(h_addr);
+        self
+    }
+
+    /// Sets whether the self-destructs follow EIP-6780. Defaults to `false`,
+    /// in which case a self-destructed account is always deleted.
+    ///
+    /// When enabled, a self-destructed account is only deleted if it was
+    /// created in the same txn, i.e. if it had no code before the txn.
+    /// Otherwise, it is kept and its balance is swept, i.e. set to zero,
+    /// unless the txn writes its final balance.
+    pub const fn with_eip_6780(mut self, eip_6780: bool) -> Self {
+        self.eip_6780 = eip_6780;
+        self
+    }
+}
+
+impl StateTrieWrites {
+    /// Sets the new balance of the account.
+    pub const fn with_balance(mut self, balance: U256) -> Self {
+        self.balance = Some(balance);
+        self
+    }
+
+    /// Sets the new nonce of the account.
+    pub const fn with_nonce(mut self, nonce: U256) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    /// Sets whether the storage trie of the account got written to, in which
+    /// case its storage root gets updated.
+    pub const fn with_storage_trie_change(mut self, storage_trie_change: bool) -> Self {
+        self.storage_trie_change = storage_trie_change;
+        self
+    }
+
+    /// Sets the new code hash of the account.
+    pub const fn with_code_hash(mut self, code_hash: CodeHash) -> Self {
+        self.code_hash = Some(code_hash);
+        self
+    }
+
+    /// Sets the storage root the account must have once the writes are
+    /// applied.
+    pub const fn with_expected_storage_root(mut self, storage_root: TrieRootHash) -> Self {
+        self.expected_storage_root = Some(storage_root);
+        self
+    }
 }
 
+/// The txn and receipt trie entries produced by a single txn, along with the
+/// gas it used.
 #[derive(Debug, Default)]
-pub(crate) struct TxnMetaState {
+pub struct TxnMetaState {
     pub(crate) txn_bytes: Option<Vec<u8>>,
     pub(crate) receipt_node_bytes: Vec<u8>,
     pub(crate) gas_used: u64,
 }
+
+impl TxnMetaState {
+    /// Creates a new `TxnMetaState`. A `txn_bytes` of `None` is inserted into
+    /// the txn trie as an empty value.
+    pub const fn new(
+        txn_bytes: Option<Vec<u8>>,
+        receipt_node_bytes: Vec<u8>,
+        gas_used: u64,
+    ) -> Self {
+        Self {
+            txn_bytes,
+            receipt_node_bytes,
+            gas_used,
+        }
+    }
+
+    /// The raw bytes of the txn, if any.
+    pub fn txn_bytes(&self) -> Option<&[u8]> {
+        self.txn_bytes.as_deref()
+    }
+
+    /// Whether the txn uses the legacy envelope, i.e. is a plain RLP list
+    /// rather than an [EIP-2718](https://eips.ethereum.org/EIPS/eip-2718)
+    /// typed envelope. Dummy txns (without any bytes) are not legacy txns.
+    ///
+    /// Both kinds of envelopes are inserted as-is into the txn trie, so this
+    /// has no effect on decoding.
+    pub fn is_legacy_txn(&self) -> bool {
+        // Typed envelopes start with their type, which is at most `0x7f`, while
+        // RLP lists start with a byte of at least `0xc0`.
+        matches!(self.txn_bytes(), Some([first, ..]) if *first >= 0xc0)
+    }
+
+    /// Whether the txn is a legacy txn whose signature does not commit to a
+    /// chain id, i.e. one predating
+    /// [EIP-155](https://eips.ethereum.org/EIPS/eip-155).
+    pub fn is_pre_eip_155_txn(&self) -> bool {
+        match self.txn_bytes() {
+            Some(txn_bytes) if self.is_legacy_txn() => {
+                // Pre-EIP-155 signatures use a `v` of `27` or `28`, while later ones
+                // use `chain_id * 2 + 35` or `chain_id * 2 + 36`.
+                let txn = rlp::Rlp::new(txn_bytes);
+                matches!(txn.item_count(), Ok(9)) && matches!(txn.val_at::<u64>(6), Ok(27 | 28))
+            }
+            _ => false,
+        }
+    }
+
+    /// The RLP-encoded receipt node of the txn.
+    pub fn receipt_node_bytes(&self) -> &[u8] {
+        &self.receipt_node_bytes
+    }
+
+    /// The gas used by the txn.
+    pub const fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use evm_arithmetization::proof::{BlockHashes, BlockMetadata};
+    use mpt_trie::builder::RlpEncodedTrie;
+
+    use super::*;
+    use crate::trace_protocol::{TxnMeta, TxnTrace};
+    use crate::types::BlockLevelData;
+
+    /// A trace which doesn't touch the account.
+    fn empty_trace() -> TxnTrace {
+        TxnTrace {
+            balance: None,
+            nonce: None,
+            storage_read: None,
+            storage_written: None,
+            storage_root: None,
+            code_usage: None,
+            self_destructed: None,
+        }
+    }
+
+    /// A txn with a single trace, of the account at `addr`.
+    fn txn_with_trace(addr: Address, trace: TxnTrace) -> TxnInfo {
+        TxnInfo {
+            traces: HashMap::from([(addr, trace)]),
+            meta: TxnMeta {
+                byte_code: vec![1],
+                new_txn_trie_node_byte: vec![1],
+                new_receipt_trie_node_byte: rlp::encode(&vec![1u8]).to_vec(),
+                gas_used: 21000,
+            },
+        }
+    }
+
+    fn self_destructing_txn(addr: Address, c_hash: CodeHash) -> TxnInfo {
+        txn_with_trace(
+            addr,
+            TxnTrace {
+                code_usage: Some(ContractCodeUsage::Read(c_hash)),
+                self_destructed: Some(true),
+                ..empty_trace()
+            },
+        )
+    }
+
+    /// A code hash resolver which only knows the given mappings.
+    fn resolver_with(
+        extra_code_hash_mappings: HashMap<CodeHash, Vec<u8>>,
+    ) -> CodeHashResolving<fn(&CodeHash) -> Vec<u8>> {
+        CodeHashResolving {
+            client_code_hash_resolve_f: |_| {
+                unreachable!("Code should be resolved from the known mappings")
+            },
+            extra_code_hash_mappings,
+            code_hashes_written: HashMap::default(),
+            validate_code_hashes: true,
+        }
+    }
+
+    /// Processes a pre-Cancun txn without any pre-image account, extra state
+    /// access, precompile or key hash cache.
+    fn into_processed(
+        txn_info: TxnInfo,
+        code_hash_resolver: &mut CodeHashResolving<fn(&CodeHash) -> Vec<u8>>,
+    ) -> TraceParsingResult<ProcessedTxnInfo> {
+        txn_info.into_processed_txn_info(&[], &[], &HashSet::new(), false, code_hash_resolver, None)
+    }
+
+    fn account_with_code(c_hash: CodeHash) -> AccountRlp {
+        AccountRlp {
+            code_hash: c_hash,
+            ..Default::default()
+        }
+    }
+
+    /// Processes a txn where the first account of the pre-image self-destructs
+    /// and returns whether its code is still resolvable afterwards.
+    fn code_remains_after_self_destruct(num_accounts_sharing_code: u8, eip_6780: bool) -> bool {
+        let code = vec![0x60, 0x00, 0xff];
+        let c_hash = hash(&code);
+
+        let addrs: Vec<_> = (1..=num_accounts_sharing_code)
+            .map(Address::repeat_byte)
+            .collect();
+        let all_accounts_in_pre_image: Vec<_> = addrs
+            .iter()
+            .map(|addr| (hash(addr.as_bytes()), account_with_code(c_hash)))
+            .collect();
+
+        let mut code_hash_resolver = resolver_with(HashMap::from([(c_hash, code.clone())]));
+
+        let processed = self_destructing_txn(addrs[0], c_hash)
+            .into_processed_txn_info(
+                &all_accounts_in_pre_image,
+                &[],
+                &HashSet::new(),
+                eip_6780,
+                &mut code_hash_resolver,
+                None,
+            )
+            .unwrap();
+
+        // The txn itself still needs the code of the destroyed contract.
+        assert_eq!(processed.contract_code_accessed.get(&c_hash), Some(&code));
+        assert_eq!(
+            processed.nodes_used_by_txn.self_destructed_accounts,
+            vec![hash(addrs[0].as_bytes())]
+        );
+
+        code_hash_resolver
+            .extra_code_hash_mappings
+            .contains_key(&c_hash)
+    }
+
+    #[test]
+    fn self_destruct_removes_code_hash_mapping() {
+        assert!(!code_remains_after_self_destruct(1, false));
+    }
+
+    #[test]
+    fn self_destruct_keeps_code_hash_mapping_shared_with_surviving_account() {
+        assert!(code_remains_after_self_destruct(2, false));
+    }
+
+    #[test]
+    fn eip_6780_self_destruct_keeps_code_hash_mapping_of_pre_existing_contract() {
+        assert!(code_remains_after_self_destruct(1, true));
+    }
+
+    #[test]
+    fn eip_6780_self_destruct_keeps_code_of_contract_created_by_an_earlier_txn() {
+        let addr = Address::repeat_byte(1);
+        let code = vec![0x60, 0x00, 0xff];
+        let c_hash = hash(&code);
+
+        let creating_txn = txn_with_trace(
+            addr,
+            TxnTrace {
+                code_usage: Some(ContractCodeUsage::Write(code.clone().into())),
+                ..empty_trace()
+            },
+        );
+
+        let mut code_hash_resolver = resolver_with(HashMap::new());
+
+        for txn_info in [creating_txn, self_destructing_txn(addr, c_hash)] {
+            txn_info
+                .into_processed_txn_info(
+                    &[],
+                    &[],
+                    &HashSet::new(),
+                    true,
+                    &mut code_hash_resolver,
+                    None,
+                )
+                .unwrap();
+        }
+
+        // The contract was not created by the self-destructing txn, so it
+        // survives along with its code.
+        assert_eq!(code_hash_resolver.resolve(&c_hash).unwrap(), code);
+    }
+
+    #[test]
+    fn accessed_keys_cover_reads_writes_and_withdrawals() {
+        let (reader, writer, recipient) = (
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            Address::repeat_byte(3),
+        );
+        let (read_slot, written_slot) = (H256::repeat_byte(4), H256::repeat_byte(5));
+
+        let mut txn_info = txn_with_trace(
+            reader,
+            TxnTrace {
+                storage_read: Some(vec![read_slot]),
+                ..empty_trace()
+            },
+        );
+        txn_info.traces.insert(
+            writer,
+            TxnTrace {
+                storage_written: Some(HashMap::from([(written_slot, U256::one())])),
+                ..empty_trace()
+            },
+        );
+
+        let processed = ProcessedBlockTrace {
+            tries: PartialTriePreImages::default(),
+            txn_info: vec![into_processed(txn_info, &mut resolver_with(HashMap::new())).unwrap()],
+            withdrawals: vec![(recipient, U256::one())],
+        };
+
+        let (accounts, storage) = processed.accessed_keys();
+        let h_addr = |addr: Address| hash(addr.as_bytes());
+        assert_eq!(
+            accounts,
+            HashSet::from([h_addr(reader), h_addr(writer), h_addr(recipient)])
+        );
+        assert_eq!(
+            storage,
+            HashMap::from([
+                (h_addr(reader), HashSet::from([hash(read_slot.as_bytes())])),
+                (
+                    h_addr(writer),
+                    HashSet::from([hash(written_slot.as_bytes())])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn summary_counts_the_txns_withdrawals_and_accessed_accounts() {
+        let processed = ProcessedBlockTrace {
+            tries: PartialTriePreImages::default(),
+            txn_info: Vec::new(),
+            withdrawals: vec![
+                (Address::repeat_byte(1), U256::one()),
+                (Address::repeat_byte(2), U256::one()),
+                (Address::repeat_byte(1), U256::one()),
+            ],
+        };
+        let other_data = OtherBlockData {
+            b_data: BlockLevelData {
+                b_meta: BlockMetadata {
+                    block_number: 42.into(),
+                    block_chain_id: 1.into(),
+                    ..Default::default()
+                },
+                b_hashes: BlockHashes {
+                    prev_hashes: vec![H256::zero(); 256],
+                    cur_hash: H256::zero(),
+                },
+                withdrawals: Vec::new(),
+                parent_beacon_block_root: None,
+            },
+            checkpoint_state_trie_root: EMPTY_TRIE_HASH,
+            final_state_trie_root: None,
+        };
+
+        let summary = processed.summary(&other_data);
+        assert_eq!(
+            summary,
+            BlockTraceSummary {
+                block_number: 42.into(),
+                chain_id: 1.into(),
+                num_txns: 0,
+                num_withdrawals: 3,
+                num_accessed_accounts: 2,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "block=42 chain_id=1 txns=0 withdrawals=3 accessed_accounts=2"
+        );
+    }
+
+    /// A legacy value transfer signed with the given `v`.
+    fn legacy_txn_bytes(v: u64) -> Vec<u8> {
+        let mut stream = rlp::RlpStream::new_list(9);
+        stream
+            .append(&0u64)
+            .append(&U256::from(50_000_000_000u64))
+            .append(&21000u64)
+            .append(&Address::repeat_byte(0x5d))
+            .append(&U256::from(31337))
+            .append_empty_data()
+            .append(&v)
+            .append(&U256::from(1))
+            .append(&U256::from(2));
+        stream.out().to_vec()
+    }
+
+    #[test]
+    fn legacy_txn_envelopes_are_recognized() {
+        let meta_of = |txn_bytes| TxnMetaState::new(txn_bytes, vec![], 21000);
+
+        let pre_eip_155_txn = meta_of(Some(legacy_txn_bytes(27)));
+        assert!(pre_eip_155_txn.is_legacy_txn());
+        assert!(pre_eip_155_txn.is_pre_eip_155_txn());
+
+        // Replay-protected on mainnet (`1 * 2 + 35`).
+        let eip_155_txn = meta_of(Some(legacy_txn_bytes(37)));
+        assert!(eip_155_txn.is_legacy_txn());
+        assert!(!eip_155_txn.is_pre_eip_155_txn());
+
+        let mut typed_txn_bytes = vec![0x02];
+        typed_txn_bytes.extend(legacy_txn_bytes(37));
+        let typed_txn = meta_of(Some(typed_txn_bytes));
+        assert!(!typed_txn.is_legacy_txn());
+        assert!(!typed_txn.is_pre_eip_155_txn());
+
+        let dummy_txn = meta_of(None);
+        assert!(!dummy_txn.is_legacy_txn());
+        assert!(!dummy_txn.is_pre_eip_155_txn());
+    }
+
+    #[test]
+    fn corrupt_receipt_node_bytes_give_a_receipt_decode_error() {
+        let err = process_rlped_receipt_node_bytes(vec![0xf8]).unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::ReceiptDecode(bytes) if bytes == "f8"
+        ));
+    }
+
+    #[test]
+    fn code_reported_for_a_precompile_is_dropped() {
+        let ecrecover = Address::from_low_u64_be(1);
+        let bogus_code = vec![0x60, 0x00];
+        let bogus_c_hash = hash(&bogus_code);
+
+        let txn_info = txn_with_trace(
+            ecrecover,
+            TxnTrace {
+                code_usage: Some(ContractCodeUsage::Read(bogus_c_hash)),
+                ..empty_trace()
+            },
+        );
+
+        let processed = txn_info
+            .into_processed_txn_info(
+                &[],
+                &[],
+                &ProcessingMeta::new(|_: &CodeHash| -> Vec<u8> { unreachable!() }).precompiles(),
+                false,
+                &mut resolver_with(HashMap::from([(bogus_c_hash, bogus_code)])),
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(
+            processed.contract_code_accessed,
+            create_empty_code_access_map()
+        );
+        assert!(processed.nodes_used_by_txn.state_writes.is_empty());
+        assert_eq!(
+            processed.nodes_used_by_txn.state_accesses,
+            vec![hash(ecrecover.as_bytes())]
+        );
+    }
+
+    #[test]
+    fn code_not_matching_its_hash_is_rejected_unless_trusted() {
+        let addr = Address::repeat_byte(1);
+        let c_hash = hash(&[0x60, 0x00]);
+        let wrong_code = vec![0x60, 0x01];
+
+        let read_code = |validate_code_hashes| {
+            let txn_info = txn_with_trace(
+                addr,
+                TxnTrace {
+                    code_usage: Some(ContractCodeUsage::Read(c_hash)),
+                    ..empty_trace()
+                },
+            );
+
+            let mut code_hash_resolver =
+                resolver_with(HashMap::from([(c_hash, wrong_code.clone())]));
+            code_hash_resolver.validate_code_hashes = validate_code_hashes;
+            into_processed(txn_info, &mut code_hash_resolver)
+        };
+
+        let err = read_code(true).unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::CodeHashMismatch { expected, got }
+                if *expected == c_hash && *got == hash(&wrong_code)
+        ));
+
+        let processed = read_code(false).unwrap();
+        assert_eq!(
+            processed.contract_code_accessed.get(&c_hash),
+            Some(&wrong_code)
+        );
+    }
+
+    #[test]
+    fn missing_code_is_reported_as_a_code_trie_entry() {
+        let addr = Address::repeat_byte(1);
+        let c_hash = hash(&[0x60, 0x00]);
+        let txn_info = txn_with_trace(
+            addr,
+            TxnTrace {
+                code_usage: Some(ContractCodeUsage::Read(c_hash)),
+                ..empty_trace()
+            },
+        );
+
+        // A client which does not know the code resolves it as empty.
+        let mut code_hash_resolver = CodeHashResolving {
+            client_code_hash_resolve_f: |_| Vec::new(),
+            ..resolver_with(HashMap::new())
+        };
+        let err = into_processed(txn_info, &mut code_hash_resolver).unwrap_err();
+        assert_eq!(
+            err.reason(),
+            &TraceParsingErrorReason::NonExistentTrieEntry(
+                TrieType::Code,
+                Nibbles::from_h256_be(c_hash),
+                EMPTY_TRIE_HASH,
+            )
+        );
+    }
+
+    #[test]
+    fn storage_trie_not_matching_the_account_is_rejected() {
+        let mut storage_trie = HashedPartialTrie::default();
+        storage_trie
+            .insert(
+                Nibbles::from_h256_be(hash(&[0; 32])),
+                rlp::encode(&U256::one()).to_vec(),
+            )
+            .unwrap();
+
+        let h_addr = hash(Address::repeat_byte(1).as_bytes());
+        let account = AccountRlp {
+            storage_root: storage_trie.hash(),
+            ..Default::default()
+        };
+        let mut state_trie = HashedPartialTrie::default();
+        state_trie
+            .insert(
+                Nibbles::from_h256_be(h_addr),
+                rlp::encode(&account).to_vec(),
+            )
+            .unwrap();
+
+        let mut tries = PartialTriePreImages {
+            state: state_trie,
+            storage: HashMap::from([(h_addr, storage_trie)]),
+        };
+        assert!(verify_storage_roots_of_pre_images(&tries).is_ok());
+
+        tries.storage.insert(h_addr, HashedPartialTrie::default());
+        let err = verify_storage_roots_of_pre_images(&tries).unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::StorageRootMismatch {
+                h_addr: got_h_addr,
+                in_account,
+                in_image,
+            } if *got_h_addr == h_addr
+                && *in_account == account.storage_root
+                && *in_image == EMPTY_TRIE_HASH
+        ));
+    }
+
+    #[test]
+    fn rlp_nodes_pre_images_rebuild_the_state_and_storage_tries() {
+        let mut storage_trie = HashedPartialTrie::default();
+        for i in 1..=3u8 {
+            storage_trie
+                .insert(
+                    Nibbles::from_h256_be(hash(&[i; 32])),
+                    rlp::encode(&U256::from(i)).to_vec(),
+                )
+                .unwrap();
+        }
+
+        let h_addr_with_storage = hash(Address::repeat_byte(1).as_bytes());
+        let h_addr_without_storage = hash(Address::repeat_byte(2).as_bytes());
+        let mut state_trie = HashedPartialTrie::default();
+        for (h_addr, storage_root) in [
+            (h_addr_with_storage, storage_trie.hash()),
+            (h_addr_without_storage, EMPTY_TRIE_HASH),
+        ] {
+            let account = AccountRlp {
+                storage_root,
+                ..Default::default()
+            };
+            state_trie
+                .insert(
+                    Nibbles::from_h256_be(h_addr),
+                    rlp::encode(&account).to_vec(),
+                )
+                .unwrap();
+        }
+
+        let mut nodes = RlpEncodedTrie::from_trie(&storage_trie).nodes;
+        nodes.extend(RlpEncodedTrie::from_trie(&state_trie).nodes);
+        let pre_images = |state_root, nodes| RlpNodesPreImages { state_root, nodes };
+
+        let processed =
+            process_rlp_nodes_pre_images(pre_images(state_trie.hash(), nodes.clone())).unwrap();
+        assert_eq!(processed.tries.state, state_trie);
+        assert_eq!(
+            processed.tries.storage,
+            HashMap::from([
+                (h_addr_with_storage, storage_trie),
+                (h_addr_without_storage, HashedPartialTrie::default()),
+            ])
+        );
+
+        let missing_root = H256::repeat_byte(1);
+        let err = process_rlp_nodes_pre_images(pre_images(missing_root, nodes)).unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::MissingPreImageRoot(root) if *root == missing_root
+        ));
+
+        let err =
+            process_rlp_nodes_pre_images(pre_images(missing_root, vec![vec![0x80]])).unwrap_err();
+        assert!(matches!(
+            err.reason(),
+            TraceParsingErrorReason::RlpDecode(_)
+        ));
+    }
+
+    #[test]
+    fn slots_accessed_by_several_txns_are_hashed_once_with_a_key_hash_cache() {
+        let addr = Address::repeat_byte(1);
+        let (slot_a, slot_b) = (H256::repeat_byte(2), H256::repeat_byte(3));
+        let txn_info = || {
+            txn_with_trace(
+                addr,
+                TxnTrace {
+                    storage_read: Some(vec![slot_a, slot_b]),
+                    storage_written: Some(HashMap::from([(slot_a, U256::one())])),
+                    ..empty_trace()
+                },
+            )
+        };
+
+        let mut code_hash_resolver = resolver_with(HashMap::new());
+        let cache = Mutex::new(KeyHashCache::new(16));
+
+        for _ in 0..3 {
+            let uncached = into_processed(txn_info(), &mut code_hash_resolver).unwrap();
+            let cached = txn_info()
+                .into_processed_txn_info(
+                    &[],
+                    &[],
+                    &HashSet::new(),
+                    false,
+                    &mut code_hash_resolver,
+                    Some(&cache),
+                )
+                .unwrap();
+
+            assert_eq!(
+                cached.nodes_used_by_txn.storage_accesses,
+                uncached.nodes_used_by_txn.storage_accesses
+            );
+        }
+
+        // Each txn looks up both reads and the write, but only the first one hashes.
+        let cache = cache.into_inner().unwrap();
+        assert_eq!(cache.num_lookups(), 9);
+        assert_eq!(cache.num_hashes(), 2);
+    }
+
+    #[test]
+    fn gas_used_not_fitting_in_a_u64_is_rejected() {
+        let gen_inputs = |gas_used_before: U256, gas_used_after: U256| GenerationInputs {
+            gas_used_before,
+            gas_used_after,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ir_entry_gas_used(0, &gen_inputs(1.into(), U256::from(u64::MAX) + 1)).unwrap(),
+            u64::MAX
+        );
+        for (gas_used_before, gas_used_after) in [
+            (U256::zero(), U256::from(u64::MAX) + 1),
+            (U256::one(), U256::zero()),
+        ] {
+            assert_eq!(
+                ir_entry_gas_used(3, &gen_inputs(gas_used_before, gas_used_after))
+                    .unwrap_err()
+                    .reason(),
+                &TraceParsingErrorReason::GasUsedOutOfRange {
+                    entry_idx: 3,
+                    gas_used_before,
+                    gas_used_after,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn processing_meta_can_be_built_in_a_const_context() {
+        const P_META: ProcessingMeta<fn(&CodeHash) -> Vec<u8>> =
+            ProcessingMeta::new(|_| Vec::new());
+
+        assert_eq!(P_META.options.ir_padding, IrPadding::default());
+        assert!(P_META
+            .precompiles()
+            .contains(&Address::from_low_u64_be(NUM_CANCUN_PRECOMPILES)));
+        assert_eq!(
+            P_META.fee_model().fee_recipients(Address::repeat_byte(1)),
+            vec![Address::repeat_byte(1)]
+        );
+    }
+}
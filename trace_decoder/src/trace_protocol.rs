@@ -30,8 +30,8 @@ use serde_with::{serde_as, FromInto, TryFromInto};
 
 use crate::{
     deserializers::ByteString,
-    types::{CodeHash, HashedAccountAddr, StorageAddr, StorageVal},
-    utils::hash,
+    types::{CodeHash, HashedAccountAddr, StorageAddr, StorageVal, TrieRootHash},
+    utils::{eip_7702_delegation_code, hash},
 };
 
 /// Core payload needed to generate a proof for a block. Note that the scheduler
@@ -59,6 +59,8 @@ pub enum BlockTraceTriePreImages {
     Separate(SeparateTriePreImages),
     /// The trie pre-image with combined state/storage tries.
     Combined(CombinedPreImages),
+    /// The trie pre-image as a plain list of RLP-encoded trie nodes.
+    RlpNodes(RlpNodesPreImages),
 }
 
 /// State/Storage trie pre-images that are separate.
@@ -106,6 +108,23 @@ pub struct TrieCompact(#[serde_as(as = "FromInto<ByteString>")] pub Vec<u8>);
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TrieDirect(pub HashedPartialTrie);
 
+/// A trie pre-image given as the RLP encoding of the state and storage trie
+/// nodes, e.g. as returned by `eth_getProof` or `debug_executionWitness`.
+///
+/// The state trie is rebuilt from the node hashing to `state_root`, and the
+/// storage trie of each account from the node hashing to its storage root.
+/// Nodes are looked up by hash, so a node which is not linked to by its
+/// parent is simply left out, and a missing node is kept as a hash node.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RlpNodesPreImages {
+    /// The root hash of the state trie.
+    pub state_root: TrieRootHash,
+    /// The RLP encoding of the state and storage trie nodes, in any order.
+    #[serde_as(as = "Vec<FromInto<ByteString>>")]
+    pub nodes: Vec<Vec<u8>>,
+}
+
 /// A trie pre-image where state and storage are separate.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -186,6 +205,12 @@ pub struct TxnTrace {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub storage_written: Option<HashMap<StorageAddr, StorageVal>>,
 
+    /// If set, the expected root of the account's storage trie after this
+    /// txn, which the root obtained from the storage writes is checked
+    /// against.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_root: Option<TrieRootHash>,
+
     /// Contract code that this address accessed.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub code_usage: Option<ContractCodeUsage>,
@@ -207,6 +232,11 @@ pub enum ContractCodeUsage {
     /// Contract was created (and these are the bytes). Note that this new
     /// contract code will not appear in the [`BlockTrace`] map.
     Write(#[serde_as(as = "FromInto<ByteString>")] ByteString),
+
+    /// The account (an EOA) got its code set to a delegation to the code of
+    /// this address through an EIP-7702 authorization. Delegating to the zero
+    /// address clears the delegation instead.
+    Delegate(Address),
 }
 
 impl ContractCodeUsage {
@@ -214,6 +244,7 @@ impl ContractCodeUsage {
         match self {
             ContractCodeUsage::Read(hash) => *hash,
             ContractCodeUsage::Write(bytes) => hash(bytes),
+            ContractCodeUsage::Delegate(delegate) => hash(&eip_7702_delegation_code(delegate)),
         }
     }
 }
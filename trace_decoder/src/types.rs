@@ -3,6 +3,8 @@ use evm_arithmetization::proof::{BlockHashes, BlockMetadata};
 use mpt_trie::nibbles::Nibbles;
 use serde::{Deserialize, Serialize};
 
+use crate::utils::hash;
+
 /// A type alias for `[`[`U256`]`; 8]` of a bloom filter.
 pub type Bloom = [U256; 8];
 /// A type alias for [`H256`] of a code hash.
@@ -28,6 +30,54 @@ pub type TrieRootHash = H256;
 /// A type alias for [`usize`] of a transaction's index within a block.
 pub type TxnIdx = usize;
 
+/// The key of an account in the state trie.
+///
+/// Can only be built from an account address (or its hash), which prevents
+/// a storage slot key from being used where an account key is expected.
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct AccountNibbles(Nibbles);
+
+impl AccountNibbles {
+    /// Creates the key of the account with the given address.
+    pub fn from_addr(addr: &Address) -> Self {
+        Self::from_hashed_addr(hash(addr.as_bytes()))
+    }
+
+    /// Creates the key of the account whose address hashes to `h_addr`.
+    pub fn from_hashed_addr(h_addr: HashedAccountAddr) -> Self {
+        Self(Nibbles::from_h256_be(h_addr))
+    }
+
+    /// Returns the underlying [`Nibbles`] of the key.
+    pub const fn into_inner(self) -> Nibbles {
+        self.0
+    }
+}
+
+/// The key of a slot in a storage trie.
+///
+/// Can only be built from a storage slot (or its hash), which prevents an
+/// account key from being used where a slot key is expected.
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StorageNibbles(Nibbles);
+
+impl StorageNibbles {
+    /// Creates the key of the given storage slot.
+    pub fn from_slot(slot: &Nibbles) -> Self {
+        Self::from_hashed_slot(hash(&slot.bytes_be()))
+    }
+
+    /// Creates the key of the storage slot which hashes to `h_slot`.
+    pub fn from_hashed_slot(h_slot: HashedStorageAddr) -> Self {
+        Self(Nibbles::from_h256_be(h_slot))
+    }
+
+    /// Returns the underlying [`Nibbles`] of the key.
+    pub const fn into_inner(self) -> Nibbles {
+        self.0
+    }
+}
+
 /// A function which turns a code hash into bytes.
 pub trait CodeHashResolveFunc = Fn(&CodeHash) -> Vec<u8>;
 
@@ -37,19 +87,34 @@ pub(crate) const EMPTY_CODE_HASH: H256 = H256([
     130, 39, 59, 123, 250, 216, 4, 93, 133, 164, 112,
 ]);
 
+/// The root hash of an empty MPT, i.e. the hash of `rlp("")`.
+///
 /// 0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421
-pub(crate) const EMPTY_TRIE_HASH: H256 = H256([
+pub const EMPTY_TRIE_HASH: H256 = H256([
     86, 232, 31, 23, 27, 204, 85, 166, 255, 131, 69, 230, 146, 192, 248, 110, 91, 72, 224, 27, 153,
     108, 173, 192, 1, 98, 47, 181, 227, 99, 180, 33,
 ]);
 
-pub(crate) const EMPTY_ACCOUNT_BYTES_RLPED: [u8; 70] = [
+/// The RLP of an account with a zero nonce and balance, no storage and no
+/// code.
+pub const EMPTY_ACCOUNT_BYTES_RLPED: [u8; 70] = [
     248, 68, 128, 128, 160, 86, 232, 31, 23, 27, 204, 85, 166, 255, 131, 69, 230, 146, 192, 248,
     110, 91, 72, 224, 27, 153, 108, 173, 192, 1, 98, 47, 181, 227, 99, 180, 33, 160, 197, 210, 70,
     1, 134, 247, 35, 60, 146, 126, 125, 178, 220, 199, 3, 192, 229, 0, 182, 83, 202, 130, 39, 59,
     123, 250, 216, 4, 93, 133, 164, 112,
 ];
 
+/// The prefix of the code of an account delegating to another one through an
+/// EIP-7702 authorization, followed by the address delegated to.
+pub(crate) const EIP_7702_DELEGATION_PREFIX: [u8; 3] = [0xef, 0x01, 0x00];
+
+/// The number of precompiled contracts as of Cancun, which live at the
+/// addresses `0x01` through `0x0a`.
+pub(crate) const NUM_CANCUN_PRECOMPILES: u64 = 10;
+
+/// The number of previous block hashes accessible through `BLOCKHASH`.
+pub(crate) const NUM_PREV_BLOCK_HASHES: usize = 256;
+
 // This is just `rlp(0)`.
 pub(crate) const ZERO_STORAGE_SLOT_VAL_RLPED: [u8; 1] = [128];
 
@@ -60,6 +125,10 @@ pub struct OtherBlockData {
     pub b_data: BlockLevelData,
     /// State trie root hash at the checkpoint.
     pub checkpoint_state_trie_root: TrieRootHash,
+    /// State trie root hash claimed by the block header, checked against the
+    /// one computed at the end of the block when provided.
+    #[serde(default)]
+    pub final_state_trie_root: Option<TrieRootHash>,
 }
 
 /// Data that is specific to a block and is constant for all txns in a given
@@ -72,4 +141,19 @@ pub struct BlockLevelData {
     pub b_hashes: BlockHashes,
     /// Block withdrawal addresses and values.
     pub withdrawals: Vec<(Address, U256)>,
+    /// The parent beacon block root of post-Cancun blocks.
+    ///
+    /// Its EIP-4788 writes to the beacon roots contract are not applied, as the
+    /// kernel doesn't perform them: the tries of the first IR entry must be the
+    /// state the block starts from.
+    #[serde(default)]
+    pub parent_beacon_block_root: Option<H256>,
+}
+
+impl BlockLevelData {
+    /// Returns whether the block follows the Cancun rules, as told by it
+    /// having a parent beacon block root.
+    pub const fn is_cancun(&self) -> bool {
+        self.parent_beacon_block_root.is_some()
+    }
 }
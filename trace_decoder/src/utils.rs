@@ -1,4 +1,4 @@
-use ethereum_types::H256;
+use ethereum_types::{Address, H256};
 use keccak_hash::keccak;
 use log::trace;
 use mpt_trie::{
@@ -6,12 +6,50 @@ use mpt_trie::{
     trie_ops::ValOrHash,
 };
 
-use crate::types::HashedStorageAddr;
+use crate::types::{HashedStorageAddr, EIP_7702_DELEGATION_PREFIX};
+
+/// Serializes a value through its [`Display`](std::fmt::Display)
+/// implementation. Used for error payloads that do not implement `Serialize`
+/// themselves.
+#[cfg(feature = "serde")]
+pub(crate) fn serialize_as_display<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: std::fmt::Display,
+    S: serde::Serializer,
+{
+    serializer.collect_str(value)
+}
+
+/// Deserializes a
+/// [`CompactParsingError`](crate::compact::compact_prestate_processing::CompactParsingError)
+/// serialized through [`serialize_as_display`], of which only the message is
+/// left.
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_compact_parsing_error_message<'de, D>(
+    deserializer: D,
+) -> Result<crate::compact::compact_prestate_processing::CompactParsingError, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    <String as serde::Deserialize>::deserialize(deserializer)
+        .map(crate::compact::compact_prestate_processing::CompactParsingError::Message)
+}
 
 pub(crate) fn hash(bytes: &[u8]) -> H256 {
     H256::from(keccak(bytes).0)
 }
 
+/// Returns the code of an account delegating to `delegate` through an EIP-7702
+/// authorization. Delegating to the zero address clears the delegation, which
+/// leaves the account without code.
+pub(crate) fn eip_7702_delegation_code(delegate: &Address) -> Vec<u8> {
+    if delegate.is_zero() {
+        return Vec::new();
+    }
+
+    [&EIP_7702_DELEGATION_PREFIX[..], delegate.as_bytes()].concat()
+}
+
 pub(crate) fn update_val_if_some<T>(target: &mut T, opt: Option<T>) {
     if let Some(new_val) = opt {
         *target = new_val;
@@ -3,7 +3,7 @@ use std::time::{Duration, Instant};
 use alloy::{
     primitives::B256,
     providers::Provider,
-    rpc::types::eth::{BlockId, BlockNumberOrTag, BlockTransactionsKind, Withdrawal},
+    rpc::types::eth::{BlockId, BlockNumberOrTag, BlockTransactionsKind, Header, Withdrawal},
     transports::Transport,
 };
 use anyhow::Context as _;
@@ -226,8 +226,53 @@ where
                      }| { (address.compat(), amount.into()) },
                 )
                 .collect(),
+            parent_beacon_block_root: target_block
+                .header
+                .parent_beacon_block_root
+                .map(|it| it.compat()),
         },
         checkpoint_state_trie_root: checkpoint_state_trie_root.compat(),
+        final_state_trie_root: expected_final_state_trie_root(&target_block.header)
+            .map(|it| it.compat()),
     };
     Ok(other_data)
 }
+
+/// Returns the state root the decoder can check its final state against, i.e.
+/// the one of the header when the decoder reproduces every state change of the
+/// block.
+///
+/// The state root of a post-Cancun header includes the EIP-4788 writes of the
+/// parent beacon block root, which neither the decoder nor the kernel perform,
+/// so it can't be checked against.
+fn expected_final_state_trie_root(header: &Header) -> Option<B256> {
+    header
+        .parent_beacon_block_root
+        .is_none()
+        .then_some(header.state_root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn final_state_root_is_only_expected_before_cancun() {
+        let state_root = B256::repeat_byte(1);
+        let header = |parent_beacon_block_root| Header {
+            state_root,
+            parent_beacon_block_root,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            expected_final_state_trie_root(&header(None)),
+            Some(state_root)
+        );
+        // A Cancun-era header has a parent beacon block root.
+        assert_eq!(
+            expected_final_state_trie_root(&header(Some(B256::repeat_byte(2)))),
+            None
+        );
+    }
+}
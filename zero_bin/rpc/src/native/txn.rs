@@ -177,6 +177,7 @@ async fn process_tx_traces(
             nonce,
             storage_read,
             storage_written,
+            storage_root: None,
             code_usage: code,
             self_destructed,
         };